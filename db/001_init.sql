@@ -8,22 +8,44 @@ CREATE EXTENSION IF NOT EXISTS vector;
 CREATE TABLE agent_memory.chunks (
     id UUID DEFAULT gen_random_uuid() PRIMARY KEY,
     agent_id TEXT NOT NULL DEFAULT 'default',
-    content TEXT NOT NULL,
+    content TEXT,
+    content_compressed BYTEA,
     source TEXT NOT NULL,
     source_path TEXT,
     source_date DATE,
     importance TEXT DEFAULT 'normal',
     tags TEXT[] DEFAULT '{}',
     embedding vector(768),
+    start_offset INTEGER,
+    end_offset INTEGER,
+    title TEXT,
+    source_mtime TIMESTAMPTZ,
+    lang TEXT,
+    metadata JSONB,
+    embed_text TEXT,
+    content_hash TEXT,
+    content_tsv tsvector GENERATED ALWAYS AS (to_tsvector('simple', coalesce(content, ''))) STORED,
     created_at TIMESTAMPTZ DEFAULT now(),
     updated_at TIMESTAMPTZ DEFAULT now()
 );
 
+-- Per-source indexing state (e.g. a git source's last indexed commit)
+CREATE TABLE agent_memory.index_state (
+    agent_id TEXT NOT NULL,
+    source TEXT NOT NULL,
+    commit_sha TEXT,
+    indexed_bytes BIGINT,
+    updated_at TIMESTAMPTZ DEFAULT now(),
+    PRIMARY KEY (agent_id, source)
+);
+
 -- Indexes
 CREATE INDEX idx_chunks_agent_id ON agent_memory.chunks(agent_id);
 CREATE INDEX idx_chunks_source ON agent_memory.chunks(source);
 CREATE INDEX idx_chunks_source_date ON agent_memory.chunks(source_date);
 CREATE INDEX idx_chunks_importance ON agent_memory.chunks(importance);
+CREATE INDEX idx_chunks_lang ON agent_memory.chunks(lang);
+CREATE INDEX idx_chunks_content_tsv ON agent_memory.chunks USING gin(content_tsv);
 
 -- IVFFlat index — create after inserting data (needs rows to build lists)
 -- CREATE INDEX idx_chunks_embedding ON agent_memory.chunks
@@ -0,0 +1,5 @@
+-- Migration: Add chunk character offsets so results can link back to the source location
+
+ALTER TABLE agent_memory.chunks
+    ADD COLUMN IF NOT EXISTS start_offset INTEGER,
+    ADD COLUMN IF NOT EXISTS end_offset INTEGER;
@@ -0,0 +1,4 @@
+-- Migration: Add document title for each chunk
+
+ALTER TABLE agent_memory.chunks
+    ADD COLUMN IF NOT EXISTS title TEXT;
@@ -0,0 +1,8 @@
+-- Migration: Optional zstd-compressed content (compress_content: true)
+-- Compressed rows store NULL in content and the bytes in content_compressed.
+
+ALTER TABLE agent_memory.chunks
+    ADD COLUMN IF NOT EXISTS content_compressed BYTEA;
+
+ALTER TABLE agent_memory.chunks
+    ALTER COLUMN content DROP NOT NULL;
@@ -0,0 +1,5 @@
+-- Migration: Record each file's modification time at index time
+-- index-incremental compares against this; rows without it are re-indexed once.
+
+ALTER TABLE agent_memory.chunks
+    ADD COLUMN IF NOT EXISTS source_mtime TIMESTAMPTZ;
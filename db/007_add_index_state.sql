@@ -0,0 +1,11 @@
+-- Migration: Per-source indexing state
+-- index-incremental records each git source's last indexed commit here and
+-- diffs from it on the next run instead of comparing file mtimes.
+
+CREATE TABLE IF NOT EXISTS agent_memory.index_state (
+    agent_id TEXT NOT NULL,
+    source TEXT NOT NULL,
+    commit_sha TEXT,
+    updated_at TIMESTAMPTZ DEFAULT now(),
+    PRIMARY KEY (agent_id, source)
+);
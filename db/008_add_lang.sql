@@ -0,0 +1,7 @@
+-- Migration: Per-chunk language (detect_language: true)
+-- ISO 639-3 code detected at index time; NULL when detection is off or unsure.
+
+ALTER TABLE agent_memory.chunks
+    ADD COLUMN IF NOT EXISTS lang TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_chunks_lang ON agent_memory.chunks(lang);
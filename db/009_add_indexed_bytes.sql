@@ -0,0 +1,6 @@
+-- Migration: Indexed length of append_only transcripts
+-- index-incremental indexes only the lines written past this many bytes,
+-- in a row whose source is the file's path.
+
+ALTER TABLE agent_memory.index_state
+    ADD COLUMN IF NOT EXISTS indexed_bytes BIGINT;
@@ -0,0 +1,6 @@
+-- Migration: Per-chunk provenance (store_metadata: true)
+-- Transcript chunks record their roles and message indices, markdown chunks
+-- their heading path and offsets. NULL when store_metadata is off.
+
+ALTER TABLE agent_memory.chunks
+    ADD COLUMN IF NOT EXISTS metadata JSONB;
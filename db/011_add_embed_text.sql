@@ -0,0 +1,6 @@
+-- Migration: Embedded text kept apart from displayed content (store_embed_text: true)
+-- Holds the exact text a chunk's embedding was computed from (e.g. with its
+-- title prepended). NULL when that is just `content`, which is what search shows.
+
+ALTER TABLE agent_memory.chunks
+    ADD COLUMN IF NOT EXISTS embed_text TEXT;
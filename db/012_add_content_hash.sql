@@ -0,0 +1,6 @@
+-- Migration: SHA-256 of each chunk's source file (content_hash: true)
+-- index-incremental skips a file whose mtime changed when its contents still
+-- hash to this. NULL when content_hash is off.
+
+ALTER TABLE agent_memory.chunks
+    ADD COLUMN IF NOT EXISTS content_hash TEXT;
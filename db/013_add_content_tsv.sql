@@ -0,0 +1,11 @@
+-- Migration: Full-text index over chunk content (search --hybrid)
+-- A generated tsvector with the 'simple' configuration (no stemming or stop
+-- words), so exact terms such as error codes and names match. Rows stored
+-- with compress_content have no plain content and never match.
+-- Adding the column rewrites the table once.
+
+ALTER TABLE agent_memory.chunks
+    ADD COLUMN IF NOT EXISTS content_tsv tsvector
+    GENERATED ALWAYS AS (to_tsvector('simple', coalesce(content, ''))) STORED;
+
+CREATE INDEX IF NOT EXISTS idx_chunks_content_tsv ON agent_memory.chunks USING gin(content_tsv);
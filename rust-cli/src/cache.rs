@@ -0,0 +1,135 @@
+//! On-disk caches for search: query embeddings, keyed by model and query
+//! text, so repeated searches skip the embedding call and can run with Ollama
+//! down; and whole result pages, kept for a short TTL.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::search::SearchResult;
+
+pub struct QueryCache {
+    path: PathBuf,
+    entries: HashMap<String, Vec<f64>>,
+}
+
+impl QueryCache {
+    /// Load the cache at `path`; a missing file is an empty cache.
+    pub fn load(path: &Path) -> Result<Self> {
+        let entries = match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| anyhow::anyhow!("Invalid query cache at {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => anyhow::bail!("Cannot read query cache at {}: {}", path.display(), e),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    pub fn get(&self, model: &str, query: &str) -> Option<&Vec<f64>> {
+        self.entries.get(&key(model, query))
+    }
+
+    /// Add an entry and write the whole cache back to disk.
+    pub fn insert(&mut self, model: &str, query: &str, embedding: Vec<f64>) -> Result<()> {
+        self.entries.insert(key(model, query), embedding);
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&self.path, serde_json::to_string(&self.entries)?).map_err(|e| {
+            anyhow::anyhow!("Cannot write query cache at {}: {}", self.path.display(), e)
+        })
+    }
+}
+
+fn key(model: &str, query: &str) -> String {
+    format!("{}\n{}", model, query)
+}
+
+/// Finished search results (`result_cache`), keyed by everything that shapes
+/// a page, so a query repeated within the TTL needs neither the embedding
+/// server nor the database. Any index run deletes the file.
+pub struct ResultCache {
+    path: PathBuf,
+    entries: HashMap<String, CachedResults>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedResults {
+    /// Seconds since the Unix epoch
+    stored_at: u64,
+    total: Option<i64>,
+    results: Vec<SearchResult>,
+}
+
+impl ResultCache {
+    /// Load the cache at `path`, dropping entries older than `ttl_secs`. A
+    /// missing or unreadable file is an empty cache: it only saves work.
+    pub fn load(path: &Path, ttl_secs: u64) -> Result<Self> {
+        let mut entries: HashMap<String, CachedResults> = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        let now = now_secs();
+        entries.retain(|_, e| now.saturating_sub(e.stored_at) < ttl_secs);
+        Ok(Self {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<(Vec<SearchResult>, Option<i64>)> {
+        self.entries.get(key).map(|e| (e.results.clone(), e.total))
+    }
+
+    /// Add an entry and write the cache back, through a temporary file so a
+    /// concurrent search never reads half of it.
+    pub fn insert(
+        &mut self,
+        key: String,
+        results: &[SearchResult],
+        total: Option<i64>,
+    ) -> Result<()> {
+        self.entries.insert(
+            key,
+            CachedResults {
+                stored_at: now_secs(),
+                total,
+                results: results.to_vec(),
+            },
+        );
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = self
+            .path
+            .with_extension(format!("tmp{}", std::process::id()));
+        std::fs::write(&tmp, serde_json::to_string(&self.entries)?)
+            .and_then(|_| std::fs::rename(&tmp, &self.path))
+            .map_err(|e| {
+                anyhow::anyhow!(
+                    "Cannot write result cache at {}: {}",
+                    self.path.display(),
+                    e
+                )
+            })
+    }
+
+    /// Forget every cached page after the index changed.
+    pub fn invalidate(config: &Config) {
+        if let Some(path) = &config.result_cache {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
@@ -1,32 +1,406 @@
-/// Text chunking logic — splits markdown and transcripts into embeddable pieces.
+//! Text chunking logic — splits markdown and transcripts into embeddable pieces.
 
-pub fn chunk_text(text: &str, max_chars: usize) -> Vec<String> {
-    let paragraphs: Vec<&str> = text.split("\n\n").collect();
-    let mut chunks: Vec<String> = Vec::new();
+/// A chunk plus its character span (`start..end`) in the source text.
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub content: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Paragraph-packed chunks of `text` with their positions, without the
+/// markdown options.
+pub fn chunk_spans(text: &str, max_chars: usize) -> Vec<Chunk> {
+    chunk_markdown(
+        text,
+        &ChunkOptions {
+            max_chars,
+            max_tokens: None,
+            overlap_chars: 0,
+            collapse_whitespace: false,
+            stable: false,
+        },
+    )
+}
+
+/// How `chunk_markdown` splits a document.
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkOptions {
+    pub max_chars: usize,
+    /// Budget chunks by tokens instead of `max_chars`. Paragraphs and lines
+    /// still come first; one that doesn't fit is split at sentence ends, and a
+    /// sentence that doesn't fit wherever the budget runs out, so no chunk
+    /// exceeds it.
+    pub max_tokens: Option<usize>,
+    /// Start each chunk after the first with about this many trailing
+    /// characters of the previous one, from a word start, on top of the budget.
+    pub overlap_chars: usize,
+    /// Chunk the `collapse_whitespace` form of the text, so blank lines and
+    /// indentation don't eat the character budget.
+    pub collapse_whitespace: bool,
+    /// Also end chunks at headings and content-chosen paragraphs (see `byte_spans`).
+    pub stable: bool,
+}
+
+/// Paragraph-packed chunks of `text`. Spans always point into `text`, even
+/// when chunking its whitespace-collapsed form.
+pub fn chunk_markdown(text: &str, opts: &ChunkOptions) -> Vec<Chunk> {
+    chunk_markdown_counted(text, opts).0
+}
+
+/// `chunk_markdown`, plus how many chunks were dropped as too small.
+pub fn chunk_markdown_counted(text: &str, opts: &ChunkOptions) -> (Vec<Chunk>, usize) {
+    if !opts.collapse_whitespace {
+        let (spans, dropped) = byte_spans(text, opts);
+        let chunks = spans
+            .into_iter()
+            .map(|(content, start, end)| Chunk {
+                content,
+                start: text[..start].chars().count(),
+                end: text[..end].chars().count(),
+            })
+            .collect();
+        return (chunks, dropped);
+    }
+
+    let (collapsed, origin) = collapse_whitespace(text);
+    let (spans, dropped) = byte_spans(&collapsed, opts);
+    let chunks = spans
+        .into_iter()
+        .map(|(content, start, end)| {
+            // Chunks are trimmed, so both ends sit on copied characters
+            let start = origin[start];
+            let end = origin[end - 1] + 1;
+            Chunk {
+                content,
+                start: text[..start].chars().count(),
+                end: text[..end].chars().count(),
+            }
+        })
+        .collect();
+    (chunks, dropped)
+}
+
+/// On average, one paragraph in this many ends a chunk when chunking is `stable`.
+const ANCHOR_EVERY: u64 = 4;
+
+/// Chunks of this many bytes or fewer are dropped.
+const MIN_CHUNK_BYTES: usize = 20;
+
+/// Paragraph-packed chunks of `text` with their byte spans, and the number of
+/// tiny chunks dropped.
+///
+/// Greedy packing lets an edit near the top shift every later boundary. With
+/// `stable`, chunks also end before each heading and after any paragraph whose
+/// hash picks it as an anchor, so boundaries depend on nearby content only and
+/// an edit changes the chunks around it rather than the rest of the file.
+fn byte_spans(text: &str, opts: &ChunkOptions) -> (Vec<(String, usize, usize)>, usize) {
+    let stable = opts.stable;
+    let mut chunks: Vec<(String, usize, usize)> = Vec::new();
     let mut current = String::new();
+    let mut current_start = 0;
+    let mut current_end = 0;
 
-    for para in paragraphs {
-        let para = para.trim();
-        if para.is_empty() {
-            continue;
-        }
-        if !current.is_empty() && current.len() + para.len() + 2 > max_chars {
-            chunks.push(current.trim().to_string());
+    let units = match opts.max_tokens {
+        Some(max) => token_pieces(text, max),
+        None => pieces(text, opts.max_chars),
+    };
+    for (para_start, para_end, sep) in units {
+        let para = &text[para_start..para_end];
+        let heading = stable && para.starts_with('#');
+        let too_big = match opts.max_tokens {
+            Some(max) => token_count(&format!("{}{}{}", current, sep, para)) > max,
+            None => current.len() + para.len() + sep.len() > opts.max_chars,
+        };
+        if !current.is_empty() && (heading || too_big) {
+            chunks.push((current.trim().to_string(), current_start, current_end));
             current = para.to_string();
+            current_start = para_start;
         } else {
-            if !current.is_empty() {
-                current.push_str("\n\n");
+            if current.is_empty() {
+                current_start = para_start;
+            } else {
+                current.push_str(sep);
             }
             current.push_str(para);
         }
+        current_end = para_end;
+
+        if stable && fnv1a(para.as_bytes()).is_multiple_of(ANCHOR_EVERY) {
+            chunks.push((current.trim().to_string(), current_start, current_end));
+            current.clear();
+        }
     }
 
     if !current.trim().is_empty() {
-        chunks.push(current.trim().to_string());
+        chunks.push((current.trim().to_string(), current_start, current_end));
     }
 
     // Skip tiny chunks
-    chunks.into_iter().filter(|c| c.len() > 20).collect()
+    let before = chunks.len();
+    chunks.retain(|(c, _, _)| c.len() > MIN_CHUNK_BYTES);
+    let dropped = before - chunks.len();
+
+    // Back to front, so each chunk overlaps its predecessor's own text
+    if opts.overlap_chars > 0 {
+        for i in (1..chunks.len()).rev() {
+            let (_, prev_start, prev_end) = chunks[i - 1];
+            let from = overlap_start(text, prev_start, prev_end, opts.overlap_chars);
+            let (content, start, _) = &mut chunks[i];
+            if from < prev_end {
+                *content = format!("{}{}", &text[from..*start], content);
+                *start = from;
+            }
+        }
+    }
+    (chunks, dropped)
+}
+
+/// Where the last `n` characters of `text[start..end]` begin, moved on to the
+/// next word so an overlap never opens mid-word. Unspaced scripts (CJK) can
+/// start at any character.
+fn overlap_start(text: &str, start: usize, end: usize, n: usize) -> usize {
+    let Some((i, _)) = text[start..end]
+        .char_indices()
+        .rev()
+        .nth(n.saturating_sub(1))
+    else {
+        return start;
+    };
+    let at = start + i;
+    if at == start || text[..at].ends_with(char::is_whitespace) {
+        return at;
+    }
+    match text[at..end].find(char::is_whitespace) {
+        Some(ws) => {
+            let rest = &text[at + ws..end];
+            end - rest.trim_start().len()
+        }
+        None if !text[at..].starts_with(|c: char| c.is_ascii_alphanumeric()) => at,
+        None => end,
+    }
+}
+
+/// Trimmed byte spans of the units `byte_spans` packs, each with the separator
+/// that joins it to the previous unit (`"\n\n"` when it starts a paragraph).
+/// Paragraphs end at one or more blank lines (whitespace-only and CRLF lines
+/// count as blank). A paragraph longer than `max_chars` that has line breaks,
+/// as in notes that never leave a blank line, is packed line by line instead
+/// of becoming one oversized chunk.
+fn pieces(text: &str, max_chars: usize) -> Vec<(usize, usize, &'static str)> {
+    let mut out = Vec::new();
+    for (start, end) in paragraph_spans(text) {
+        let para = &text[start..end];
+        if para.len() <= max_chars || !para.contains('\n') {
+            out.push((start, end, "\n\n"));
+            continue;
+        }
+        for (i, (s, e)) in line_spans(text, start, end).into_iter().enumerate() {
+            out.push((s, e, if i == 0 { "\n\n" } else { "\n" }));
+        }
+    }
+    out
+}
+
+/// `pieces` for a token budget: paragraphs, then lines, then sentences, then
+/// budget-sized runs of characters, going only as fine as needed to fit `max`.
+fn token_pieces(text: &str, max: usize) -> Vec<(usize, usize, &'static str)> {
+    let mut out = Vec::new();
+    for (start, end) in paragraph_spans(text) {
+        split_to_fit(text, start, end, max, 0, "\n\n", &mut out);
+    }
+    out
+}
+
+fn split_to_fit(
+    text: &str,
+    start: usize,
+    end: usize,
+    max: usize,
+    level: u8,
+    sep: &'static str,
+    out: &mut Vec<(usize, usize, &'static str)>,
+) {
+    if token_count(&text[start..end]) <= max {
+        out.push((start, end, sep));
+        return;
+    }
+    let (parts, inner) = match level {
+        0 => (line_spans(text, start, end), "\n"),
+        1 => (sentence_spans(text, start, end), " "),
+        _ => {
+            // Longest prefix that fits, by binary search over char boundaries
+            let mut s = start;
+            let mut sep = sep;
+            while s < end {
+                let bounds: Vec<usize> = text[s..end]
+                    .char_indices()
+                    .skip(1)
+                    .map(|(i, _)| s + i)
+                    .chain([end])
+                    .collect();
+                let fits = bounds.partition_point(|&e| token_count(&text[s..e]) <= max);
+                let e = bounds[fits.saturating_sub(1)];
+                out.push((s, e, sep));
+                sep = "";
+                s = e;
+            }
+            return;
+        }
+    };
+    let mut prev_end = start;
+    for (i, (s, e)) in parts.into_iter().enumerate() {
+        let sep = match i {
+            0 => sep,
+            // CJK sentences run on without a space between them
+            _ if s == prev_end => "",
+            _ => inner,
+        };
+        split_to_fit(text, s, e, max, level + 1, sep, out);
+        prev_end = e;
+    }
+}
+
+/// Trimmed, non-empty paragraphs of `text`.
+fn paragraph_spans(text: &str) -> Vec<(usize, usize)> {
+    let blank_lines = regex::Regex::new(r"\n[ \t\r]*\n").unwrap();
+    let mut bounds = Vec::new();
+    let mut start = 0;
+    for m in blank_lines.find_iter(text) {
+        bounds.push(trimmed(text, start, m.start()));
+        start = m.end();
+    }
+    bounds.push(trimmed(text, start, text.len()));
+    bounds.retain(|(s, e)| s < e);
+    bounds
+}
+
+/// Trimmed, non-empty lines of `text[start..end]`.
+fn line_spans(text: &str, start: usize, end: usize) -> Vec<(usize, usize)> {
+    let mut out = Vec::new();
+    let mut line_start = start;
+    for line in text[start..end].split('\n') {
+        let (s, e) = trimmed(text, line_start, line_start + line.len());
+        line_start += line.len() + 1;
+        if s < e {
+            out.push((s, e));
+        }
+    }
+    out
+}
+
+/// Trimmed sentences of `text[start..end]`: each ends after `.`, `!` or `?`
+/// followed by whitespace, or after a CJK full stop, which needs none.
+fn sentence_spans(text: &str, start: usize, end: usize) -> Vec<(usize, usize)> {
+    let mut out = Vec::new();
+    let mut s = start;
+    let mut chars = text[start..end].char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|&(_, n)| n);
+        let boundary = matches!(c, '。' | '！' | '？')
+            || (matches!(c, '.' | '!' | '?') && next.is_none_or(char::is_whitespace));
+        if boundary {
+            let e = start + i + c.len_utf8();
+            out.push(trimmed(text, s, e));
+            s = e;
+        }
+    }
+    out.push(trimmed(text, s, end));
+    out.retain(|(s, e)| s < e);
+    out
+}
+
+fn trimmed(text: &str, start: usize, end: usize) -> (usize, usize) {
+    let raw = &text[start..end];
+    let start = start + (raw.len() - raw.trim_start().len());
+    (start, start + raw.trim().len())
+}
+
+/// Tokens in `text` under OpenAI's cl100k_base BPE: exact for OpenAI
+/// embedding models, a close estimate for other subword tokenizers.
+pub fn token_count(text: &str) -> usize {
+    tiktoken_rs::cl100k_base_singleton()
+        .encode_ordinary(text)
+        .len()
+}
+
+/// 64-bit FNV-1a: small, and unlike `DefaultHasher` fixed across Rust releases.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| {
+        (h ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Outside ``` fences: trim every line, squeeze runs of spaces/tabs to one
+/// space and runs of blank lines to a single blank line. Fenced code is kept
+/// byte for byte. Also returns, for each byte of the result, the byte offset
+/// in `text` it was copied from.
+pub fn collapse_whitespace(text: &str) -> (String, Vec<usize>) {
+    let mut out = String::with_capacity(text.len());
+    let mut origin = Vec::with_capacity(text.len());
+    let mut in_fence = false;
+    // Offsets of newlines seen since the last emitted line
+    let mut newlines: Vec<usize> = Vec::new();
+    let mut line_start = 0;
+
+    for line in text.split_inclusive('\n') {
+        let base = line_start;
+        line_start += line.len();
+        let body = line.strip_suffix('\n').unwrap_or(line);
+        let body = body.strip_suffix('\r').unwrap_or(body);
+        let is_fence = body.trim_start().starts_with("```");
+
+        if in_fence || is_fence {
+            if !out.is_empty() {
+                let keep = if in_fence {
+                    newlines.len()
+                } else {
+                    newlines.len().min(2)
+                };
+                for &nl in &newlines[..keep] {
+                    out.push('\n');
+                    origin.push(nl);
+                }
+            }
+            newlines.clear();
+            out.push_str(body);
+            origin.extend(base..base + body.len());
+            if is_fence {
+                in_fence = !in_fence;
+            }
+        } else {
+            let trimmed = body.trim();
+            if !trimmed.is_empty() {
+                if !out.is_empty() {
+                    for &nl in &newlines[..newlines.len().min(2)] {
+                        out.push('\n');
+                        origin.push(nl);
+                    }
+                }
+                newlines.clear();
+                let offset = base + (body.len() - body.trim_start().len());
+                let mut prev_space = false;
+                for (i, c) in trimmed.char_indices() {
+                    if c == ' ' || c == '\t' {
+                        if !prev_space {
+                            out.push(' ');
+                            origin.push(offset + i);
+                        }
+                        prev_space = true;
+                    } else {
+                        out.push(c);
+                        origin.extend(offset + i..offset + i + c.len_utf8());
+                        prev_space = false;
+                    }
+                }
+            }
+        }
+        if line.ends_with('\n') {
+            newlines.push(base + line.len() - 1);
+        }
+    }
+
+    (out, origin)
 }
 
 /// Extract date from a filename like "2026-01-30.md"
@@ -35,72 +409,675 @@ pub fn extract_date(filename: &str) -> Option<String> {
     re.captures(filename).map(|c| c[1].to_string())
 }
 
-/// Parse JSONL transcript into conversation chunks.
-/// Handles two formats:
-///   {"role": "user", "content": "..."}
-///   {"type": "message", "message": {"role": "...", "content": [{"type":"text","text":"..."}]}}
-pub fn parse_transcript(text: &str) -> Vec<String> {
-    let mut messages: Vec<String> = Vec::new();
+/// Split a leading `---` YAML frontmatter block (closed by `---` or `...`)
+/// off `text`, returning it parsed and the body after it. A block that isn't
+/// a YAML mapping is left in the body, as is text with no block at all.
+pub fn parse_frontmatter(text: &str) -> (Option<serde_yaml::Value>, &str) {
+    let Some(rest) = text
+        .strip_prefix("---\n")
+        .or_else(|| text.strip_prefix("---\r\n"))
+    else {
+        return (None, text);
+    };
+    let mut pos = 0;
+    for line in rest.split_inclusive('\n') {
+        let end = pos + line.len();
+        if matches!(line.trim_end(), "---" | "...") {
+            return match serde_yaml::from_str::<serde_yaml::Value>(&rest[..pos]) {
+                Ok(fm) if fm.is_mapping() => (Some(fm), &rest[end..]),
+                _ => (None, text),
+            };
+        }
+        pos = end;
+    }
+    (None, text)
+}
+
+/// Frontmatter `date:` as `YYYY-MM-DD` (a timestamp is cut to its day).
+pub fn frontmatter_date(frontmatter: &serde_yaml::Value) -> Option<String> {
+    frontmatter
+        .get("date")
+        .and_then(|d| d.as_str())
+        .and_then(extract_date)
+}
+
+/// Title for a markdown document: frontmatter `title:`, else the first `# `
+/// heading, else the filename stem.
+pub fn extract_title(text: &str, file_stem: &str) -> String {
+    let title = parse_frontmatter(text).0.and_then(|fm| {
+        fm.get("title")
+            .and_then(|t| t.as_str())
+            .map(|t| t.trim().to_string())
+    });
+    if let Some(title) = title.filter(|t| !t.is_empty()) {
+        return title;
+    }
+
+    text.lines()
+        .find_map(|l| l.strip_prefix("# "))
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| file_stem.to_string())
+}
+
+/// A heading: character offset (as in `Chunk::start`), level and text.
+pub struct Heading {
+    pub pos: usize,
+    pub level: usize,
+    pub title: String,
+}
 
-    for line in text.lines() {
-        let line = line.trim();
-        if line.is_empty() {
+/// Every heading, in order: `#` lines, and setext titles underlined with
+/// `===` (level 1) or `---` (level 2). Lines inside ``` or ~~~ fences and a
+/// leading `---` front matter block are not headings.
+pub fn heading_positions(text: &str) -> Vec<Heading> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    let mut fence: Option<&str> = None;
+    let mut front_matter = text.starts_with("---\n") || text.starts_with("---\r\n");
+    // The previous line, when it could be a setext title: (offset, text)
+    let mut previous: Option<(usize, &str)> = None;
+    for (i, line) in text.split_inclusive('\n').enumerate() {
+        let line_pos = pos;
+        pos += line.chars().count();
+        let body = line.trim_end();
+        let trimmed = body.trim_start();
+        if front_matter {
+            front_matter = i == 0 || body != "---";
             continue;
         }
-        let Ok(entry) = serde_json::from_str::<serde_json::Value>(line) else {
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) {
+                fence = None;
+            }
+            continue;
+        }
+        if let Some(marker) = ["```", "~~~"].into_iter().find(|m| trimmed.starts_with(m)) {
+            fence = Some(marker);
+            previous = None;
+            continue;
+        }
+
+        let title = body.trim_start_matches('#');
+        let hashes = body.len() - title.len();
+        if (1..=6).contains(&hashes) && title.starts_with(' ') && !title.trim().is_empty() {
+            out.push(Heading {
+                pos: line_pos,
+                level: hashes,
+                title: title.trim().trim_end_matches('#').trim().to_string(),
+            });
+            previous = None;
             continue;
+        }
+        let underline = |c: char| {
+            !trimmed.is_empty() && trimmed.chars().all(|x| x == c) && body.len() - trimmed.len() < 4
+        };
+        let setext = if underline('=') {
+            Some(1)
+        } else if underline('-') {
+            Some(2)
+        } else {
+            None
         };
+        match (setext, previous) {
+            (Some(level), Some((title_pos, title))) => {
+                out.push(Heading {
+                    pos: title_pos,
+                    level,
+                    title: title.to_string(),
+                });
+                previous = None;
+            }
+            _ => {
+                // List items, quotes and table rows don't take an underline
+                let paragraph = !trimmed.is_empty()
+                    && body.len() - trimmed.len() < 4
+                    && !["- ", "* ", "+ ", "> ", "|"]
+                        .iter()
+                        .any(|m| trimmed.starts_with(m));
+                previous = paragraph.then_some((line_pos, trimmed));
+            }
+        }
+    }
+    out
+}
+
+/// Titles of the headings enclosing character offset `pos`, outermost first.
+pub fn heading_path(headings: &[Heading], pos: usize) -> Vec<String> {
+    let mut path: Vec<&Heading> = Vec::new();
+    for h in headings.iter().take_while(|h| h.pos <= pos) {
+        while path.last().is_some_and(|p| p.level >= h.level) {
+            path.pop();
+        }
+        path.push(h);
+    }
+    path.into_iter().map(|h| h.title.clone()).collect()
+}
+
+/// `# Day > ## Meetings`: the headings enclosing character offset `pos`,
+/// outermost first, not counting one that starts right at `pos` (a chunk
+/// opening with its own heading already shows it). `None` outside any section.
+pub fn breadcrumb(headings: &[Heading], pos: usize) -> Option<String> {
+    let mut path: Vec<&Heading> = Vec::new();
+    for h in headings.iter().take_while(|h| h.pos < pos) {
+        while path.last().is_some_and(|p| p.level >= h.level) {
+            path.pop();
+        }
+        path.push(h);
+    }
+    if path.is_empty() {
+        return None;
+    }
+    let crumbs: Vec<String> = path
+        .iter()
+        .map(|h| format!("{} {}", "#".repeat(h.level), h.title))
+        .collect();
+    Some(crumbs.join(" > "))
+}
+
+/// Chunk size limit for transcripts (`max_chars` applies to markdown only).
+pub const TRANSCRIPT_CHUNK_CHARS: usize = 1000;
+
+/// Parse a transcript into conversation chunks.
+/// Handles JSONL, one message per line:
+///   {"role": "user", "content": "..."}
+///   {"type": "message", "message": {"role": "...", "content": [{"type":"text","text":"..."}]}}
+/// and whole-file JSON:
+///   {"messages": [...]}                      (OpenAI chat format, also accepted per JSONL line)
+///   [{"mapping": {...}, "current_node": ...}] (ChatGPT conversations.json export)
+/// Only messages whose role is in `include_roles` are kept. A `name` field on
+/// the message is kept in the prefix, e.g. `[assistant:planner]`.
+/// Each conversation is chunked on its own so chunks don't straddle two chats.
+pub fn parse_transcript(text: &str, include_roles: &[String]) -> Vec<String> {
+    parse_transcript_chunks(text, include_roles)
+        .into_iter()
+        .map(|c| c.content)
+        .collect()
+}
+
+/// A transcript chunk and the messages it was built from.
+pub struct TranscriptChunk {
+    pub content: String,
+    /// Position of the conversation in an export; `None` for per-line messages
+    pub conversation: Option<usize>,
+    /// Index of each message: its line in JSONL, else its place in the conversation
+    pub messages: Vec<usize>,
+    /// Distinct role prefixes of those messages (`user`, `assistant:planner`, ...)
+    pub roles: Vec<String>,
+}
+
+/// One kept message: where it came from, its role prefix and rendered text.
+struct Message {
+    index: usize,
+    role: String,
+    text: String,
+}
+
+/// `parse_transcript`, keeping which messages each chunk covers.
+pub fn parse_transcript_chunks(text: &str, include_roles: &[String]) -> Vec<TranscriptChunk> {
+    // Index 0 collects loose per-line messages; exports add one entry per conversation.
+    let mut conversations: Vec<Vec<Message>> = vec![Vec::new()];
+
+    match serde_json::from_str::<serde_json::Value>(text.trim()) {
+        Ok(doc) => collect_messages(&doc, include_roles, 0, &mut conversations),
+        Err(_) => {
+            for (i, line) in text.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                let Ok(entry) = serde_json::from_str::<serde_json::Value>(line) else {
+                    continue;
+                };
+                collect_messages(&entry, include_roles, i, &mut conversations);
+            }
+        }
+    }
 
-        let (role, content) = if entry.get("type").and_then(|t| t.as_str()) == Some("message") {
+    let mut out = Vec::new();
+    for (i, messages) in conversations.into_iter().enumerate() {
+        if messages.is_empty() {
+            continue;
+        }
+        // Character span of each message in the joined conversation
+        let mut spans = Vec::with_capacity(messages.len());
+        let mut pos = 0;
+        for m in &messages {
+            let len = m.text.chars().count();
+            spans.push((pos, pos + len));
+            pos += len + 2;
+        }
+        let joined = messages
+            .iter()
+            .map(|m| m.text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        for c in chunk_spans(&joined, TRANSCRIPT_CHUNK_CHARS) {
+            let covered: Vec<&Message> = messages
+                .iter()
+                .zip(&spans)
+                .filter(|(_, &(start, end))| start < c.end && end > c.start)
+                .map(|(m, _)| m)
+                .collect();
+            let mut roles: Vec<String> = Vec::new();
+            for m in &covered {
+                if !roles.contains(&m.role) {
+                    roles.push(m.role.clone());
+                }
+            }
+            out.push(TranscriptChunk {
+                content: c.content,
+                conversation: i.checked_sub(1),
+                messages: covered.iter().map(|m| m.index).collect(),
+                roles,
+            });
+        }
+    }
+    out
+}
+
+/// Add the messages in `entry` to `conversations`; a loose message is
+/// recorded at `index` (its line, or its place in a top-level array).
+fn collect_messages(
+    entry: &serde_json::Value,
+    include_roles: &[String],
+    index: usize,
+    conversations: &mut Vec<Vec<Message>>,
+) {
+    if let Some(items) = entry.as_array() {
+        for (i, item) in items.iter().enumerate() {
+            collect_messages(item, include_roles, i, conversations);
+        }
+    } else if let Some(mapping) = entry.get("mapping").and_then(|m| m.as_object()) {
+        let messages =
+            linearize_mapping(mapping, entry.get("current_node").and_then(|n| n.as_str()))
+                .into_iter()
+                .enumerate()
+                .filter_map(|(i, msg)| format_message(msg, include_roles, i))
+                .collect();
+        conversations.push(messages);
+    } else if let Some(items) = entry.get("messages").and_then(|m| m.as_array()) {
+        let messages = items
+            .iter()
+            .enumerate()
+            .filter_map(|(i, msg)| format_message(msg, include_roles, i))
+            .collect();
+        conversations.push(messages);
+    } else {
+        let msg = if entry.get("type").and_then(|t| t.as_str()) == Some("message") {
             // Nested format
-            let msg = entry.get("message").unwrap_or(&entry);
-            let role = msg.get("role").and_then(|r| r.as_str()).unwrap_or("");
-            let content = match msg.get("content") {
-                Some(serde_json::Value::String(s)) => s.clone(),
-                Some(serde_json::Value::Array(arr)) => arr
-                    .iter()
-                    .filter_map(|c| {
-                        if c.get("type").and_then(|t| t.as_str()) == Some("text") {
-                            c.get("text").and_then(|t| t.as_str()).map(|s| s.to_string())
-                        } else {
-                            None
-                        }
-                    })
-                    .collect::<Vec<_>>()
-                    .join(" "),
-                _ => String::new(),
-            };
-            (role.to_string(), content)
+            entry.get("message").unwrap_or(entry)
         } else {
-            // Simple format
-            let role = entry.get("role").and_then(|r| r.as_str()).unwrap_or("");
-            let content = match entry.get("content") {
-                Some(serde_json::Value::String(s)) => s.clone(),
-                Some(serde_json::Value::Array(arr)) => arr
-                    .iter()
-                    .filter_map(|c| {
-                        if c.get("type").and_then(|t| t.as_str()) == Some("text") {
-                            c.get("text").and_then(|t| t.as_str()).map(|s| s.to_string())
-                        } else {
-                            None
-                        }
-                    })
-                    .collect::<Vec<_>>()
-                    .join(" "),
-                _ => String::new(),
-            };
-            (role.to_string(), content)
+            entry
+        };
+        if let Some(m) = format_message(msg, include_roles, index) {
+            conversations[0].push(m);
+        }
+    }
+}
+
+/// Render one message as `[role] content`, or `None` if its role isn't wanted
+/// or it has no real text. ChatGPT exports keep role/name under `author`.
+fn format_message(
+    msg: &serde_json::Value,
+    include_roles: &[String],
+    index: usize,
+) -> Option<Message> {
+    let author = msg.get("author").unwrap_or(msg);
+    let role = author.get("role").and_then(|r| r.as_str())?;
+    let name = author.get("name").and_then(|n| n.as_str());
+    let content = msg.get("content").map(extract_content).unwrap_or_default();
+
+    if !include_roles.iter().any(|r| r == role) || content.len() <= 20 {
+        return None;
+    }
+    let role = match name {
+        Some(name) => format!("{}:{}", role, name),
+        None => role.to_string(),
+    };
+    Some(Message {
+        index,
+        text: format!("[{}] {}", role, content),
+        role,
+    })
+}
+
+/// Flatten the text parts of a message `content`: a plain string, an array of
+/// strings / `{"type":"text","text":...}` parts, or ChatGPT's `{"parts": [...]}`.
+fn extract_content(content: &serde_json::Value) -> String {
+    match content {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Array(arr) => arr
+            .iter()
+            .filter_map(|c| match c {
+                serde_json::Value::String(s) => Some(s.clone()),
+                _ if c.get("type").and_then(|t| t.as_str()) == Some("text") => c
+                    .get("text")
+                    .and_then(|t| t.as_str())
+                    .map(|s| s.to_string()),
+                _ => None,
+            })
+            .filter(|s| !s.trim().is_empty())
+            .collect::<Vec<_>>()
+            .join(" "),
+        serde_json::Value::Object(_) => content
+            .get("parts")
+            .map(extract_content)
+            .unwrap_or_default(),
+        _ => String::new(),
+    }
+}
+
+/// Turn a ChatGPT `mapping` tree into the message sequence of the visible
+/// branch: walk up from `current_node` when present, otherwise follow the
+/// first child down from the root.
+fn linearize_mapping<'a>(
+    mapping: &'a serde_json::Map<String, serde_json::Value>,
+    current_node: Option<&str>,
+) -> Vec<&'a serde_json::Value> {
+    let mut path: Vec<&serde_json::Value> = Vec::new();
+
+    if let Some(mut id) = current_node {
+        while let Some(node) = mapping.get(id) {
+            path.push(node);
+            match node.get("parent").and_then(|p| p.as_str()) {
+                Some(parent) if path.len() <= mapping.len() => id = parent,
+                _ => break,
+            }
+        }
+        path.reverse();
+    } else {
+        let mut node = mapping
+            .values()
+            .find(|n| n.get("parent").is_none_or(|p| p.is_null()));
+        while let Some(n) = node {
+            if path.len() > mapping.len() {
+                break;
+            }
+            path.push(n);
+            node = n
+                .get("children")
+                .and_then(|c| c.get(0))
+                .and_then(|c| c.as_str())
+                .and_then(|id| mapping.get(id));
+        }
+    }
+
+    path.into_iter()
+        .filter_map(|n| n.get("message"))
+        .filter(|m| !m.is_null())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(max_chars: usize) -> ChunkOptions {
+        ChunkOptions {
+            max_chars,
+            max_tokens: None,
+            overlap_chars: 0,
+            collapse_whitespace: false,
+            stable: false,
+        }
+    }
+
+    /// A daily-notes style document: a heading every eight paragraphs.
+    fn notes(paragraphs: usize) -> Vec<String> {
+        (0..paragraphs)
+            .map(|i| match i % 8 {
+                0 => format!("## Section {}", i / 8),
+                _ => format!(
+                    "Paragraph {} notes the follow-ups from meeting {} and who owns them.",
+                    i,
+                    i * 7 % 13
+                ),
+            })
+            .collect()
+    }
+
+    fn contents(text: &str, opts: &ChunkOptions) -> Vec<String> {
+        chunk_markdown(text, opts)
+            .into_iter()
+            .map(|c| c.content)
+            .collect()
+    }
+
+    #[test]
+    fn stable_chunks_change_only_around_an_insert() {
+        let mut paragraphs = notes(60);
+        let before = paragraphs.join("\n\n");
+        paragraphs.insert(
+            30,
+            "An inserted paragraph about a decision made later that day.".to_string(),
+        );
+        let after = paragraphs.join("\n\n");
+
+        let stable = ChunkOptions {
+            stable: true,
+            ..options(400)
+        };
+        let (old, new) = (contents(&before, &stable), contents(&after, &stable));
+        let changed = new.iter().filter(|c| !old.contains(c)).count();
+        assert!(changed <= 2, "{} of {} chunks changed", changed, new.len());
+        assert!(new.len() > 8);
+
+        // Greedy packing shifts every boundary after the insert
+        let (old, new) = (
+            contents(&before, &options(400)),
+            contents(&after, &options(400)),
+        );
+        assert!(new.iter().filter(|c| !old.contains(c)).count() > changed);
+    }
+
+    const MIXED: &str = "# 週報 Weekly notes\n\n\
+        The migration to the new queue finished on Tuesday. Latency dropped by a third, and the on-call load went down with it.\n\n\
+        新しいキューへの移行は火曜日に完了しました。レイテンシは三分の一減り、当番の負担も軽くなりました。次は監視ダッシュボードの整理です。\n\n\
+        我们把旧的批处理任务全部迁移到了新的队列系统。迁移过程中没有丢失任何消息，但是有两次重试风暴，需要在下个季度调整退避参数。\n\n\
+        这一段故意没有任何标点符号所以只能按照字符切分才能放进预算里面而不是按照句子或者行来切分的一段很长很长的中文文本用来测试最细的切分";
+
+    #[test]
+    fn token_chunks_fit_the_budget_in_any_language() {
+        let opts = ChunkOptions {
+            max_tokens: Some(40),
+            ..options(usize::MAX)
+        };
+        let chunks = chunk_markdown(MIXED, &opts);
+        assert!(chunks.len() >= 5, "{} chunks", chunks.len());
+        for c in &chunks {
+            assert!(
+                token_count(&c.content) <= 40,
+                "{} tokens: {:?}",
+                token_count(&c.content),
+                c.content
+            );
+        }
+        for script in ["Latency", "レイテンシ", "退避参数", "最细的切分"] {
+            assert!(
+                chunks.iter().any(|c| c.content.contains(script)),
+                "{} missing",
+                script
+            );
+        }
+    }
+
+    #[test]
+    fn token_chunks_pack_whole_paragraphs_first() {
+        let text = "Standup moved to ten.\n\nRetro is on Friday.\n\nお昼はカレーでした。";
+        let opts = ChunkOptions {
+            max_tokens: Some(100),
+            ..options(usize::MAX)
         };
+        let chunks = contents(text, &opts);
+        assert_eq!(chunks, [text]);
+    }
 
-        if (role == "user" || role == "assistant") && content.len() > 20 {
-            messages.push(format!("[{}] {}", role, content));
+    #[test]
+    fn overlap_repeats_the_end_of_the_previous_chunk_from_a_word_start() {
+        let text = notes(40).join("\n\n");
+        let opts = ChunkOptions {
+            overlap_chars: 100,
+            ..options(300)
+        };
+        let chunks = chunk_markdown(&text, &opts);
+        assert!(chunks.len() > 5);
+        for pair in chunks.windows(2) {
+            let (prev, next) = (&pair[0].content, &pair[1].content);
+            // Longest start of `next` that `prev` ends with
+            let shared = (1..=next.len().min(prev.len()))
+                .rev()
+                .find(|&n| prev.ends_with(&next[..n]))
+                .unwrap_or(0);
+            assert!(
+                (85..=100).contains(&shared),
+                "{} shared: {:?} / {:?}",
+                shared,
+                prev,
+                next
+            );
+            assert!(text[..pair[1].start].ends_with(char::is_whitespace));
+            assert_eq!(*next, text[pair[1].start..pair[1].end]);
         }
     }
 
-    if messages.is_empty() {
-        return Vec::new();
+    #[test]
+    fn overlap_does_not_rescue_tiny_chunks() {
+        let text = format!("{}\n\nDone.", notes(6)[1..].join("\n\n"));
+        let opts = ChunkOptions {
+            overlap_chars: 100,
+            ..options(70)
+        };
+        let chunks = contents(&text, &opts);
+        assert!(chunks.iter().all(|c| !c.ends_with("Done.")), "{:?}", chunks);
+        assert_eq!(contents(&text, &options(70)).len(), chunks.len());
+    }
+
+    const SECTIONS: &str = "---\ntitle: Monday\n---\n\
+# Day\n\nIntro line.\n\n## Meetings\n\nStandup notes.\n\n\
+```sh\n# not a heading\n## nor this\n```\n\n\
+### Retro\n\nRetro notes.\n\n## Errands\n\nErrand notes.\n\n\
+Evening\n=======\n\nEvening notes.\n\nLate\n----\n\nLate notes.\n\n- a list item\n---\n";
+
+    fn pos_of(text: &str, needle: &str) -> usize {
+        text[..text.find(needle).unwrap()].chars().count()
+    }
+
+    #[test]
+    fn headings_skip_fences_and_front_matter_and_read_setext() {
+        let found: Vec<(usize, String)> = heading_positions(SECTIONS)
+            .into_iter()
+            .map(|h| (h.level, h.title))
+            .collect();
+        let expected = [
+            (1, "Day"),
+            (2, "Meetings"),
+            (3, "Retro"),
+            (2, "Errands"),
+            (1, "Evening"),
+            (2, "Late"),
+        ];
+        assert_eq!(found, expected.map(|(l, t)| (l, t.to_string())));
+        let headings = heading_positions(SECTIONS);
+        assert_eq!(headings[4].pos, pos_of(SECTIONS, "Evening\n"));
+    }
+
+    #[test]
+    fn breadcrumbs_follow_nesting() {
+        let headings = heading_positions(SECTIONS);
+        let crumb = |needle: &str| breadcrumb(&headings, pos_of(SECTIONS, needle));
+        assert_eq!(crumb("Intro line").as_deref(), Some("# Day"));
+        assert_eq!(
+            crumb("# not a heading").as_deref(),
+            Some("# Day > ## Meetings")
+        );
+        assert_eq!(
+            crumb("Retro notes").as_deref(),
+            Some("# Day > ## Meetings > ### Retro")
+        );
+        // A sibling section closes the deeper one
+        assert_eq!(crumb("Errand notes").as_deref(), Some("# Day > ## Errands"));
+        assert_eq!(crumb("Late notes").as_deref(), Some("# Evening > ## Late"));
+        // A chunk starting at its own heading isn't told about it twice
+        assert_eq!(crumb("### Retro").as_deref(), Some("# Day > ## Meetings"));
+        assert_eq!(breadcrumb(&headings, 0), None);
+        assert_eq!(
+            heading_path(&headings, pos_of(SECTIONS, "### Retro")),
+            ["Day", "Meetings", "Retro"]
+        );
     }
 
-    let full_text = messages.join("\n\n");
-    chunk_text(&full_text, 1000)
+    #[test]
+    fn frontmatter_is_split_off_the_body() {
+        let (fm, body) = parse_frontmatter(
+            "---\ntitle: Plan\ndate: 2024-12-24T09:30:00\ntags: [work]\n---\n# Plan\n",
+        );
+        let fm = fm.unwrap();
+        assert_eq!(body, "# Plan\n");
+        assert_eq!(fm.get("title").and_then(|t| t.as_str()), Some("Plan"));
+        assert_eq!(frontmatter_date(&fm).as_deref(), Some("2024-12-24"));
+
+        let (fm, body) = parse_frontmatter("---\r\ntags: [a]\r\n...\r\nBody\r\n");
+        assert!(fm.is_some());
+        assert_eq!(body, "Body\r\n");
+    }
+
+    #[test]
+    fn text_without_a_frontmatter_mapping_is_all_body() {
+        for text in [
+            "# No frontmatter\n",
+            "---\nnever closed\n",
+            "---\n- a list, not a mapping\n---\nBody\n",
+            "Body first\n---\ntitle: x\n---\n",
+        ] {
+            assert_eq!(parse_frontmatter(text), (None, text));
+        }
+        assert_eq!(
+            extract_title(
+                "---\ntitle: From frontmatter\n---\n# From heading\n",
+                "stem"
+            ),
+            "From frontmatter"
+        );
+        assert_eq!(extract_title("# From heading\n", "stem"), "From heading");
+        assert_eq!(extract_title("no heading\n", "stem"), "stem");
+    }
+
+    #[test]
+    fn blank_lines_of_any_kind_end_paragraphs() {
+        let text = "one\r\n\r\ntwo\n \t\n\n\nthree\nstill three";
+        let paragraphs: Vec<&str> = paragraph_spans(text)
+            .into_iter()
+            .map(|(s, e)| &text[s..e])
+            .collect();
+        assert_eq!(paragraphs, ["one", "two", "three\nstill three"]);
+    }
+
+    #[test]
+    fn single_newline_notes_are_packed_line_by_line() {
+        let text: Vec<String> = (0..30)
+            .map(|i| {
+                format!(
+                    "- {:02}:00 checked the build queue and replied to the on-call thread",
+                    i % 24
+                )
+            })
+            .collect();
+        let text = text.join("\n");
+        let chunks = chunk_markdown(&text, &options(300));
+        assert!(chunks.len() >= 7, "{} chunks", chunks.len());
+        for c in &chunks {
+            assert!(c.content.len() <= 300, "{} bytes", c.content.len());
+            assert_eq!(c.content, text[c.start..c.end]);
+        }
+        let lines: usize = chunks.iter().map(|c| c.content.lines().count()).sum();
+        assert_eq!(lines, 30);
+    }
+
+    #[test]
+    fn crlf_paragraphs_chunk_like_lf_ones() {
+        let lf = notes(24).join("\n\n");
+        let crlf = lf.replace('\n', "\r\n");
+        assert_eq!(contents(&lf, &options(300)), contents(&crlf, &options(300)));
+    }
 }
@@ -0,0 +1,30 @@
+//! zstd compression for stored chunk content (`compress_content`). Bytes go
+//! through Postgres as hex so they fit the simple-query text protocol.
+
+use anyhow::{Context, Result};
+
+const LEVEL: i32 = 3;
+
+/// zstd-compressed `text`, as stored in `content_compressed`.
+pub fn compress(text: &str) -> Result<Vec<u8>> {
+    Ok(zstd::encode_all(text.as_bytes(), LEVEL)?)
+}
+
+/// Compress `text` and hex-encode it for `decode(..., 'hex')`.
+pub fn compress_hex(text: &str) -> Result<String> {
+    Ok(compress(text)?
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Inverse of `compress_hex`, for `encode(content_compressed, 'hex')`.
+pub fn decompress_hex(hex: &str) -> Result<String> {
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2).unwrap_or(""), 16))
+        .collect::<std::result::Result<Vec<u8>, _>>()
+        .context("content_compressed is not valid hex")?;
+    let raw = zstd::decode_all(bytes.as_slice()).context("content_compressed is not valid zstd")?;
+    Ok(String::from_utf8(raw)?)
+}
@@ -1,27 +1,361 @@
 use anyhow::Result;
 use serde::Deserialize;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub agent_id: String,
+    /// Filled from `db_url_file` when that is set
+    #[serde(default)]
     pub db_url: String,
+    /// File holding the connection string (e.g. a mounted secret); wins over `db_url`
+    pub db_url_file: Option<PathBuf>,
+    /// Replica connection string for `search`, `health` and `stats`; indexing always uses `db_url`
+    #[serde(default)]
+    pub read_db_url: Option<String>,
+    /// Encrypt database connections: `sslmode=require` for connection strings
+    /// that don't set `sslmode` themselves
+    #[serde(default)]
+    pub ssl: bool,
+    /// CA certificate (PEM) to verify the database server against, for
+    /// connection strings without `sslrootcert`; the system roots otherwise
+    #[serde(default)]
+    pub root_cert_path: Option<PathBuf>,
+    /// Limit on connecting and on each query for `search` and `health`
+    #[serde(default)]
+    pub query_timeout_secs: Option<u64>,
+    /// Embedding endpoint: Ollama's `/api/embeddings`, llama.cpp's `/embedding`,
+    /// or an OpenAI-compatible `/v1/embeddings`
     #[serde(default = "default_ollama_url")]
     pub ollama_url: String,
+    /// API spoken by `ollama_url`
+    #[serde(default)]
+    pub embed_provider: EmbedProvider,
+    /// Bearer token for `embed_provider: openai`; `OPENAI_API_KEY` when unset
+    #[serde(default)]
+    pub embed_api_key: Option<String>,
+    /// Times an embedding request is retried after a connection error or 5xx
+    #[serde(default = "default_embed_max_retries")]
+    pub embed_max_retries: u32,
+    /// First retry delay; each further retry waits twice as long (plus jitter)
+    #[serde(default = "default_embed_retry_base_ms")]
+    pub embed_retry_base_ms: u64,
     #[serde(default = "default_model")]
     pub model: String,
     #[serde(default = "default_schema")]
     pub schema: String,
+    /// Chunk table inside `schema`, for existing pgvector tables with another name
+    #[serde(default = "default_table")]
+    pub table: String,
+    /// Vector column of `table`, for tables shared with a tool that named it differently
+    #[serde(default = "default_embedding_column")]
+    pub embedding_column: String,
+    /// Known embedding size; skips probing Ollama and is checked against what the model returns
+    #[serde(default)]
+    pub model_dimension: Option<usize>,
+    /// pgvector type of the `embedding` column
+    #[serde(default)]
+    pub vector_type: VectorType,
+    /// Shortlist this many chunks by Hamming distance of binary-quantized
+    /// vectors, then rank only those by exact cosine distance
+    #[serde(default)]
+    pub binary_prefilter: Option<usize>,
+    #[serde(default = "default_max_chars")]
+    pub max_chars: usize,
+    /// What bounds a markdown chunk: `max_chars` characters or `max_tokens` tokens
+    #[serde(default)]
+    pub chunk_strategy: ChunkStrategy,
+    /// Token budget per markdown chunk with `chunk_strategy: tokens`
+    #[serde(default = "default_max_tokens")]
+    pub max_tokens: usize,
+    /// Characters of the previous markdown chunk repeated at the start of the next
+    #[serde(default)]
+    pub chunk_overlap: usize,
+    /// Files shorter than this (trimmed) are skipped. 0 leaves it to the chunk-level minimum.
+    #[serde(default)]
+    pub min_file_chars: usize,
+    /// Store `source_path` with symlinks and `..` resolved; turn off to keep paths as configured
+    #[serde(default = "default_true")]
+    pub canonical_paths: bool,
+    /// Index non-UTF-8 files with invalid bytes replaced instead of skipping them
+    #[serde(default)]
+    pub lossy_utf8: bool,
+    /// Files that chunk into more pieces than this are skipped instead of embedded
+    #[serde(default)]
+    pub max_chunks_per_file: Option<usize>,
+    /// Squeeze blank-line runs and repeated spaces (outside code fences) before chunking markdown
+    #[serde(default)]
+    pub collapse_whitespace: bool,
+    /// Anchor markdown chunk boundaries on headings and paragraph content so edits stay local
+    #[serde(default)]
+    pub stable_chunks: bool,
+    /// Embed each markdown chunk as `[<title>] <chunk>` (stored content stays unprefixed)
+    #[serde(default)]
+    pub prepend_title: bool,
+    /// Embed each markdown chunk after a `# Day > ## Meetings` line naming its
+    /// enclosing headings (stored content stays as written)
+    #[serde(default)]
+    pub section_context: bool,
+    /// Also store one embedding per file (source `document_summary`) for `search --level document`
+    #[serde(default)]
+    pub document_vectors: bool,
+    /// Store chunk text zstd-compressed in `content_compressed` instead of `content`
+    #[serde(default)]
+    pub compress_content: bool,
+    /// Detect each chunk's language and store it in `lang`, for `search --lang`
+    #[serde(default)]
+    pub detect_language: bool,
+    /// Store each chunk's provenance in `metadata` (transcript roles and message
+    /// indices, markdown heading path and offsets)
+    #[serde(default)]
+    pub store_metadata: bool,
+    /// Markdown frontmatter keys copied into every chunk's `metadata`
+    #[serde(default = "default_frontmatter_keys")]
+    pub frontmatter_keys: Vec<String>,
+    /// Store the text actually embedded in `embed_text` when it differs from
+    /// the displayed `content` (e.g. with `prepend_title`)
+    #[serde(default)]
+    pub store_embed_text: bool,
+    /// Store a SHA-256 of each file in `content_hash`, so incremental runs skip
+    /// files whose mtime changed but whose contents didn't
+    #[serde(default)]
+    pub content_hash: bool,
+    /// How chunk inserts and search queries reach Postgres: inlined `simple`
+    /// queries (safe behind transaction poolers), bound `prepared` statements,
+    /// or `auto` to probe the database once per process
+    #[serde(default)]
+    pub query_mode: QueryMode,
+    /// Older spelling of `query_mode`: `true` for `prepared`, `false` for
+    /// `simple`; ignored when `query_mode` isn't `auto`
+    #[serde(default)]
+    pub use_prepared_statements: Option<bool>,
+    /// Load each file's chunks with binary COPY instead of one INSERT per chunk
+    /// (needs `vector_type: vector` and a direct, non-pooled connection)
+    #[serde(default)]
+    pub bulk_copy: bool,
+    /// Store chunks with a NULL embedding for `embed-pending` to fill in later
+    #[serde(default)]
+    pub defer_embeddings: bool,
+    /// Embed a throwaway string before indexing so the model is loaded up front
+    #[serde(default)]
+    pub model_warmup: bool,
+    /// Chunks sent per `/api/embed` request
+    #[serde(default = "default_embed_batch_size")]
+    pub embed_batch_size: usize,
+    /// Embedding requests in flight at once while indexing
+    #[serde(default = "default_embed_concurrency")]
+    pub embed_concurrency: usize,
+    /// Worker threads reading and chunking files ahead of embedding in full index runs
+    #[serde(default)]
+    pub threads: Option<usize>,
+    /// Transcript message roles to index
+    #[serde(default = "default_include_roles")]
+    pub include_roles: Vec<String>,
+    /// Fail indexing runs that skipped any file or chunk
+    #[serde(default)]
+    pub strict: bool,
+    /// Where a full index lists the files it has finished, so an interrupted run
+    /// resumes (default: a file in the temp directory named after the store)
+    pub index_checkpoint: Option<PathBuf>,
+    /// JSON file caching query embeddings by model and query (enables `search --offline`)
+    pub query_cache: Option<PathBuf>,
+    /// JSON file caching whole search result pages for `result_cache_ttl_secs`
+    /// (`search --no-cache` bypasses it; indexing clears it)
+    #[serde(default)]
+    pub result_cache: Option<PathBuf>,
+    /// How long a cached result page is served
+    #[serde(default = "default_result_cache_ttl_secs")]
+    pub result_cache_ttl_secs: u64,
+    /// Generative model used by `search --rerank` to score candidates
+    #[serde(default)]
+    pub rerank_model: Option<String>,
+    /// Candidates fetched and scored when reranking (caps the model calls)
+    #[serde(default = "default_rerank_candidates")]
+    pub rerank_candidates: usize,
+    /// Age in days at which `search --recency-weight` halves a chunk's recency score
+    #[serde(default = "default_recency_half_life_days")]
+    pub recency_half_life_days: f64,
+    /// Allowed `source` labels; when set, config sources and `index-stdin --label` must use one
+    #[serde(default)]
+    pub source_labels: Vec<String>,
+    /// Extra stores queried together by `search --federated`
+    #[serde(default)]
+    pub federated_targets: Vec<SearchTarget>,
+    /// Suppress per-file progress output (also `--quiet`)
+    #[serde(default)]
+    pub quiet: bool,
     #[serde(default)]
     pub sources: Vec<Source>,
+    /// Directory the config was loaded from (not read from YAML)
+    #[serde(skip)]
+    pub config_dir: PathBuf,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Source {
     pub path: PathBuf,
     #[serde(rename = "type")]
-    pub source_type: String,
+    pub source_type: SourceType,
     pub source_label: Option<String>,
+    /// `YYYY-MM-DD` used when the filename has no date (`single_file` only)
+    pub source_date: Option<String>,
+    /// JSONL transcripts only grow: index-incremental indexes just the new lines
+    #[serde(default)]
+    pub append_only: bool,
+    /// Also index subdirectories, skipping hidden ones (`markdown_dir` only)
+    #[serde(default)]
+    pub recursive: bool,
+    /// Label each file by a directory it sits in; files at the top use the source label
+    #[serde(default)]
+    pub label_from: Option<LabelFrom>,
+}
+
+/// Which directory names a file's `source` label under `label_from`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LabelFrom {
+    /// The directory directly containing the file
+    Dirname,
+    /// The first directory below the source path
+    TopDir,
+}
+
+impl Source {
+    /// `source` stored on this source's chunks: `source_label`, else a per-type default.
+    pub fn label(&self) -> String {
+        self.source_label
+            .clone()
+            .unwrap_or_else(|| self.source_type.default_label().to_string())
+    }
+}
+
+/// One agent's memory in some database/schema, for federated search.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SearchTarget {
+    pub name: Option<String>,
+    pub db_url: String,
+    #[serde(default = "default_schema")]
+    pub schema: String,
+    /// Chunk table inside `schema`, for existing pgvector tables with another name
+    #[serde(default = "default_table")]
+    pub table: String,
+    #[serde(default = "default_embedding_column")]
+    pub embedding_column: String,
+    pub agent_id: String,
+}
+
+impl SearchTarget {
+    /// `schema.table` for use in SQL.
+    pub fn table_name(&self) -> String {
+        format!("{}.{}", self.schema, self.table)
+    }
+
+    /// `name`, or `schema/agent_id` when unnamed.
+    pub fn label(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| format!("{}/{}", self.schema, self.agent_id))
+    }
+}
+
+/// Unknown `type:` values are rejected by serde when the config is parsed,
+/// with an error listing the valid names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceType {
+    MarkdownDir,
+    SingleFile,
+    TranscriptDir,
+    /// An `.epub` file, or a directory of them
+    Epub,
+    /// A `.docx` file, or a directory of them
+    Docx,
+    /// A git work tree (or a directory inside one); its tracked `.md` files
+    Git,
+}
+
+impl SourceType {
+    fn default_label(self) -> &'static str {
+        match self {
+            SourceType::MarkdownDir => "daily_note",
+            SourceType::SingleFile => "single_file",
+            SourceType::TranscriptDir => "transcript",
+            SourceType::Epub => "epub",
+            SourceType::Docx => "docx",
+            SourceType::Git => "git",
+        }
+    }
+}
+
+/// Request and response shape of the embedding server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbedProvider {
+    /// `{"model", "prompt"}` → `{"embedding"}`, batching through `/api/embed`
+    #[default]
+    Ollama,
+    /// llama.cpp server: `{"content"}` → `{"embedding"}` (or `data[0].embedding`), one text per request
+    Llamacpp,
+    /// OpenAI-compatible API (OpenAI, Together, LiteLLM): `{"model", "input"}` → `{"data": [{"embedding"}]}`
+    Openai,
+}
+
+/// Statement protocol for chunk inserts and search queries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryMode {
+    /// Prepared when a probe statement survives being run twice, else simple
+    #[default]
+    Auto,
+    /// Values inlined into `simple_query` text; works through any pooler
+    Simple,
+    /// Parameterized, prepared statements; needs a direct or session-pooled connection
+    Prepared,
+}
+
+/// Unit of the markdown chunk budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkStrategy {
+    /// `max_chars` characters
+    #[default]
+    Chars,
+    /// `max_tokens` tokens, so CJK and English chunks fit the model alike
+    Tokens,
+}
+
+/// How embeddings are stored, for casting vector literals in SQL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VectorType {
+    /// 4-byte floats
+    #[default]
+    Vector,
+    /// 2-byte floats: half the storage and index size, slightly lower precision
+    Halfvec,
+}
+
+impl VectorType {
+    pub fn sql(self) -> &'static str {
+        match self {
+            VectorType::Vector => "vector",
+            VectorType::Halfvec => "halfvec",
+        }
+    }
+}
+
+/// A label with case, `_`, `-` and spaces ignored, so `dailyNote` and
+/// `daily_note` compare equal.
+pub fn label_key(label: &str) -> String {
+    label
+        .chars()
+        .filter(|c| !matches!(c, '_' | '-' | ' '))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn default_true() -> bool {
+    true
 }
 
 fn default_ollama_url() -> String {
@@ -36,8 +370,122 @@ fn default_schema() -> String {
     "agent_memory".to_string()
 }
 
+fn default_table() -> String {
+    "chunks".to_string()
+}
+
+fn default_embedding_column() -> String {
+    "embedding".to_string()
+}
+
+fn default_max_chars() -> usize {
+    800
+}
+
+fn default_max_tokens() -> usize {
+    256
+}
+
+fn default_embed_max_retries() -> u32 {
+    3
+}
+
+fn default_embed_retry_base_ms() -> u64 {
+    200
+}
+
+fn default_embed_batch_size() -> usize {
+    16
+}
+
+fn default_embed_concurrency() -> usize {
+    4
+}
+
+fn default_rerank_candidates() -> usize {
+    20
+}
+
+fn default_result_cache_ttl_secs() -> u64 {
+    60
+}
+
+fn default_recency_half_life_days() -> f64 {
+    30.0
+}
+
+fn default_frontmatter_keys() -> Vec<String> {
+    vec!["tags".to_string(), "title".to_string()]
+}
+
+fn default_include_roles() -> Vec<String> {
+    vec!["user".to_string(), "assistant".to_string()]
+}
+
 impl Config {
-    pub fn load(path: Option<&str>) -> Result<Self> {
+    /// `schema.table` for use in SQL.
+    pub fn table_name(&self) -> String {
+        format!("{}.{}", self.schema, self.table)
+    }
+
+    /// With `source_labels` set, reject any other label, suggesting the one it
+    /// most likely misspells.
+    pub fn check_label(&self, label: &str) -> Result<()> {
+        if self.source_labels.is_empty() || self.source_labels.iter().any(|l| l == label) {
+            return Ok(());
+        }
+        let hint = match self
+            .source_labels
+            .iter()
+            .find(|l| label_key(l) == label_key(label))
+        {
+            Some(close) => format!(" (did you mean '{}'?)", close),
+            None => String::new(),
+        };
+        anyhow::bail!(
+            "Source label '{}' is not in source_labels [{}]{}",
+            label,
+            self.source_labels.join(", "),
+            hint
+        )
+    }
+
+    /// `index_checkpoint`, else `agent-memory-<schema>-<table>-<agent_id>.checkpoint`
+    /// in the temp directory.
+    pub fn checkpoint_path(&self) -> PathBuf {
+        self.index_checkpoint.clone().unwrap_or_else(|| {
+            let name: String = format!("{}-{}-{}", self.schema, self.table, self.agent_id)
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || c == '-' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+            std::env::temp_dir().join(format!("agent-memory-{}.checkpoint", name))
+        })
+    }
+
+    /// Connection string for read-only commands: `read_db_url`, else `db_url`.
+    pub fn read_url(&self) -> &str {
+        self.read_db_url.as_deref().unwrap_or(&self.db_url)
+    }
+
+    /// Read the config, after loading `.env` into the process environment and
+    /// expanding `${VAR}` references in the file.
+    pub fn load(path: Option<&str>, env_file: Option<&str>) -> Result<Self> {
+        match env_file {
+            Some(p) => {
+                dotenvy::from_path(p)
+                    .map_err(|e| anyhow::anyhow!("Cannot load env file {}: {}", p, e))?;
+            }
+            None => {
+                load_dotenv(Path::new(".env"))?;
+            }
+        }
+
         let config_path = if let Some(p) = path {
             PathBuf::from(p)
         } else if let Ok(p) = std::env::var("AGENT_MEMORY_CONFIG") {
@@ -46,9 +494,204 @@ impl Config {
             PathBuf::from("config.yaml")
         };
 
-        let contents = std::fs::read_to_string(&config_path)
-            .map_err(|e| anyhow::anyhow!("Cannot read config at {}: {}", config_path.display(), e))?;
-        let config: Config = serde_yaml::from_str(&contents)?;
+        if env_file.is_none() {
+            if let Some(dir) = config_path
+                .canonicalize()
+                .ok()
+                .as_deref()
+                .and_then(Path::parent)
+            {
+                load_dotenv(&dir.join(".env"))?;
+            }
+        }
+
+        let contents = std::fs::read_to_string(&config_path).map_err(|e| {
+            anyhow::anyhow!("Cannot read config at {}: {}", config_path.display(), e)
+        })?;
+        let mut value: serde_yaml::Value = serde_yaml::from_str(&contents)
+            .map_err(|e| anyhow::anyhow!("Invalid config at {}: {}", config_path.display(), e))?;
+        interpolate_env(&mut value)
+            .map_err(|e| anyhow::anyhow!("Invalid config at {}: {}", config_path.display(), e))?;
+        let mut config: Config = serde_yaml::from_value(value)
+            .map_err(|e| anyhow::anyhow!("Invalid config at {}: {}", config_path.display(), e))?;
+        if let Some(secret) = &config.db_url_file {
+            let url = std::fs::read_to_string(secret).map_err(|e| {
+                anyhow::anyhow!("Cannot read db_url_file at {}: {}", secret.display(), e)
+            })?;
+            if url.trim().is_empty() {
+                anyhow::bail!("db_url_file at {} is empty", secret.display());
+            }
+            config.db_url = url.trim().to_string();
+        } else if config.db_url.trim().is_empty() {
+            anyhow::bail!(
+                "Invalid config at {}: set db_url, or db_url_file pointing at a file with the connection string",
+                config_path.display()
+            );
+        }
+        let tls = |url: &str| {
+            crate::tls::with_defaults(url, config.ssl, config.root_cert_path.as_deref())
+        };
+        config.db_url = tls(&config.db_url);
+        config.read_db_url = config.read_db_url.as_deref().map(tls);
+        for target in &mut config.federated_targets {
+            target.db_url = tls(&target.db_url);
+        }
+        if config.embed_provider == EmbedProvider::Openai && config.embed_api_key.is_none() {
+            config.embed_api_key = std::env::var("OPENAI_API_KEY")
+                .ok()
+                .filter(|k| !k.is_empty());
+        }
+        if let (Some(prepared), QueryMode::Auto) =
+            (config.use_prepared_statements, config.query_mode)
+        {
+            config.query_mode = if prepared {
+                QueryMode::Prepared
+            } else {
+                QueryMode::Simple
+            };
+        }
+        config.config_dir = config_path
+            .canonicalize()
+            .ok()
+            .and_then(|p| p.parent().map(|d| d.to_path_buf()))
+            .unwrap_or_default();
+        // Table and column names are spliced into SQL unquoted
+        let targets = std::iter::once((&config.schema, &config.table, &config.embedding_column))
+            .chain(
+                config
+                    .federated_targets
+                    .iter()
+                    .map(|t| (&t.schema, &t.table, &t.embedding_column)),
+            );
+        for (schema, table, column) in targets {
+            for (key, name) in [
+                ("schema", schema),
+                ("table", table),
+                ("embedding_column", column),
+            ] {
+                check_identifier(name).map_err(|e| {
+                    anyhow::anyhow!("Invalid config at {}: {} {}", config_path.display(), key, e)
+                })?;
+            }
+        }
+        for source in &config.sources {
+            if let Some(date) = &source.source_date {
+                if source.source_type != SourceType::SingleFile {
+                    anyhow::bail!(
+                        "Invalid config at {}: source_date is only supported on single_file sources ({})",
+                        config_path.display(),
+                        source.path.display()
+                    );
+                }
+                check_date(date).map_err(|e| {
+                    anyhow::anyhow!("Invalid config at {}: {}", config_path.display(), e)
+                })?;
+            }
+            if source.append_only
+                && !matches!(
+                    source.source_type,
+                    SourceType::TranscriptDir | SourceType::SingleFile
+                )
+            {
+                anyhow::bail!(
+                    "Invalid config at {}: append_only is only supported on transcript_dir and single_file sources ({})",
+                    config_path.display(),
+                    source.path.display()
+                );
+            }
+            if source.recursive && source.source_type != SourceType::MarkdownDir {
+                anyhow::bail!(
+                    "Invalid config at {}: recursive is only supported on markdown_dir sources ({})",
+                    config_path.display(),
+                    source.path.display()
+                );
+            }
+            if source.label_from.is_some()
+                && !matches!(
+                    source.source_type,
+                    SourceType::MarkdownDir | SourceType::Git
+                )
+            {
+                anyhow::bail!(
+                    "Invalid config at {}: label_from is only supported on markdown_dir and git sources ({})",
+                    config_path.display(),
+                    source.path.display()
+                );
+            }
+            config.check_label(&source.label()).map_err(|e| {
+                anyhow::anyhow!(
+                    "Invalid config at {} ({}): {}",
+                    config_path.display(),
+                    source.path.display(),
+                    e
+                )
+            })?;
+        }
         Ok(config)
     }
 }
+
+/// Load `path` if it exists. Variables already set in the process win.
+fn load_dotenv(path: &Path) -> Result<()> {
+    if !path.is_file() {
+        return Ok(());
+    }
+    dotenvy::from_path(path)
+        .map_err(|e| anyhow::anyhow!("Cannot load env file {}: {}", path.display(), e))
+}
+
+/// Replace each `${VAR}` in string values with the variable's value; unset
+/// variables are an error.
+fn interpolate_env(value: &mut serde_yaml::Value) -> Result<()> {
+    match value {
+        serde_yaml::Value::String(s) => {
+            let re = regex::Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}")?;
+            let mut missing = None;
+            let expanded = re.replace_all(s, |caps: &regex::Captures| {
+                std::env::var(&caps[1]).unwrap_or_else(|_| {
+                    missing.get_or_insert_with(|| caps[1].to_string());
+                    String::new()
+                })
+            });
+            if let Some(name) = missing {
+                anyhow::bail!("environment variable {} is not set", name);
+            }
+            *s = expanded.into_owned();
+        }
+        serde_yaml::Value::Sequence(items) => {
+            for item in items {
+                interpolate_env(item)?;
+            }
+        }
+        serde_yaml::Value::Mapping(map) => {
+            for (_, v) in map.iter_mut() {
+                interpolate_env(v)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Accept only a lowercase SQL identifier that needs no quoting.
+fn check_identifier(name: &str) -> Result<()> {
+    let re = regex::Regex::new(r"^[a-z_][a-z0-9_]{0,62}$")?;
+    if re.is_match(name) {
+        Ok(())
+    } else {
+        anyhow::bail!(
+            "must be a lowercase identifier (letters, digits, _), got {:?}",
+            name
+        )
+    }
+}
+
+/// Accept only a plain `YYYY-MM-DD` date.
+pub fn check_date(date: &str) -> Result<()> {
+    let re = regex::Regex::new(r"^\d{4}-\d{2}-\d{2}$")?;
+    if re.is_match(date) {
+        Ok(())
+    } else {
+        anyhow::bail!("source_date must be YYYY-MM-DD, got {:?}", date)
+    }
+}
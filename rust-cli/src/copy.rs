@@ -0,0 +1,79 @@
+//! Bulk loading through binary `COPY ... FROM STDIN` (`bulk_copy`, or
+//! `index --copy`): one stream per file instead of one INSERT per chunk.
+//! Unlike the rest of the CLI this needs the extended query protocol (for
+//! COPY and the column-type probe), which transaction-mode poolers such as
+//! Supabase's may reject; load through a direct connection.
+
+use anyhow::Result;
+use bytes::BytesMut;
+use std::error::Error;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio_postgres::binary_copy::BinaryCopyInWriter;
+use tokio_postgres::types::{to_sql_checked, IsNull, ToSql, Type};
+use tokio_postgres::Client;
+
+/// One column value of a COPY row.
+pub type Value = Box<dyn ToSql + Sync + Send>;
+
+/// Days from 1970-01-01 to 2000-01-01, Postgres' binary date epoch.
+const PG_EPOCH_DAYS: i64 = 10_957;
+
+/// Stream `rows` into `columns` of `table`, returning how many were written.
+pub async fn copy_rows(
+    client: &Client,
+    table: &str,
+    columns: &[String],
+    rows: &[Vec<Value>],
+) -> Result<u64> {
+    let list = columns.join(", ");
+    // Binary COPY needs each column's type up front, pgvector's OID included
+    let probe = client
+        .prepare(&format!("SELECT {} FROM {} LIMIT 0", list, table))
+        .await?;
+    let types: Vec<Type> = probe.columns().iter().map(|c| c.type_().clone()).collect();
+
+    let sink = client
+        .copy_in(&format!(
+            "COPY {} ({}) FROM STDIN (FORMAT binary)",
+            table, list
+        ))
+        .await?;
+    let writer = BinaryCopyInWriter::new(sink, &types);
+    futures::pin_mut!(writer);
+    for row in rows {
+        let values: Vec<&(dyn ToSql + Sync)> = row
+            .iter()
+            .map(|v| v.as_ref() as &(dyn ToSql + Sync))
+            .collect();
+        writer.as_mut().write(&values).await?;
+    }
+    Ok(writer.finish().await?)
+}
+
+/// `source_mtime` (µs since the Unix epoch) as a `timestamptz` value.
+pub fn timestamp(us: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_micros(us.max(0) as u64)
+}
+
+/// A `YYYY-MM-DD` string sent as a binary `date`.
+#[derive(Debug)]
+pub struct Date(pub String);
+
+impl ToSql for Date {
+    fn to_sql(
+        &self,
+        _: &Type,
+        out: &mut BytesMut,
+    ) -> std::result::Result<IsNull, Box<dyn Error + Sync + Send>> {
+        let days = crate::rerank::parse_days(&self.0)
+            .ok_or_else(|| format!("invalid date {:?}", self.0))?;
+        out.extend_from_slice(&((days - PG_EPOCH_DAYS) as i32).to_be_bytes());
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::DATE
+    }
+
+    to_sql_checked!();
+}
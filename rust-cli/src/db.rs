@@ -0,0 +1,328 @@
+//! Database connections. Clients come from one deadpool-postgres pool per
+//! database URL, created on first use and kept for the life of the process: a
+//! finished client goes back to its pool and the next caller reuses it instead
+//! of connecting again, which is what lets the servers (`serve`, `serve-http`)
+//! answer request after request on a handful of connections. A connection
+//! that fails records why, and `explain` attaches that cause to the
+//! "connection closed" error its caller would otherwise see alone.
+
+use anyhow::Result;
+use deadpool_postgres::{Manager, ManagerConfig, Object, RecyclingMethod, Runtime, Timeouts};
+use futures::future::BoxFuture;
+use postgres_native_tls::MakeTlsConnector;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio_postgres::Client;
+
+use crate::config::QueryMode;
+use crate::telemetry;
+use crate::tls::TlsSettings;
+
+/// Connections open at once per pool; callers beyond that wait for one to be
+/// returned.
+pub const MAX_CONNECTIONS: usize = 16;
+
+static POOLS: OnceLock<Mutex<HashMap<String, Pool>>> = OnceLock::new();
+
+/// Clients for one database URL. Cheap to clone; clones share the clients.
+#[derive(Clone)]
+pub struct Pool {
+    pool: deadpool_postgres::Pool,
+    /// Why the last connection that failed did, until `explain` reports it.
+    failure: Arc<Mutex<Option<String>>>,
+    /// What `query_mode: auto` settled on for this database, once probed.
+    prepared: Arc<Mutex<Option<bool>>>,
+}
+
+/// Opens connections for the pool, recording why one fails.
+struct Connector {
+    tls: MakeTlsConnector,
+    failure: Arc<Mutex<Option<String>>>,
+}
+
+impl deadpool_postgres::Connect for Connector {
+    fn connect(
+        &self,
+        pg_config: &tokio_postgres::Config,
+    ) -> BoxFuture<'_, std::result::Result<(Client, JoinHandle<()>), tokio_postgres::Error>> {
+        let (pg_config, tls, failure) = (pg_config.clone(), self.tls.clone(), self.failure.clone());
+        Box::pin(async move {
+            let (client, connection) = pg_config.connect(tls).await?;
+            let task = tokio::spawn(async move {
+                if let Err(e) = connection.await {
+                    telemetry::record_db_error();
+                    *lock(&failure) = Some(e.to_string());
+                }
+            });
+            Ok((client, task))
+        })
+    }
+}
+
+impl Pool {
+    /// The pool for `db_url`, created on first use. TLS is set up here, once
+    /// per pool, from the string's `sslmode` and `sslrootcert`.
+    pub fn for_url(db_url: &str) -> Result<Pool> {
+        let mut pools = lock(POOLS.get_or_init(Default::default));
+        if let Some(pool) = pools.get(db_url) {
+            return Ok(pool.clone());
+        }
+        let tls = TlsSettings::parse(db_url)?;
+        let mut config: tokio_postgres::Config = tls.db_url.parse()?;
+        config.ssl_mode(tls.pg_ssl_mode());
+        let failure = Arc::new(Mutex::new(None));
+        let connector = Connector {
+            tls: tls.connector()?,
+            failure: failure.clone(),
+        };
+        // Verified: an idle connection answers a test query before it is
+        // handed out again, so one the server dropped is replaced, not returned
+        let manager = Manager::from_connect(
+            config,
+            connector,
+            ManagerConfig {
+                recycling_method: RecyclingMethod::Verified,
+            },
+        );
+        let pool = Pool {
+            pool: deadpool_postgres::Pool::builder(manager)
+                .max_size(MAX_CONNECTIONS)
+                .runtime(Runtime::Tokio1)
+                .build()?,
+            failure,
+            prepared: Default::default(),
+        };
+        pools.insert(db_url.to_string(), pool.clone());
+        Ok(pool)
+    }
+
+    /// An idle client, or a new connection when none is left and the pool has
+    /// room, giving up on connecting after `timeout_secs` when set.
+    pub async fn get(&self, timeout_secs: Option<u64>) -> Result<Object> {
+        let timeouts = Timeouts {
+            create: timeout_secs.map(Duration::from_secs),
+            ..Timeouts::default()
+        };
+        self.pool.timeout_get(&timeouts).await.map_err(|e| match e {
+            deadpool_postgres::PoolError::Timeout(_) => {
+                telemetry::record_db_error();
+                anyhow::anyhow!(
+                    "Could not connect to the database within {}s (query_timeout_secs); is it reachable?",
+                    timeout_secs.unwrap_or_default()
+                )
+            }
+            // Kept as the tokio-postgres error so `explain` can recognize it
+            deadpool_postgres::PoolError::Backend(e) => e.into(),
+            e => anyhow::anyhow!("Database pool error: {}", e),
+        })
+    }
+
+    /// Whether to run chunk inserts and search queries as prepared statements
+    /// on this pool's connections. `auto` prepares a probe on `client` and
+    /// runs it twice: behind a transaction-mode pooler (Supabase, PgBouncer)
+    /// the second run can land on a backend that never saw the statement, so
+    /// `simple_query` is kept there. The answer is kept for the pool's life,
+    /// so only its first caller pays for the probe.
+    pub async fn use_prepared(&self, client: &Client, mode: QueryMode) -> bool {
+        match mode {
+            QueryMode::Simple => false,
+            QueryMode::Prepared => true,
+            QueryMode::Auto => {
+                if let Some(prepared) = *lock(&self.prepared) {
+                    return prepared;
+                }
+                let probe = async {
+                    let statement = client.prepare("SELECT 1").await?;
+                    client.query(&statement, &[]).await?;
+                    client.query(&statement, &[]).await
+                };
+                let prepared = probe.await.is_ok();
+                // A connection lost mid-probe says nothing about the server
+                if !client.is_closed() {
+                    *lock(&self.prepared) = Some(prepared);
+                }
+                prepared
+            }
+        }
+    }
+}
+
+/// A client for `db_url` from its pool.
+pub async fn connect(db_url: &str, timeout_secs: Option<u64>) -> Result<Object> {
+    Pool::for_url(db_url)?.get(timeout_secs).await
+}
+
+/// `Pool::use_prepared` for the pool of `db_url`, which `client` came from.
+pub async fn use_prepared(db_url: &str, client: &Client, mode: QueryMode) -> Result<bool> {
+    Ok(Pool::for_url(db_url)?.use_prepared(client, mode).await)
+}
+
+/// `e` with the recorded cause attached when it came from a connection that
+/// had closed; other errors are returned unchanged.
+pub fn explain(e: anyhow::Error) -> anyhow::Error {
+    let closed = e.chain().any(|c| {
+        c.downcast_ref::<tokio_postgres::Error>()
+            .is_some_and(|pg| pg.is_closed())
+    });
+    if !closed {
+        return e;
+    }
+    let Some(pools) = POOLS.get() else {
+        return e;
+    };
+    let cause = lock(pools)
+        .values()
+        .find_map(|pool| lock(&pool.failure).take());
+    match cause {
+        // Often it is the bare "connection closed" the caller already has
+        Some(cause) if !e.chain().any(|c| c.to_string() == cause) => {
+            e.context(format!("Database connection lost: {}", cause))
+        }
+        _ => e,
+    }
+}
+
+/// A poisoned lock only means another task panicked while holding it; the
+/// value itself is still usable.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    async fn backend_pid(client: &Client) -> i32 {
+        client
+            .query_one("SELECT pg_backend_pid()", &[])
+            .await
+            .unwrap()
+            .get(0)
+    }
+
+    #[tokio::test]
+    async fn sequential_operations_reuse_one_connection() {
+        let Some(url) = testing::db_url() else { return };
+        let pool = Pool::for_url(&testing::own_pool_url(&url, "pool_sequential")).unwrap();
+
+        let mut pids = Vec::new();
+        for i in 0..5i32 {
+            let client = pool.get(Some(10)).await.unwrap();
+            let row = client.query_one("SELECT $1::int + 1", &[&i]).await.unwrap();
+            assert_eq!(row.get::<_, i32>(0), i + 1);
+            pids.push(backend_pid(&client).await);
+        }
+        assert!(pids.iter().all(|p| *p == pids[0]), "{:?}", pids);
+        assert_eq!(pool.pool.status().size, 1);
+    }
+
+    #[tokio::test]
+    async fn concurrent_borrowers_share_at_most_max_connections() {
+        let Some(url) = testing::db_url() else { return };
+        let pool = Pool::for_url(&testing::own_pool_url(&url, "pool_capped")).unwrap();
+
+        let tasks: Vec<_> = (0..MAX_CONNECTIONS * 3)
+            .map(|_| {
+                let pool = pool.clone();
+                tokio::spawn(async move {
+                    let client = pool.get(Some(10)).await.unwrap();
+                    client.simple_query("SELECT pg_sleep(0.05)").await.unwrap();
+                    backend_pid(&client).await
+                })
+            })
+            .collect();
+        let mut pids = Vec::new();
+        for task in tasks {
+            pids.push(task.await.unwrap());
+        }
+        pids.sort();
+        pids.dedup();
+        assert!(pids.len() <= MAX_CONNECTIONS, "{} connections", pids.len());
+        assert!(pool.pool.status().size <= MAX_CONNECTIONS);
+    }
+
+    #[tokio::test]
+    async fn failed_timed_read_leaves_the_client_usable() {
+        let Some(url) = testing::db_url() else { return };
+        let pool = Pool::for_url(&testing::own_pool_url(&url, "pool_failed_read")).unwrap();
+
+        let client = pool.get(Some(10)).await.unwrap();
+        assert!(crate::search::read_query(&client, "SELECT 1/0", Some(5))
+            .await
+            .is_err());
+        drop(client);
+
+        // The same connection, no longer inside the aborted transaction
+        let client = pool.get(Some(10)).await.unwrap();
+        assert_eq!(pool.pool.status().size, 1);
+        client.simple_query("SELECT 1").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn recycling_replaces_a_connection_the_server_closed() {
+        let Some(url) = testing::db_url() else { return };
+        let pool = Pool::for_url(&testing::own_pool_url(&url, "pool_recycled")).unwrap();
+        let admin = Pool::for_url(&testing::own_pool_url(&url, "pool_recycled_admin")).unwrap();
+
+        let first = backend_pid(&pool.get(Some(10)).await.unwrap()).await;
+        let killer = admin.get(Some(10)).await.unwrap();
+        killer
+            .execute("SELECT pg_terminate_backend($1)", &[&first])
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let client = pool.get(Some(10)).await.unwrap();
+        assert_ne!(backend_pid(&client).await, first);
+    }
+
+    #[tokio::test]
+    async fn auto_query_mode_is_probed_once_per_pool() {
+        let Some(url) = testing::db_url() else { return };
+        let pool = Pool::for_url(&testing::own_pool_url(&url, "pool_query_mode")).unwrap();
+        let admin = Pool::for_url(&testing::own_pool_url(&url, "pool_query_mode_admin")).unwrap();
+
+        let client = pool.get(Some(10)).await.unwrap();
+        assert!(!pool.use_prepared(&client, QueryMode::Simple).await);
+        assert_eq!(*lock(&pool.prepared), None);
+        assert!(pool.use_prepared(&client, QueryMode::Auto).await);
+        assert_eq!(*lock(&pool.prepared), Some(true));
+
+        // A probe would fail on this connection now; the settled answer stands
+        let pid = backend_pid(&client).await;
+        let killer = admin.get(Some(10)).await.unwrap();
+        killer
+            .execute("SELECT pg_terminate_backend($1)", &[&pid])
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(client.simple_query("SELECT 1").await.is_err());
+        assert!(pool.use_prepared(&client, QueryMode::Auto).await);
+
+        // Nor is a failure on a lost connection remembered
+        let fresh = Pool::for_url(&testing::own_pool_url(&url, "pool_query_mode_fresh")).unwrap();
+        assert!(!fresh.use_prepared(&client, QueryMode::Auto).await);
+        assert_eq!(*lock(&fresh.prepared), None);
+    }
+
+    #[tokio::test]
+    async fn unreachable_server_is_an_error() {
+        let Err(err) = connect(
+            "host=127.0.0.1 port=1 user=nobody connect_timeout=2",
+            Some(5),
+        )
+        .await
+        else {
+            panic!("nothing listens on port 1");
+        };
+        assert!(
+            err.chain()
+                .any(|c| c.downcast_ref::<tokio_postgres::Error>().is_some()),
+            "{:#}",
+            err
+        );
+    }
+}
@@ -0,0 +1,286 @@
+//! `agent-memory doctor`: walk through everything a working setup needs and
+//! say what to fix for each check that fails.
+
+use anyhow::Result;
+use tokio_postgres::{Client, SimpleQueryMessage};
+
+use crate::config::{Config, EmbedProvider};
+use crate::{db, embed, index};
+
+struct Checks {
+    failed: usize,
+}
+
+impl Checks {
+    fn pass(&self, what: &str) {
+        println!("  ✅ {}", what);
+    }
+
+    /// Worth fixing, but doesn't stop anything from working.
+    fn warn(&self, what: &str, hint: &str) {
+        println!("  ⚠️  {}", what);
+        println!("     → {}", hint);
+    }
+
+    fn fail(&mut self, what: &str, hint: &str) {
+        self.failed += 1;
+        println!("  ❌ {}", what);
+        println!("     → {}", hint);
+    }
+}
+
+pub async fn run(config_path: Option<&str>, env_file: Option<&str>) -> Result<()> {
+    println!("🐑⚡ Agent Memory — Doctor\n");
+    let mut checks = Checks { failed: 0 };
+
+    let config = match Config::load(config_path, env_file) {
+        Ok(c) => {
+            checks.pass("Config parses");
+            c
+        }
+        Err(e) => {
+            checks.fail(
+                &format!("Config: {}", e),
+                "Pass --config <path> or set AGENT_MEMORY_CONFIG; see config.example.yaml",
+            );
+            return finish(&checks);
+        }
+    };
+
+    check_sources(&config, &mut checks);
+    let dimension = check_model(&config, &mut checks).await;
+    check_database(&config, dimension, &mut checks).await;
+
+    finish(&checks)
+}
+
+fn finish(checks: &Checks) -> Result<()> {
+    println!();
+    if checks.failed > 0 {
+        anyhow::bail!("{} check(s) failed", checks.failed);
+    }
+    println!("All checks passed.");
+    Ok(())
+}
+
+fn check_sources(config: &Config, checks: &mut Checks) {
+    if config.sources.is_empty() {
+        checks.fail(
+            "No sources configured",
+            "Add at least one entry under `sources:`",
+        );
+        return;
+    }
+    for source in &config.sources {
+        let path = source.path.display();
+        let readable = if source.path.is_dir() {
+            std::fs::read_dir(&source.path).is_ok()
+        } else {
+            std::fs::File::open(&source.path).is_ok()
+        };
+        if readable {
+            checks.pass(&format!("Source {} is readable", path));
+        } else if source.path.exists() {
+            checks.fail(
+                &format!("Source {} is not readable", path),
+                "Check the file permissions",
+            );
+        } else {
+            checks.fail(
+                &format!("Source {} does not exist", path),
+                "Fix the path or remove the source",
+            );
+        }
+    }
+
+    let files = index::indexable_file_count(config);
+    if files > 0 {
+        checks.pass(&format!("{} indexable file(s) found", files));
+    } else {
+        checks.fail(
+            "No indexable files in any source",
+            "markdown_dir needs *.md files, transcript_dir *.jsonl/*.json, epub/docx *.epub/*.docx",
+        );
+    }
+}
+
+/// Embed a probe string; returns the model's dimension when that works,
+/// warning when it isn't the configured `model_dimension`.
+async fn check_model(config: &Config, checks: &mut Checks) -> Option<usize> {
+    match embed::get_embedding(config, "dimension probe").await {
+        Ok(v) => {
+            checks.pass(&format!(
+                "Model {} answers at {} ({} dims)",
+                config.model,
+                config.ollama_url,
+                v.len()
+            ));
+            match config.model_dimension {
+                Some(configured) if configured != v.len() => checks.warn(
+                    &format!(
+                        "model_dimension is {} but model {} produces {} dims",
+                        configured,
+                        config.model,
+                        v.len()
+                    ),
+                    &format!(
+                        "Set model_dimension: {} (or remove it); indexing stops at the mismatch",
+                        v.len()
+                    ),
+                ),
+                _ => {}
+            }
+            Some(v.len())
+        }
+        Err(e) => {
+            let status = e.downcast_ref::<reqwest::Error>().and_then(|e| e.status());
+            let hint = match status {
+                Some(s) if s == reqwest::StatusCode::NOT_FOUND && config.embed_provider == EmbedProvider::Llamacpp => {
+                    "Point `ollama_url` at the server's /embedding path and start it with --embedding".to_string()
+                }
+                Some(s) if s == reqwest::StatusCode::NOT_FOUND && config.embed_provider == EmbedProvider::Openai => {
+                    "Point `ollama_url` at the API's /v1/embeddings and check `model`".to_string()
+                }
+                Some(s) if s == reqwest::StatusCode::UNAUTHORIZED || s == reqwest::StatusCode::FORBIDDEN => {
+                    "Set `embed_api_key` (or OPENAI_API_KEY) to a valid key".to_string()
+                }
+                Some(s) if s == reqwest::StatusCode::NOT_FOUND => format!("Run `ollama pull {}`", config.model),
+                Some(_) => "Check the Ollama server logs".to_string(),
+                None => "Start Ollama (`ollama serve`) or fix `ollama_url`".to_string(),
+            };
+            checks.fail(&format!("Embedding model {}: {}", config.model, e), &hint);
+            config.model_dimension
+        }
+    }
+}
+
+async fn check_database(config: &Config, model_dim: Option<usize>, checks: &mut Checks) {
+    let client = match db::connect(&config.db_url, None).await {
+        Ok(client) => {
+            checks.pass("Database reachable");
+            client
+        }
+        Err(e) => {
+            checks.fail(
+                &format!("Database: {}", e),
+                "Check db_url (host, port, credentials) and that PostgreSQL is running",
+            );
+            return;
+        }
+    };
+
+    let schema_exists = first_value(
+        &client,
+        &format!(
+            "SELECT 1 FROM information_schema.schemata WHERE schema_name = '{}'",
+            config.schema
+        ),
+    )
+    .await
+    .is_some();
+    if !schema_exists {
+        checks.fail(
+            &format!("Schema {} is missing", config.schema),
+            "Run db/001_init.sql (and set `schema` if you renamed it)",
+        );
+        return;
+    }
+    checks.pass(&format!("Schema {} exists", config.schema));
+
+    let column_type = first_value(
+        &client,
+        &format!(
+            "SELECT format_type(a.atttypid, a.atttypmod) FROM pg_attribute a
+             WHERE a.attrelid = to_regclass('{}') AND a.attname = '{}' AND NOT a.attisdropped",
+            config.table_name(),
+            config.embedding_column
+        ),
+    )
+    .await;
+    let Some(column_type) = column_type else {
+        let table_exists = first_value(
+            &client,
+            &format!("SELECT to_regclass('{}')", config.table_name()),
+        )
+        .await
+        .is_some();
+        if table_exists {
+            checks.fail(
+                &format!(
+                    "Table {} has no {} column",
+                    config.table_name(),
+                    config.embedding_column
+                ),
+                "Point `table` at a table created by db/001_init.sql, or set `embedding_column`",
+            );
+        } else {
+            checks.fail(
+                &format!("Table {} is missing", config.table_name()),
+                "Run db/001_init.sql, or set `table` to your chunk table",
+            );
+        }
+        return;
+    };
+    checks.pass(&format!("Table {} exists", config.table_name()));
+
+    let vector_type = config.vector_type.sql();
+    let table_dim = column_type
+        .strip_prefix(vector_type)
+        .and_then(|s| s.strip_prefix('('))
+        .and_then(|s| s.strip_suffix(')'))
+        .and_then(|s| s.parse::<usize>().ok());
+    match (table_dim, model_dim) {
+        (Some(t), Some(m)) if t == m => checks.pass(&format!(
+            "Embedding column is {}({}), matching the model",
+            vector_type, t
+        )),
+        (Some(t), Some(m)) => checks.fail(
+            &format!(
+                "Embedding column is {}({}) but the model produces {} dims",
+                vector_type, t, m
+            ),
+            "Use a model with the table's dimension, or recreate the column and re-index",
+        ),
+        (Some(t), None) => checks.pass(&format!(
+            "Embedding column is {}({}) (model not checked)",
+            vector_type, t
+        )),
+        (None, _) if column_type == vector_type => checks.pass(&format!(
+            "Embedding column is {} (any dimension)",
+            vector_type
+        )),
+        (None, _) => checks.fail(
+            &format!(
+                "Embedding column has type {} but vector_type is {}",
+                column_type, vector_type
+            ),
+            "Set vector_type to match the column, or install pgvector and run db/001_init.sql",
+        ),
+    }
+
+    let vector_index = first_value(
+        &client,
+        &format!(
+            "SELECT indexname FROM pg_indexes WHERE schemaname = '{}' AND tablename = '{}'
+             AND (indexdef ILIKE '%USING ivfflat%' OR indexdef ILIKE '%USING hnsw%')",
+            config.schema, config.table
+        ),
+    )
+    .await;
+    match vector_index {
+        Some(name) => checks.pass(&format!("Vector index {} present", name)),
+        None => checks.warn(
+            "No vector index on the embedding column",
+            "Searches fall back to a full scan; create the ivfflat index from db/001_init.sql after indexing",
+        ),
+    }
+}
+
+/// First column of the first row, or `None` for no rows, NULL or a query error.
+async fn first_value(client: &Client, sql: &str) -> Option<String> {
+    let msgs = client.simple_query(sql).await.ok()?;
+    msgs.iter().find_map(|m| match m {
+        SimpleQueryMessage::Row(row) => row.get(0).map(|s| s.to_string()),
+        _ => None,
+    })
+}
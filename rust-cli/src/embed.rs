@@ -1,5 +1,10 @@
 use anyhow::Result;
+use futures::stream::{self, FuturesUnordered, StreamExt};
 use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config::{Config, EmbedProvider};
+use crate::telemetry;
 
 #[derive(Serialize)]
 struct EmbedRequest {
@@ -12,17 +17,654 @@ struct EmbedResponse {
     embedding: Vec<f64>,
 }
 
-pub async fn get_embedding(ollama_url: &str, model: &str, text: &str) -> Result<Vec<f64>> {
+#[derive(Serialize)]
+struct BatchEmbedRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct BatchEmbedResponse {
+    embeddings: Vec<Vec<f64>>,
+}
+
+#[derive(Deserialize)]
+struct OpenaiEmbedResponse {
+    data: Vec<OpenaiEmbedding>,
+}
+
+#[derive(Deserialize)]
+struct OpenaiEmbedding {
+    #[serde(default)]
+    index: usize,
+    embedding: Vec<f64>,
+}
+
+/// Embed one text with `config`'s provider, URL and model.
+pub async fn get_embedding(config: &Config, text: &str) -> Result<Vec<f64>> {
+    let started = Instant::now();
     let client = reqwest::Client::new();
-    let resp = client
-        .post(ollama_url)
-        .json(&EmbedRequest {
-            model: model.to_string(),
-            prompt: text.to_string(),
-        })
-        .send()
+    match config.embed_provider {
+        EmbedProvider::Llamacpp => {
+            return llamacpp_embedding(config, &client, &config.ollama_url, text).await
+        }
+        EmbedProvider::Openai => {
+            let texts = [text.to_string()];
+            let mut vectors =
+                openai_embeddings(config, &client, &config.ollama_url, &texts).await?;
+            return Ok(vectors.remove(0));
+        }
+        EmbedProvider::Ollama => {}
+    }
+    let request = client.post(&config.ollama_url).json(&EmbedRequest {
+        model: config.model.clone(),
+        prompt: text.to_string(),
+    });
+    let resp = send(config, request).await?.json::<EmbedResponse>().await?;
+    telemetry::record_embed(started);
+    Ok(resp.embedding)
+}
+
+/// Embed many texts through Ollama's `/api/embed` (or an OpenAI-compatible
+/// `/v1/embeddings`, which takes a list too), `batch_size` at a time with up
+/// to `embed_concurrency` requests in flight. Output order matches `texts`.
+/// A batch the server rejects is split in half until single items remain; a
+/// single item that is still rejected comes back as `None` with a warning, so
+/// one bad chunk doesn't sink the rest. Servers without `/api/embed`, URLs
+/// that aren't Ollama's, and llama.cpp get one request per text instead.
+pub async fn get_embeddings_batch(
+    config: &Config,
+    texts: &[String],
+    batch_size: usize,
+) -> Result<Vec<Option<Vec<f64>>>> {
+    let batch_size = batch_size.max(1);
+    let concurrency = config.embed_concurrency.max(1);
+    let client = reqwest::Client::new();
+    let mut out: Vec<Option<Vec<f64>>> = vec![None; texts.len()];
+
+    let provider = config.embed_provider;
+    let batch_url = match provider {
+        EmbedProvider::Ollama => batch_endpoint(&config.ollama_url),
+        EmbedProvider::Openai => Some(config.ollama_url.clone()),
+        EmbedProvider::Llamacpp => None,
+    };
+    let Some(batch_url) = batch_url else {
+        embed_each(config, texts, (0..texts.len()).collect(), &mut out).await?;
+        return Ok(out);
+    };
+
+    // Ranges still to embed, popped from the back so earlier ranges go first.
+    let mut pending: Vec<(usize, usize)> = (0..texts.len())
+        .step_by(batch_size)
+        .map(|start| (start, (start + batch_size).min(texts.len())))
+        .rev()
+        .collect();
+    let mut in_flight = FuturesUnordered::new();
+
+    loop {
+        while in_flight.len() < concurrency {
+            let Some((start, end)) = pending.pop() else {
+                break;
+            };
+            let (client, batch_url) = (&client, &batch_url);
+            in_flight.push(async move {
+                let batch = &texts[start..end];
+                let vectors = match provider {
+                    EmbedProvider::Openai => {
+                        openai_embeddings(config, client, batch_url, batch).await
+                    }
+                    _ => embed_batch(config, client, batch_url, batch).await,
+                };
+                (start, end, vectors)
+            });
+        }
+        let Some((start, end, vectors)) = in_flight.next().await else {
+            break;
+        };
+        match vectors {
+            Ok(vectors) => {
+                for (i, v) in vectors.into_iter().enumerate() {
+                    out[start + i] = Some(v);
+                }
+            }
+            Err(e)
+                if provider == EmbedProvider::Ollama
+                    && http_status(&e) == Some(reqwest::StatusCode::NOT_FOUND) =>
+            {
+                // Older Ollama without the batch endpoint
+                drop(in_flight);
+                let missing = (0..texts.len()).filter(|&i| out[i].is_none()).collect();
+                embed_each(config, texts, missing, &mut out).await?;
+                return Ok(out);
+            }
+            Err(e) if is_fatal(&e) => return Err(e),
+            Err(_) if end - start > 1 => {
+                let mid = start + (end - start) / 2;
+                pending.push((mid, end));
+                pending.push((start, mid));
+            }
+            Err(e) => {
+                let preview: String = texts[start].chars().take(60).collect();
+                eprintln!(
+                    "  ⚠️  Skipping chunk the embedding server rejected ({}): {:?}…",
+                    e, preview
+                );
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// `texts[i]` for each of `indices` into `out[i]`, one request per text and
+/// up to `embed_concurrency` at once, skipping texts the server rejects.
+async fn embed_each(
+    config: &Config,
+    texts: &[String],
+    indices: Vec<usize>,
+    out: &mut [Option<Vec<f64>>],
+) -> Result<()> {
+    // Futures built up front: a `.map` closure over `&String` on the stream
+    // would keep indexing from being `Send`
+    let requests: Vec<_> = indices
+        .iter()
+        .map(|&i| async move { (i, get_embedding(config, &texts[i]).await) })
+        .collect();
+    let mut results = stream::iter(requests).buffer_unordered(config.embed_concurrency.max(1));
+    while let Some((i, result)) = results.next().await {
+        match result {
+            Ok(v) => out[i] = Some(v),
+            Err(e) if is_fatal(&e) => return Err(e),
+            Err(e) => {
+                let preview: String = texts[i].chars().take(60).collect();
+                eprintln!(
+                    "  ⚠️  Skipping chunk the embedding server rejected ({}): {:?}…",
+                    e, preview
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn embed_batch(
+    config: &Config,
+    client: &reqwest::Client,
+    url: &str,
+    texts: &[String],
+) -> Result<Vec<Vec<f64>>> {
+    let started = Instant::now();
+    let request = client.post(url).json(&BatchEmbedRequest {
+        model: &config.model,
+        input: texts,
+    });
+    let resp = send(config, request)
         .await?
-        .json::<EmbedResponse>()
+        .json::<BatchEmbedResponse>()
         .await?;
-    Ok(resp.embedding)
+    telemetry::record_embed(started);
+
+    if resp.embeddings.len() != texts.len() {
+        anyhow::bail!(
+            "Embedding server returned {} vectors for {} inputs",
+            resp.embeddings.len(),
+            texts.len()
+        );
+    }
+    Ok(resp.embeddings)
+}
+
+/// OpenAI-compatible `/v1/embeddings` (OpenAI, Together, LiteLLM, ...):
+/// `{"model", "input": [...]}` → `{"data": [{"index", "embedding"}]}`, put
+/// back in input order.
+/// `embed_api_key` goes along as a Bearer token.
+async fn openai_embeddings(
+    config: &Config,
+    client: &reqwest::Client,
+    url: &str,
+    texts: &[String],
+) -> Result<Vec<Vec<f64>>> {
+    let started = Instant::now();
+    let mut request = client.post(url).json(&BatchEmbedRequest {
+        model: &config.model,
+        input: texts,
+    });
+    if let Some(key) = &config.embed_api_key {
+        request = request.bearer_auth(key);
+    }
+    let mut resp = send(config, request)
+        .await?
+        .json::<OpenaiEmbedResponse>()
+        .await?;
+    telemetry::record_embed(started);
+
+    if resp.data.len() != texts.len() {
+        anyhow::bail!(
+            "Embedding server returned {} vectors for {} inputs",
+            resp.data.len(),
+            texts.len()
+        );
+    }
+    resp.data.sort_by_key(|d| d.index);
+    Ok(resp.data.into_iter().map(|d| d.embedding).collect())
+}
+
+/// One text through llama.cpp's server. Its `/embedding` route answers
+/// `{"embedding": [...]}` or, in newer builds, `[{"index": 0, "embedding": [...]}]`
+/// (nested one level deeper without pooling); its OpenAI-style `/v1/embeddings`
+/// answers `{"data": [{"embedding": [...]}]}`. All are accepted.
+async fn llamacpp_embedding(
+    config: &Config,
+    client: &reqwest::Client,
+    url: &str,
+    text: &str,
+) -> Result<Vec<f64>> {
+    let started = Instant::now();
+    let request = client
+        .post(url)
+        .json(&serde_json::json!({ "content": text }));
+    let resp = send(config, request)
+        .await?
+        .json::<serde_json::Value>()
+        .await?;
+    telemetry::record_embed(started);
+
+    let first = |v: &serde_json::Value| v.as_array().and_then(|a| a.first()).cloned();
+    let mut embedding = resp
+        .get("embedding")
+        .cloned()
+        .or_else(|| {
+            resp.get("data")
+                .and_then(first)
+                .and_then(|d| d.get("embedding").cloned())
+        })
+        .or_else(|| first(&resp).and_then(|d| d.get("embedding").cloned()))
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "llama.cpp response has no embedding: {}",
+                truncate(&resp.to_string())
+            )
+        })?;
+    // Unpooled output is one vector per token; the first is the sequence's
+    if embedding.get(0).is_some_and(|v| v.is_array()) {
+        embedding = embedding[0].take();
+    }
+    Ok(serde_json::from_value(embedding)?)
+}
+
+/// Send an embedding request, retrying connection failures and 5xx answers
+/// (a model still loading, a server under load) up to `embed_max_retries`
+/// times. Waits double from `embed_retry_base_ms`, plus up to half again as
+/// jitter so parallel requests don't retry in lockstep. 4xx answers are final.
+async fn send(config: &Config, mut request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+    let mut attempt = 0;
+    loop {
+        let retry = request.try_clone();
+        let result = request.send().await.and_then(|r| r.error_for_status());
+        let transient = match &result {
+            Ok(_) => false,
+            Err(e) => e.status().map_or(!e.is_builder(), |s| s.is_server_error()),
+        };
+        match (result, retry) {
+            (Err(e), Some(next)) if transient && attempt < config.embed_max_retries => {
+                let delay = config
+                    .embed_retry_base_ms
+                    .saturating_mul(1 << attempt.min(16));
+                let jitter = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.subsec_nanos() as u64)
+                    % (delay / 2 + 1);
+                eprintln!(
+                    "  ⚠️  Embedding request failed ({}); retrying in {} ms",
+                    e,
+                    delay + jitter
+                );
+                tokio::time::sleep(Duration::from_millis(delay + jitter)).await;
+                request = next;
+                attempt += 1;
+            }
+            (result, _) => return Ok(result?),
+        }
+    }
+}
+
+fn truncate(s: &str) -> String {
+    s.chars().take(200).collect()
+}
+
+/// Cosine similarity of two vectors; 0.0 if either is all zeros or lengths differ.
+pub fn cosine_similarity(a: &[f64], b: &[f64]) -> f64 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f64 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f64>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a * norm_b)
+}
+
+/// pgvector's text form `[1,2,3]`.
+pub fn vector_literal(v: &[f64]) -> String {
+    format!(
+        "[{}]",
+        v.iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    )
+}
+
+/// Parse pgvector's text form `[1,2,3]`.
+pub fn parse_vector(s: &str) -> Vec<f64> {
+    s.trim_matches(|c| c == '[' || c == ']')
+        .split(',')
+        .filter_map(|v| v.trim().parse().ok())
+        .collect()
+}
+
+/// `.../api/embeddings` (single prompt) → `.../api/embed` (batch input);
+/// `None` for a URL that is neither, whose batch support is unknown.
+fn batch_endpoint(ollama_url: &str) -> Option<String> {
+    match ollama_url.strip_suffix("/api/embeddings") {
+        Some(base) => Some(format!("{}/api/embed", base)),
+        None if ollama_url.ends_with("/api/embed") => Some(ollama_url.to_string()),
+        None => None,
+    }
+}
+
+fn http_status(e: &anyhow::Error) -> Option<reqwest::StatusCode> {
+    e.downcast_ref::<reqwest::Error>().and_then(|e| e.status())
+}
+
+/// Errors no retry or smaller batch gets past: no HTTP answer at all, or a
+/// rejected API key. Any other HTTP error is blamed on the texts sent.
+fn is_fatal(e: &anyhow::Error) -> bool {
+    matches!(
+        http_status(e),
+        None | Some(reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use wiremock::matchers::{body_string_contains, header, method, path};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    /// `text 7` embeds as `[7.0]`, so a vector shows which text it came from.
+    fn numbered(text: &str) -> Vec<f64> {
+        vec![text.trim_start_matches("text ").parse().unwrap()]
+    }
+
+    fn texts(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("text {}", i)).collect()
+    }
+
+    fn inputs(request: &Request) -> Vec<String> {
+        let body: serde_json::Value = request.body_json().unwrap();
+        serde_json::from_value(body["input"].clone()).unwrap()
+    }
+
+    /// Answer Ollama's `/api/embed` with `numbered` vectors.
+    async fn mount_batch(server: &MockServer) {
+        Mock::given(method("POST"))
+            .and(path("/api/embed"))
+            .respond_with(|r: &Request| {
+                let embeddings: Vec<Vec<f64>> = inputs(r).iter().map(|t| numbered(t)).collect();
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "embeddings": embeddings }))
+            })
+            .mount(server)
+            .await;
+    }
+
+    fn ollama_config(server: &MockServer) -> Config {
+        testing::config("", &format!("{}/api/embeddings", server.uri()))
+    }
+
+    /// Answer Ollama's `/api/embeddings` with `numbered` vectors, after
+    /// answering the first `failures` requests with `status`.
+    async fn flaky_server(status: u16, failures: u64) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(path("/api/embeddings"))
+            .respond_with(ResponseTemplate::new(status))
+            .up_to_n_times(failures)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/api/embeddings"))
+            .respond_with(|r: &Request| {
+                let body: serde_json::Value = r.body_json().unwrap();
+                ResponseTemplate::new(200).set_body_json(
+                    serde_json::json!({ "embedding": numbered(body["prompt"].as_str().unwrap()) }),
+                )
+            })
+            .mount(&server)
+            .await;
+        server
+    }
+
+    async fn requests(server: &MockServer) -> usize {
+        server.received_requests().await.unwrap().len()
+    }
+
+    /// Embed `n` texts `batch_size` at a time through a server that holds
+    /// each request, with `concurrency` requests allowed in flight; the most
+    /// the server saw at once.
+    async fn max_in_flight(
+        n: usize,
+        batch_size: usize,
+        concurrency: usize,
+        batch_endpoint: bool,
+    ) -> usize {
+        let embedder =
+            testing::SlowEmbedder::start(Duration::from_millis(20), batch_endpoint).await;
+        let mut config = testing::config("", &embedder.url);
+        config.embed_concurrency = concurrency;
+        let texts = texts(n);
+        let out = get_embeddings_batch(&config, &texts, batch_size)
+            .await
+            .unwrap();
+        for (text, vector) in texts.iter().zip(out) {
+            assert_eq!(vector, Some(testing::vector_for(text)), "{}", text);
+        }
+        embedder.max_in_flight()
+    }
+
+    #[tokio::test]
+    async fn batches_in_flight_stay_within_embed_concurrency() {
+        assert_eq!(max_in_flight(24, 1, 3, true).await, 3);
+        assert_eq!(max_in_flight(24, 4, 4, true).await, 4);
+        // Fewer batches than the cap: one request each
+        assert_eq!(max_in_flight(8, 4, 4, true).await, 2);
+        assert_eq!(max_in_flight(8, 1, 1, true).await, 1);
+        assert_eq!(max_in_flight(8, 1, 0, true).await, 1);
+    }
+
+    #[tokio::test]
+    async fn single_text_requests_stay_within_embed_concurrency() {
+        assert_eq!(max_in_flight(9, 8, 3, false).await, 3);
+        assert_eq!(max_in_flight(3, 8, 1, false).await, 1);
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried_until_one_succeeds() {
+        let server = flaky_server(500, 2).await;
+        assert_eq!(
+            get_embedding(&ollama_config(&server), "text 3")
+                .await
+                .unwrap(),
+            [3.0]
+        );
+        assert_eq!(requests(&server).await, 3);
+    }
+
+    #[tokio::test]
+    async fn retries_stop_at_embed_max_retries() {
+        let server = flaky_server(503, 10).await;
+        let mut config = ollama_config(&server);
+        config.embed_max_retries = 2;
+        let err = get_embedding(&config, "text 3").await.unwrap_err();
+        assert_eq!(
+            http_status(&err),
+            Some(reqwest::StatusCode::SERVICE_UNAVAILABLE)
+        );
+        assert_eq!(requests(&server).await, 3);
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let server = flaky_server(400, 1).await;
+        let err = get_embedding(&ollama_config(&server), "text 3")
+            .await
+            .unwrap_err();
+        assert_eq!(http_status(&err), Some(reqwest::StatusCode::BAD_REQUEST));
+        assert_eq!(requests(&server).await, 1);
+    }
+
+    #[tokio::test]
+    async fn refused_connections_are_retried_then_reported() {
+        // Bound and dropped: nothing listens there any more
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut config = testing::config("", &format!("http://127.0.0.1:{}/api/embeddings", port));
+        config.embed_max_retries = 2;
+        let err = get_embedding(&config, "text 3").await.unwrap_err();
+        assert!(is_fatal(&err), "{:#}", err);
+    }
+
+    #[tokio::test]
+    async fn batches_keep_each_embedding_with_its_text() {
+        let server = MockServer::start().await;
+        mount_batch(&server).await;
+        let out = get_embeddings_batch(&ollama_config(&server), &texts(7), 3)
+            .await
+            .unwrap();
+        let expected: Vec<Option<Vec<f64>>> = texts(7).iter().map(|t| Some(numbered(t))).collect();
+        assert_eq!(out, expected);
+
+        let sizes: Vec<usize> = server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| inputs(r).len())
+            .collect();
+        assert_eq!(sizes.iter().sum::<usize>(), 7);
+        assert!(sizes.iter().all(|&n| n <= 3), "{:?}", sizes);
+        assert_eq!(sizes.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn rejected_batch_is_split_down_to_the_bad_text() {
+        let server = MockServer::start().await;
+        Mock::given(body_string_contains("text 4"))
+            .respond_with(ResponseTemplate::new(500))
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        mount_batch(&server).await;
+        let mut config = ollama_config(&server);
+        config.embed_max_retries = 0;
+
+        let out = get_embeddings_batch(&config, &texts(6), 6).await.unwrap();
+        let embedded: Vec<bool> = out.iter().map(Option::is_some).collect();
+        assert_eq!(embedded, [true, true, true, true, false, true]);
+        assert_eq!(out[5], Some(vec![5.0]));
+    }
+
+    #[tokio::test]
+    async fn servers_without_the_batch_endpoint_get_one_text_per_request() {
+        let server = MockServer::start().await;
+        Mock::given(path("/api/embed"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        Mock::given(path("/api/embeddings"))
+            .respond_with(|r: &Request| {
+                let body: serde_json::Value = r.body_json().unwrap();
+                ResponseTemplate::new(200).set_body_json(
+                    serde_json::json!({ "embedding": numbered(body["prompt"].as_str().unwrap()) }),
+                )
+            })
+            .expect(5)
+            .mount(&server)
+            .await;
+
+        let out = get_embeddings_batch(&ollama_config(&server), &texts(5), 16)
+            .await
+            .unwrap();
+        assert_eq!(
+            out,
+            texts(5)
+                .iter()
+                .map(|t| Some(numbered(t)))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn openai_answers_are_put_back_in_input_order() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/embeddings"))
+            .and(header("authorization", "Bearer sk-test"))
+            .respond_with(|r: &Request| {
+                let data: Vec<serde_json::Value> = inputs(r)
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .map(|(i, t)| serde_json::json!({ "index": i, "embedding": numbered(t) }))
+                    .collect();
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "data": data }))
+            })
+            .mount(&server)
+            .await;
+        let mut config = testing::config("", &format!("{}/v1/embeddings", server.uri()));
+        config.embed_provider = EmbedProvider::Openai;
+        config.embed_api_key = Some("sk-test".to_string());
+
+        let out = get_embeddings_batch(&config, &texts(4), 4).await.unwrap();
+        assert_eq!(
+            out,
+            texts(4)
+                .iter()
+                .map(|t| Some(numbered(t)))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(get_embedding(&config, "text 9").await.unwrap(), [9.0]);
+    }
+
+    #[tokio::test]
+    async fn llamacpp_answers_in_every_shape_are_read() {
+        let shapes = [
+            serde_json::json!({ "embedding": [1.0, 2.0] }),
+            serde_json::json!([{ "index": 0, "embedding": [1.0, 2.0] }]),
+            serde_json::json!([{ "index": 0, "embedding": [[1.0, 2.0], [3.0, 4.0]] }]),
+            serde_json::json!({ "data": [{ "embedding": [1.0, 2.0] }] }),
+        ];
+        for shape in shapes {
+            let server = MockServer::start().await;
+            Mock::given(path("/embedding"))
+                .and(body_string_contains("\"content\""))
+                .respond_with(ResponseTemplate::new(200).set_body_json(&shape))
+                .mount(&server)
+                .await;
+            let mut config = testing::config("", &format!("{}/embedding", server.uri()));
+            config.embed_provider = EmbedProvider::Llamacpp;
+            assert_eq!(
+                get_embedding(&config, "hello").await.unwrap(),
+                [1.0, 2.0],
+                "{}",
+                shape
+            );
+        }
+    }
 }
@@ -0,0 +1,212 @@
+//! Plain text out of EPUB and DOCX files, rendered as markdown so chapter and
+//! section titles become `#` headings the markdown chunker already understands.
+//! Both formats are zip archives of XML.
+
+use anyhow::Result;
+use std::io::Read;
+use std::path::Path;
+
+type Archive = zip::ZipArchive<std::fs::File>;
+
+fn open(path: &Path) -> Result<Archive> {
+    Ok(zip::ZipArchive::new(std::fs::File::open(path)?)?)
+}
+
+fn read_entry(archive: &mut Archive, name: &str) -> Result<String> {
+    let mut entry = archive
+        .by_name(name)
+        .map_err(|e| anyhow::anyhow!("{}: {}", name, e))?;
+    let mut text = String::new();
+    entry.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+/// Chapters in spine (reading) order, each starting with its heading.
+pub fn epub_text(path: &Path) -> Result<String> {
+    let mut archive = open(path)?;
+
+    let container = read_entry(&mut archive, "META-INF/container.xml")?;
+    let container = roxmltree::Document::parse(&container)?;
+    let opf_path = container
+        .descendants()
+        .find(|n| n.has_tag_name("rootfile"))
+        .and_then(|n| n.attribute("full-path"))
+        .ok_or_else(|| anyhow::anyhow!("container.xml names no rootfile"))?
+        .to_string();
+    let opf_dir = match opf_path.rfind('/') {
+        Some(i) => &opf_path[..=i],
+        None => "",
+    };
+
+    let opf = read_entry(&mut archive, &opf_path)?;
+    let opf = roxmltree::Document::parse(&opf)?;
+    let href_of = |id: &str| {
+        opf.descendants()
+            .find(|n| n.has_tag_name("item") && n.attribute("id") == Some(id))
+            .and_then(|n| n.attribute("href"))
+    };
+    let chapters: Vec<String> = opf
+        .descendants()
+        .filter(|n| n.has_tag_name("itemref"))
+        .filter_map(|n| n.attribute("idref"))
+        .filter_map(href_of)
+        .map(|href| format!("{}{}", opf_dir, percent_decode(href)))
+        .collect();
+    if chapters.is_empty() {
+        anyhow::bail!("EPUB spine is empty");
+    }
+
+    let mut out = Vec::new();
+    for chapter in chapters {
+        let html = read_entry(&mut archive, &chapter)?;
+        let text = html_to_markdown(&html);
+        if !text.trim().is_empty() {
+            out.push(text);
+        }
+    }
+    Ok(out.join("\n\n"))
+}
+
+/// Strip an XHTML chapter down to paragraphs, keeping `<h1>`–`<h6>` as `#`
+/// headings. Falls back to `<title>` as the heading when the body has none.
+/// Regex-based rather than a parser, since EPUB XHTML often uses HTML entities
+/// that a strict XML parser rejects.
+fn html_to_markdown(html: &str) -> String {
+    let body = match (html.find("<body"), html.rfind("</body>")) {
+        (Some(start), Some(end)) if start < end => &html[start..end],
+        _ => html,
+    };
+    let title = regex::Regex::new(r"(?is)<title[^>]*>(.*?)</title>")
+        .unwrap()
+        .captures(html)
+        .map(|c| clean_inline(&c[1]))
+        .unwrap_or_default();
+
+    let headings = regex::Regex::new(r"(?is)<h([1-6])[^>]*>(.*?)</h[1-6]>").unwrap();
+    let has_heading = headings.is_match(body);
+    let text = headings.replace_all(body, |c: &regex::Captures| {
+        let level: usize = c[1].parse().unwrap_or(1);
+        format!("\n\n{} {}\n\n", "#".repeat(level), clean_inline(&c[2]))
+    });
+    let blocks = regex::Regex::new(r"(?i)</(p|div|li|blockquote|section|tr)>|<br\s*/?>").unwrap();
+    let text = blocks.replace_all(&text, "\n\n");
+    let tags = regex::Regex::new(r"(?s)<[^>]*>").unwrap();
+    let text = decode_entities(&tags.replace_all(&text, ""));
+
+    let paragraphs: Vec<String> = text
+        .split("\n\n")
+        .map(|p| p.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|p| !p.is_empty())
+        .collect();
+    let mut out = paragraphs.join("\n\n");
+    if !has_heading && !title.is_empty() && !out.is_empty() {
+        out = format!("# {}\n\n{}", title, out);
+    }
+    out
+}
+
+fn clean_inline(html: &str) -> String {
+    let tags = regex::Regex::new(r"(?s)<[^>]*>").unwrap();
+    decode_entities(&tags.replace_all(html, ""))
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn decode_entities(text: &str) -> String {
+    let re = regex::Regex::new(r"&(#x[0-9a-fA-F]+|#[0-9]+|[a-zA-Z]+);").unwrap();
+    re.replace_all(text, |c: &regex::Captures| {
+        let name = &c[1];
+        let ch = if let Some(hex) = name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+            u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+        } else if let Some(dec) = name.strip_prefix('#') {
+            dec.parse().ok().and_then(char::from_u32)
+        } else {
+            match name {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                "mdash" => Some('—'),
+                "ndash" => Some('–'),
+                "hellip" => Some('…'),
+                "lsquo" => Some('‘'),
+                "rsquo" => Some('’'),
+                "ldquo" => Some('“'),
+                "rdquo" => Some('”'),
+                _ => None,
+            }
+        };
+        ch.map(String::from).unwrap_or_else(|| c[0].to_string())
+    })
+    .into_owned()
+}
+
+/// Manifest hrefs are URLs; zip entry names are not.
+fn percent_decode(href: &str) -> String {
+    let bytes = href.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(b) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Paragraphs of `word/document.xml`; paragraphs styled `Title` or
+/// `Heading N` become `#` headings.
+pub fn docx_text(path: &Path) -> Result<String> {
+    let mut archive = open(path)?;
+    let xml = read_entry(&mut archive, "word/document.xml")?;
+    let doc = roxmltree::Document::parse(&xml)?;
+
+    let mut out = Vec::new();
+    for p in doc.descendants().filter(|n| n.has_tag_name("p")) {
+        let mut text = String::new();
+        for n in p.descendants() {
+            match n.tag_name().name() {
+                "t" => text.push_str(n.text().unwrap_or("")),
+                "tab" => text.push('\t'),
+                "br" | "cr" => text.push('\n'),
+                _ => {}
+            }
+        }
+        let text = text.trim();
+        if text.is_empty() {
+            continue;
+        }
+        let style = p
+            .descendants()
+            .find(|n| n.has_tag_name("pStyle"))
+            .and_then(|n| n.attributes().find(|a| a.name() == "val"))
+            .map(|a| a.value().to_ascii_lowercase())
+            .unwrap_or_default();
+        let level = if style == "title" {
+            Some(1)
+        } else {
+            style
+                .strip_prefix("heading")
+                .and_then(|n| n.trim().parse::<usize>().ok())
+                .map(|n| n.clamp(1, 6))
+        };
+        match level {
+            Some(n) => out.push(format!("{} {}", "#".repeat(n), text.replace('\n', " "))),
+            None => out.push(text.to_string()),
+        }
+    }
+    if out.is_empty() {
+        anyhow::bail!("document has no text");
+    }
+    Ok(out.join("\n\n"))
+}
@@ -0,0 +1,100 @@
+//! `git` sources: tracked markdown files, and what changed between two commits,
+//! read by shelling out to the `git` CLI.
+
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Files a `git` source indexes.
+const PATHSPEC: &str = "*.md";
+
+/// A markdown file added, modified, deleted or renamed between two commits.
+/// Paths are absolute (the work tree path joined with git's relative path).
+pub enum Change {
+    Upsert(PathBuf),
+    Delete(PathBuf),
+    Rename { from: PathBuf, to: PathBuf },
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let out = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|e| anyhow::anyhow!("Cannot run git: {}", e))?;
+    if !out.status.success() {
+        anyhow::bail!(
+            "git {} failed in {}: {}",
+            args.join(" "),
+            dir.display(),
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+/// Tracked markdown files under `dir`, which may be a subdirectory of the work tree.
+pub fn tracked_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let out = git(dir, &["ls-files", "-z", "--", PATHSPEC])?;
+    Ok(out
+        .split('\0')
+        .filter(|p| !p.is_empty())
+        .map(|p| dir.join(p))
+        .collect())
+}
+
+/// Full SHA of `HEAD`.
+pub fn head(dir: &Path) -> Result<String> {
+    Ok(git(dir, &["rev-parse", "HEAD"])?.trim().to_string())
+}
+
+/// Whether `sha` names a commit in this repository (it may have been
+/// garbage-collected after a rebase).
+pub fn has_commit(dir: &Path, sha: &str) -> bool {
+    git(dir, &["cat-file", "-e", &format!("{}^{{commit}}", sha)]).is_ok()
+}
+
+/// Markdown changes under `dir` from `from` to `to`, with renames detected.
+pub fn changes(dir: &Path, from: &str, to: &str) -> Result<Vec<Change>> {
+    let out = git(
+        dir,
+        &[
+            "diff",
+            "--name-status",
+            "-z",
+            "-M",
+            "--relative",
+            from,
+            to,
+            "--",
+            PATHSPEC,
+        ],
+    )?;
+    let mut fields = out.split('\0').filter(|f| !f.is_empty());
+    let mut changes = Vec::new();
+    while let Some(status) = fields.next() {
+        let mut path = || fields.next().map(|p| dir.join(p));
+        let change = match status.chars().next() {
+            Some('R') => match (path(), path()) {
+                (Some(from), Some(to)) => Change::Rename { from, to },
+                _ => break,
+            },
+            Some('D') => match path() {
+                Some(p) => Change::Delete(p),
+                None => break,
+            },
+            // A, M, C (copy: the new path), T (type change)
+            Some('C') => match (path(), path()) {
+                (Some(_), Some(to)) => Change::Upsert(to),
+                _ => break,
+            },
+            _ => match path() {
+                Some(p) => Change::Upsert(p),
+                None => break,
+            },
+        };
+        changes.push(change);
+    }
+    Ok(changes)
+}
@@ -0,0 +1,290 @@
+//! `agent-memory serve-http`: search and incremental indexing over HTTP, so
+//! several local agents can share one memory store through one long-running
+//! process instead of each spawning the CLI. Every response is JSON; failures
+//! are `{"error": "..."}` with a 4xx/5xx status.
+
+use anyhow::Result;
+use axum::extract::{self, Query};
+use axum::http::{Method, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+use crate::config::Config;
+use crate::{db, index, search};
+
+struct State {
+    config: Config,
+    /// Held while an incremental index runs; a second request gets 409.
+    indexing: tokio::sync::Mutex<()>,
+}
+
+/// A failed request: its status, and the message sent as `{"error": ...}`.
+struct Failure(StatusCode, String);
+
+impl IntoResponse for Failure {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+type Reply = std::result::Result<Json<Value>, Failure>;
+
+/// Serve on `host:port` until the process is stopped. Port 0 picks a free one;
+/// the address actually bound is printed on startup.
+pub async fn run(config: &Config, host: &str, port: u16) -> Result<()> {
+    let listener = TcpListener::bind((host, port))
+        .await
+        .map_err(|e| anyhow::anyhow!("Cannot listen on {}:{}: {}", host, port, e))?;
+    println!(
+        "🐑⚡ Serving {} on http://{}",
+        config.agent_id,
+        listener.local_addr()?
+    );
+    axum::serve(listener, router(config.clone())).await?;
+    Ok(())
+}
+
+/// The routes, answering for `config`'s store. Unknown paths and methods get
+/// the same JSON errors as everything else.
+fn router(config: Config) -> Router {
+    let state = Arc::new(State {
+        config,
+        indexing: tokio::sync::Mutex::new(()),
+    });
+    Router::new()
+        .route("/search", get(search))
+        .route("/index/incremental", post(index_incremental))
+        .method_not_allowed_fallback(method_not_allowed)
+        .fallback(not_found)
+        .with_state(state)
+}
+
+async fn method_not_allowed(method: Method, uri: Uri) -> Failure {
+    Failure(
+        StatusCode::METHOD_NOT_ALLOWED,
+        format!("{} does not accept {}", uri.path(), method),
+    )
+}
+
+async fn not_found(uri: Uri) -> Failure {
+    Failure(
+        StatusCode::NOT_FOUND,
+        format!("No route for {}", uri.path()),
+    )
+}
+
+/// `GET /search?q=...`: the matching chunks as a JSON array, most similar
+/// first. `top` (default 5), `agent` (search another agent's chunks in the
+/// same table), `since`, `until`, `lang`, `min_similarity` and `hybrid`
+/// narrow it the way the CLI flags do.
+async fn search(
+    extract::State(state): extract::State<Arc<State>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Reply {
+    let bad = |message: String| Failure(StatusCode::BAD_REQUEST, message);
+    let query = params
+        .get("q")
+        .filter(|q| !q.trim().is_empty())
+        .ok_or_else(|| bad("`q` is required".to_string()))?;
+    let top_k = match params.get("top") {
+        Some(top) => top
+            .parse::<i64>()
+            .ok()
+            .filter(|&top| top >= 1)
+            .ok_or_else(|| {
+                bad(format!(
+                    "`top` must be a number of at least 1, got {:?}",
+                    top
+                ))
+            })?,
+        None => 5,
+    };
+    let checked = |key: &str, parse: fn(&str) -> std::result::Result<String, String>| {
+        params
+            .get(key)
+            .map(|v| parse(v).map_err(|e| bad(format!("`{}`: {}", key, e))))
+            .transpose()
+    };
+
+    let date = |key: &str| {
+        params
+            .get(key)
+            .map(|v| crate::parse_date(v).map_err(|e| bad(format!("`{}`: {}", key, e))))
+            .transpose()
+    };
+
+    let mut opts = search::SearchOptions::json(top_k);
+    opts.lang = checked("lang", crate::parse_lang)?;
+    opts.since = date("since")?;
+    opts.until = date("until")?;
+    opts.min_similarity = match params.get("min_similarity") {
+        Some(v) => Some(
+            v.parse::<f64>()
+                .ok()
+                .filter(|v| (-1.0..=1.0).contains(v))
+                .ok_or_else(|| {
+                    bad("`min_similarity` must be a number between -1 and 1".to_string())
+                })?,
+        ),
+        None => None,
+    };
+    opts.hybrid = matches!(params.get("hybrid").map(String::as_str), Some("1" | "true"));
+
+    let mut config = state.config.clone();
+    if let Some(agent) = params.get("agent").filter(|a| !a.is_empty()) {
+        config.agent_id = agent.clone();
+    }
+    let (results, _) = search::run(&config, std::slice::from_ref(query), &opts)
+        .await
+        .map_err(|e| {
+            Failure(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("{:#}", db::explain(e)),
+            )
+        })?;
+    serde_json::to_value(results)
+        .map(Json)
+        .map_err(|e| Failure(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+/// `POST /index/incremental`: index new and modified files from the
+/// configured sources and answer with the counts. One run at a time.
+async fn index_incremental(extract::State(state): extract::State<Arc<State>>) -> Reply {
+    let Ok(_running) = state.indexing.try_lock() else {
+        return Err(Failure(
+            StatusCode::CONFLICT,
+            "An incremental index is already running".to_string(),
+        ));
+    };
+    let summary = index::run_incremental_index(&state.config, false, false, None)
+        .await
+        .map_err(|e| {
+            Failure(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("{:#}", db::explain(e)),
+            )
+        })?;
+    serde_json::to_value(summary)
+        .map(Json)
+        .map_err(|e| Failure(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    /// Serve `config` on a free port; the base URL to send requests to.
+    async fn serve(config: Config) -> String {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router(config)).await });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn bad_requests_get_json_errors() {
+        let base = serve(testing::config(
+            "host=/nonexistent",
+            "http://127.0.0.1:1/api/embeddings",
+        ))
+        .await;
+        let http = reqwest::Client::new();
+        let cases = [
+            (
+                http.get(format!("{}/elsewhere", base)),
+                404,
+                "No route for /elsewhere",
+            ),
+            (
+                http.post(format!("{}/search", base)),
+                405,
+                "/search does not accept POST",
+            ),
+            (
+                http.get(format!("{}/index/incremental", base)),
+                405,
+                "/index/incremental does not accept GET",
+            ),
+            (
+                http.get(format!("{}/search?q=+", base)),
+                400,
+                "`q` is required",
+            ),
+            (
+                http.get(format!("{}/search?q=x&top=many", base)),
+                400,
+                "`top` must be a number of at least 1, got \"many\"",
+            ),
+            (
+                http.get(format!("{}/search?q=x&top=0", base)),
+                400,
+                "`top` must be a number of at least 1, got \"0\"",
+            ),
+            (
+                http.get(format!("{}/search?q=x&min_similarity=2", base)),
+                400,
+                "`min_similarity` must be a number between -1 and 1",
+            ),
+        ];
+        for (request, status, error) in cases {
+            let resp = request.send().await.unwrap();
+            assert_eq!(resp.status().as_u16(), status, "{}", error);
+            let body: Value = resp.json().await.unwrap();
+            assert_eq!(body, json!({ "error": error }));
+        }
+    }
+
+    #[tokio::test]
+    async fn indexes_and_searches_over_http() {
+        let Some(db_url) = testing::db_url() else {
+            return;
+        };
+        let embedder = testing::embedder().await;
+        let notes = testing::notes(&[
+            ("2025-01-06.md", testing::MONDAY),
+            ("2025-01-07.md", testing::TUESDAY),
+        ]);
+        let config = testing::notes_config(&db_url, &embedder, notes.path());
+        let base = serve(config.clone()).await;
+        let http = reqwest::Client::new();
+
+        let resp = http
+            .post(format!("{}/index/incremental", base))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        let summary: Value = resp.json().await.unwrap();
+        assert_eq!(summary["new_files"], 2, "{}", summary);
+
+        let resp = http
+            .get(format!("{}/search?q=deploy+disk+space&top=1", base))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        let results: Value = resp.json().await.unwrap();
+        let results = results.as_array().unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(
+            results[0]["content"]
+                .as_str()
+                .unwrap()
+                .contains("out of disk space"),
+            "{:?}",
+            results
+        );
+        assert!(results[0]["source_path"]
+            .as_str()
+            .unwrap()
+            .ends_with("2025-01-06.md"));
+
+        testing::clear_agent(&config).await;
+    }
+}
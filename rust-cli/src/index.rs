@@ -1,322 +1,3795 @@
 use anyhow::Result;
-use std::path::Path;
+use chrono::NaiveDate;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio_postgres::types::ToSql;
 use tokio_postgres::Client;
 use uuid::Uuid;
 
+use crate::cache::ResultCache;
 use crate::chunk;
-use crate::config::{Config, Source};
-use crate::embed::get_embedding;
+use crate::compress;
+use crate::config::{ChunkStrategy, Config, LabelFrom, QueryMode, Source, SourceType, VectorType};
+use crate::copy;
+use crate::db;
+use crate::embed::{get_embedding, get_embeddings_batch, parse_vector, vector_literal};
+use crate::extract;
+use crate::git;
+use crate::search::{self, Binds};
+use crate::telemetry;
 
-pub async fn run_full_index(config: &Config) -> Result<()> {
-    let (client, connection) = tokio_postgres::connect(&config.db_url, tokio_postgres::NoTls).await?;
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("DB connection error: {}", e);
-        }
-    });
+pub async fn run_full_index(
+    config: &Config,
+    chunk_report: bool,
+    profile: bool,
+    resume: bool,
+    batch_commit: Option<usize>,
+) -> Result<()> {
+    let client = db::connect(&config.db_url, None).await?;
+    let config = &settle_query_mode(config, &client).await?;
 
+    warm_up_model(config).await?;
+    let mut report = IndexReport::new(chunk_report, profile);
     let mut total_chunks = 0;
 
+    let mut files = Vec::new();
     for source in &config.sources {
-        match source.source_type.as_str() {
-            "markdown_dir" => {
-                let n = index_markdown_dir(&client, config, source).await?;
-                total_chunks += n;
-            }
-            "single_file" => {
-                let label = source.source_label.as_deref().unwrap_or("single_file");
-                let n = index_markdown_file(&client, config, &source.path, label).await?;
+        if !source.path.exists() {
+            report.skip(&source.path, "source not found");
+            continue;
+        }
+        files.extend(source_files(source, config.canonical_paths));
+    }
+    let mut files = unique_files(files);
+
+    // Files a previous, interrupted run finished are already stored
+    let mut checkpoint = Checkpoint::open(config.checkpoint_path(), resume)?;
+    if !checkpoint.done.is_empty() {
+        let before = files.len();
+        files.retain(|f| {
+            !checkpoint
+                .done
+                .contains(&f.path.to_string_lossy().to_string())
+        });
+        println!(
+            "⏩ Resuming: skipping {} file(s) already indexed (checkpoint {}; --no-resume to start over)\n",
+            before - files.len(),
+            checkpoint.path.display()
+        );
+    }
+
+    let print_file = |file: &SourceFile, n: usize| {
+        if !config.quiet {
+            println!(
+                "  {}: {} chunks",
+                file.path.file_name().unwrap_or_default().to_string_lossy(),
+                n
+            );
+        }
+    };
+    let mut batch = Batch::begin(&client, batch_commit).await?;
+    match config.threads.filter(|&n| n > 1) {
+        Some(threads) => {
+            let mut prepared = prepare_ahead(config, files, threads, chunk_report, profile)?;
+            while let Some((file, ready, file_report)) = prepared.recv().await {
+                report.absorb(file_report);
+                let n = match ready {
+                    Some(p) => {
+                        store_prepared(
+                            &client,
+                            config,
+                            &file,
+                            p,
+                            &EmbeddingCache::new(),
+                            &mut report,
+                        )
+                        .await?
+                    }
+                    None => 0,
+                };
+                print_file(&file, n);
+                batch
+                    .file_done(&client, &mut checkpoint, &file.path, n)
+                    .await?;
                 total_chunks += n;
             }
-            "transcript_dir" => {
-                let n = index_transcript_dir(&client, config, source).await?;
+        }
+        None => {
+            for file in files {
+                let n =
+                    index_file(&client, config, &file, &EmbeddingCache::new(), &mut report).await?;
+                print_file(&file, n);
+                batch
+                    .file_done(&client, &mut checkpoint, &file.path, n)
+                    .await?;
                 total_chunks += n;
             }
-            other => {
-                eprintln!("  ⚠️  Unknown source type: {}", other);
-            }
         }
     }
+    batch.finish(&client, &mut checkpoint).await?;
+    checkpoint.clear();
+    ResultCache::invalidate(config);
 
-    println!("\n✅ Indexed {} total chunks for agent '{}'", total_chunks, config.agent_id);
+    println!(
+        "\n✅ Indexed {} total chunks for agent '{}'",
+        total_chunks, config.agent_id
+    );
+    report.finish(config.strict)?;
     Ok(())
 }
 
-pub async fn run_incremental_index(config: &Config) -> Result<()> {
-    let (client, connection) = tokio_postgres::connect(&config.db_url, tokio_postgres::NoTls).await?;
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("DB connection error: {}", e);
+/// Paths a full index has finished, one per line, appended as each file is
+/// stored so a run that fails part-way can pick up where it stopped.
+struct Checkpoint {
+    path: PathBuf,
+    done: std::collections::HashSet<String>,
+    file: std::fs::File,
+}
+
+impl Checkpoint {
+    /// Open for appending; `resume` keeps what an earlier run recorded.
+    fn open(path: PathBuf, resume: bool) -> Result<Self> {
+        let done = if resume {
+            std::fs::read_to_string(&path)
+                .map(|s| s.lines().map(str::to_string).collect())
+                .unwrap_or_default()
+        } else {
+            std::collections::HashSet::new()
+        };
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| {
+                anyhow::anyhow!("Cannot open index checkpoint {}: {}", path.display(), e)
+            })?;
+        if !resume {
+            file.set_len(0)?;
         }
-    });
+        Ok(Self { path, done, file })
+    }
+
+    fn record(&mut self, path: &Path) -> Result<()> {
+        use std::io::Write;
+        writeln!(self.file, "{}", path.to_string_lossy())?;
+        Ok(())
+    }
+
+    /// The run completed: nothing to resume next time.
+    fn clear(self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// `--batch-commit N`: a full index's writes go into one transaction that is
+/// committed once at least `N` chunks are pending, always between files so
+/// none is half stored. Files reach the checkpoint only once committed, so a
+/// crash loses at most the open batch and a resumed run redoes exactly that.
+/// Without it every statement commits on its own.
+struct Batch {
+    size: Option<usize>,
+    chunks: usize,
+    files: Vec<PathBuf>,
+}
+
+impl Batch {
+    async fn begin(client: &Client, size: Option<usize>) -> Result<Self> {
+        if size.is_some() {
+            client.simple_query("BEGIN").await?;
+        }
+        Ok(Self {
+            size,
+            chunks: 0,
+            files: Vec::new(),
+        })
+    }
+
+    async fn file_done(
+        &mut self,
+        client: &Client,
+        checkpoint: &mut Checkpoint,
+        path: &Path,
+        n: usize,
+    ) -> Result<()> {
+        let Some(size) = self.size else {
+            return checkpoint.record(path);
+        };
+        self.files.push(path.to_path_buf());
+        self.chunks += n;
+        if self.chunks >= size {
+            self.commit(client, checkpoint, true).await?;
+        }
+        Ok(())
+    }
+
+    async fn finish(mut self, client: &Client, checkpoint: &mut Checkpoint) -> Result<()> {
+        if self.size.is_some() {
+            self.commit(client, checkpoint, false).await?;
+        }
+        Ok(())
+    }
+
+    async fn commit(
+        &mut self,
+        client: &Client,
+        checkpoint: &mut Checkpoint,
+        reopen: bool,
+    ) -> Result<()> {
+        client
+            .simple_query(if reopen { "COMMIT; BEGIN" } else { "COMMIT" })
+            .await
+            .inspect_err(|_| telemetry::record_db_error())?;
+        for path in self.files.drain(..) {
+            checkpoint.record(&path)?;
+        }
+        self.chunks = 0;
+        Ok(())
+    }
+}
+
+/// What an incremental run changed, for its closing line (or a caller
+/// reporting it some other way).
+#[derive(Debug, Clone, Copy, Default, serde::Serialize)]
+pub struct IncrementalSummary {
+    pub new_files: usize,
+    pub updated_files: usize,
+    pub removed_files: usize,
+    pub chunks_added: usize,
+    pub chunks_deleted: usize,
+    /// Files skipped or only partly indexed (listed on stderr)
+    pub skipped_files: usize,
+}
+
+impl IncrementalSummary {
+    /// Print the closing line; under `strict`, anything skipped fails the run.
+    pub fn finish(&self, config: &Config) -> Result<()> {
+        self.print(config.quiet);
+        if config.strict && self.skipped_files > 0 {
+            anyhow::bail!(
+                "Strict mode: {} file(s) were not fully indexed",
+                self.skipped_files
+            );
+        }
+        Ok(())
+    }
+
+    fn print(&self, quiet: bool) {
+        let IncrementalSummary {
+            new_files,
+            updated_files,
+            removed_files,
+            chunks_added,
+            chunks_deleted,
+            ..
+        } = *self;
+        if new_files == 0 && updated_files == 0 && removed_files == 0 {
+            if !quiet {
+                println!("Nothing new to index.");
+            }
+        } else if removed_files > 0 {
+            println!(
+                "\n✅ {} new, {} updated, {} removed | +{} chunks, -{} old",
+                new_files, updated_files, removed_files, chunks_added, chunks_deleted
+            );
+        } else {
+            println!(
+                "\n✅ {} new, {} updated | +{} chunks, -{} old",
+                new_files, updated_files, chunks_added, chunks_deleted
+            );
+        }
+    }
+}
 
-    // Get indexed state: source_path -> last indexed timestamp
+pub async fn run_incremental_index(
+    config: &Config,
+    chunk_report: bool,
+    profile: bool,
+    since_commit: Option<&str>,
+) -> Result<IncrementalSummary> {
+    let client = db::connect(&config.db_url, None).await?;
+    let config = &settle_query_mode(config, &client).await?;
+
+    // Get indexed state: source_path -> file mtime when it was indexed
     let indexed_state = get_indexed_state(&client, config).await?;
 
+    warm_up_model(config).await?;
+    let mut report = IndexReport::new(chunk_report, profile);
     let mut new_files = 0;
     let mut updated_files = 0;
+    let mut removed_files = 0;
     let mut chunks_added = 0;
     let mut chunks_deleted = 0;
 
     let all_files = collect_all_files(config);
+    let on_disk: std::collections::HashSet<String> = all_files
+        .iter()
+        .map(|f| f.path.to_string_lossy().to_string())
+        .collect();
 
-    for (filepath, source_type, label) in &all_files {
-        let path_str = filepath.to_string_lossy().to_string();
-        let mtime = std::fs::metadata(filepath)
-            .and_then(|m| m.modified())
-            .ok();
-
-        if let Some(last_indexed) = indexed_state.get(&path_str) {
-            // File was indexed before — check if modified
-            if let Some(mtime) = mtime {
-                let mtime_secs = mtime
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap_or_default()
-                    .as_secs() as i64;
-                if mtime_secs <= *last_indexed {
-                    continue; // Not modified
-                }
+    // Git sources with a known last-indexed commit are updated from `git diff`;
+    // the rest fall through to the mtime comparison below.
+    let mut diffed: std::collections::HashSet<String> = std::collections::HashSet::new();
+    let mut heads: Vec<(String, String)> = Vec::new();
+    for source in config
+        .sources
+        .iter()
+        .filter(|s| s.source_type == SourceType::Git)
+    {
+        let key = source.path.to_string_lossy().to_string();
+        let head = match git::head(&source.path) {
+            Ok(head) => head,
+            Err(e) => {
+                report.skip(&source.path, e.to_string());
+                continue;
+            }
+        };
+        let base = match since_commit {
+            Some(sha) if !git::has_commit(&source.path, sha) => {
+                anyhow::bail!(
+                    "--since-commit {} is not a commit in {}",
+                    sha,
+                    source.path.display()
+                );
+            }
+            Some(sha) => Some(sha.to_string()),
+            None => get_source_commit(&client, config, &key)
+                .await?
+                .filter(|sha| git::has_commit(&source.path, sha)),
+        };
+        heads.push((key, head.clone()));
+        let Some(base) = base else {
+            continue;
+        };
+
+        for f in source_files(source, config.canonical_paths) {
+            diffed.insert(f.path.to_string_lossy().to_string());
+        }
+        let stored = |p: PathBuf| {
+            if config.canonical_paths {
+                normalize_path(&p)
             } else {
+                p
+            }
+        };
+        let mut failed = false;
+        for change in git::changes(&source.path, &base, &head)? {
+            let (old, new) = match change {
+                git::Change::Upsert(p) => (None, Some(stored(p))),
+                git::Change::Delete(p) => (Some(stored(p)), None),
+                git::Change::Rename { from, to } => (Some(stored(from)), Some(stored(to))),
+            };
+            let file = new
+                .as_ref()
+                .and_then(|p| all_files.iter().find(|f| &f.path == p));
+            // Rows under the old and the new path are both replaced (the new one
+            // can have rows already when --since-commit goes back further)
+            let mut replaced: Vec<String> = old
+                .iter()
+                .chain(&new)
+                .map(|p| p.to_string_lossy().to_string())
+                .collect();
+            replaced.dedup();
+            match file {
+                Some(file) => {
+                    let Some((deleted, n)) =
+                        replace_or_skip(&client, config, file, &replaced, &mut report).await?
+                    else {
+                        failed = true;
+                        continue;
+                    };
+                    chunks_deleted += deleted;
+                    chunks_added += n;
+                    if deleted > 0 {
+                        updated_files += 1;
+                        if !config.quiet {
+                            println!(
+                                "  ♻️  {}: {} old → {} new chunks",
+                                file.path.display(),
+                                deleted,
+                                n
+                            );
+                        }
+                    } else {
+                        new_files += 1;
+                        if !config.quiet {
+                            println!("  ✨ {}: {} chunks", file.path.display(), n);
+                        }
+                    }
+                }
+                None => {
+                    let mut deleted = 0;
+                    for path in &replaced {
+                        deleted += delete_chunks_for(&client, config, path).await?;
+                    }
+                    if deleted > 0 {
+                        chunks_deleted += deleted;
+                        removed_files += 1;
+                        if !config.quiet {
+                            println!("  🗑️  {}: {} chunks removed", replaced.join(", "), deleted);
+                        }
+                    }
+                }
+            }
+        }
+        // Left at the old commit, so the next run diffs from there and retries
+        if failed {
+            heads.pop();
+        }
+    }
+
+    // Rows stored under another route to a file (before canonical_paths, or
+    // through a symlink) are moved to its resolved path instead of duplicated
+    let mut aliases: std::collections::HashMap<String, Vec<String>> =
+        std::collections::HashMap::new();
+    if config.canonical_paths {
+        for stored in indexed_state.keys() {
+            let resolved = normalize_path(Path::new(stored))
+                .to_string_lossy()
+                .to_string();
+            if resolved != *stored && on_disk.contains(&resolved) {
+                aliases.entry(resolved).or_default().push(stored.clone());
+            }
+        }
+    }
+
+    for file in &all_files {
+        let filepath = &file.path;
+        let path_str = filepath.to_string_lossy().to_string();
+        if diffed.contains(&path_str) {
+            continue;
+        }
+        let Some(mtime) = file_mtime(filepath) else {
+            continue;
+        };
+
+        if let Some(indexed_mtime) = indexed_state.get(&path_str) {
+            // File was indexed before — re-index unless it still has the mtime
+            // recorded then. Rows from before source_mtime existed always re-index.
+            if *indexed_mtime == Some(mtime) {
+                continue;
+            }
+            // Touched but not edited (a backup, a sync client): record the new
+            // mtime so the next run skips it on mtime alone
+            if config.content_hash && unchanged_content(&client, config, &path_str).await? {
+                set_source_mtime(&client, config, &path_str, mtime).await?;
                 continue;
             }
 
-            // Modified — delete old chunks and re-index
-            let deleted = delete_chunks_for(&client, config, &path_str).await?;
-            chunks_deleted += deleted;
+            if file.append_only {
+                if let Some(n) = index_appended(&client, config, file, &mut report).await? {
+                    chunks_added += n;
+                    updated_files += 1;
+                    if !config.quiet {
+                        println!("  ➕ {}: +{} appended chunks", filepath.display(), n);
+                    }
+                    continue;
+                }
+            }
 
-            let n = if source_type == "transcript" {
-                index_transcript_file(&client, config, filepath).await?
-            } else {
-                index_markdown_file(&client, config, filepath, label).await?
+            // Modified — replace the old chunks, keeping embeddings of unchanged ones
+            let Some((deleted, n)) = replace_or_skip(
+                &client,
+                config,
+                file,
+                std::slice::from_ref(&path_str),
+                &mut report,
+            )
+            .await?
+            else {
+                continue;
+            };
+            chunks_deleted += deleted;
+            chunks_added += n;
+            updated_files += 1;
+            if !config.quiet {
+                println!(
+                    "  ♻️  {}: {} old → {} new chunks",
+                    filepath.display(),
+                    deleted,
+                    n
+                );
+            }
+        } else if let Some(old_paths) = aliases
+            .get(&path_str)
+            .cloned()
+            .or_else(|| renamed_from(filepath, &indexed_state, &on_disk).map(|p| vec![p]))
+        {
+            // Same file under a new dated name, or another route to it — replace rather than duplicate
+            let Some((deleted, n)) =
+                replace_or_skip(&client, config, file, &old_paths, &mut report).await?
+            else {
+                continue;
             };
+            chunks_deleted += deleted;
             chunks_added += n;
             updated_files += 1;
-            println!("  ♻️  {}: {} old → {} new chunks", filepath.display(), deleted, n);
+            if !config.quiet {
+                println!(
+                    "  ♻️  {} → {}: {} old → {} new chunks",
+                    old_paths.join(", "),
+                    filepath.display(),
+                    deleted,
+                    n
+                );
+            }
         } else {
             // New file
-            let n = if source_type == "transcript" {
-                index_transcript_file(&client, config, filepath).await?
-            } else {
-                index_markdown_file(&client, config, filepath, label).await?
+            let Some((_, n)) = replace_or_skip(&client, config, file, &[], &mut report).await?
+            else {
+                continue;
             };
             if n > 0 {
                 chunks_added += n;
                 new_files += 1;
-                println!("  ✨ {}: {} chunks", filepath.display(), n);
+                if !config.quiet {
+                    println!("  ✨ {}: {} chunks", filepath.display(), n);
+                }
             }
         }
     }
 
-    if new_files == 0 && updated_files == 0 {
-        println!("Nothing new to index.");
-    } else {
-        println!(
-            "\n✅ {} new, {} updated | +{} chunks, -{} old",
-            new_files, updated_files, chunks_added, chunks_deleted
-        );
+    // Files deleted from disk since they were indexed. Only paths under a
+    // configured source count, so stdin documents and dropped sources stay.
+    let roots: Vec<PathBuf> = config
+        .sources
+        .iter()
+        .map(|s| {
+            if config.canonical_paths {
+                normalize_path(&s.path)
+            } else {
+                s.path.clone()
+            }
+        })
+        .collect();
+    let mut gone: Vec<&String> = indexed_state
+        .keys()
+        .filter(|p| !on_disk.contains(*p) && !Path::new(p).exists())
+        .filter(|p| roots.iter().any(|r| Path::new(p).starts_with(r)))
+        .collect();
+    gone.sort();
+    let mut stale_files = 0;
+    let mut stale_chunks = 0;
+    for path in gone {
+        // Zero when a git diff or rename above already replaced them
+        let deleted = delete_chunks_for(&client, config, path).await?;
+        if deleted == 0 {
+            continue;
+        }
+        stale_files += 1;
+        stale_chunks += deleted;
+        if !config.quiet {
+            println!("  🗑️  {}: {} chunks removed", path, deleted);
+        }
+    }
+    if stale_files > 0 {
+        if !config.quiet {
+            println!(
+                "  🗑️  {} removed files, -{} stale chunks",
+                stale_files, stale_chunks
+            );
+        }
+        removed_files += stale_files;
+        chunks_deleted += stale_chunks;
     }
 
-    Ok(())
-}
-
-// --- Helpers ---
-
-async fn index_markdown_dir(client: &Client, config: &Config, source: &Source) -> Result<usize> {
-    let dir = &source.path;
-    if !dir.exists() {
-        eprintln!("  ⚠️  Directory not found: {}", dir.display());
-        return Ok(0);
+    for (source, head) in &heads {
+        set_source_commit(&client, config, source, head).await?;
+    }
+    if new_files > 0 || updated_files > 0 || removed_files > 0 {
+        ResultCache::invalidate(config);
     }
 
-    let label = source.source_label.as_deref().unwrap_or("daily_note");
-    let mut total = 0;
+    // Strict mode is left to `IncrementalSummary::finish`, after the closing line
+    report.finish(false)?;
 
-    let mut entries: Vec<_> = std::fs::read_dir(dir)?
-        .filter_map(|e| e.ok())
-        .filter(|e| e.path().extension().map_or(false, |ext| ext == "md"))
-        .collect();
-    entries.sort_by_key(|e| e.path());
+    Ok(IncrementalSummary {
+        new_files,
+        updated_files,
+        removed_files,
+        chunks_added,
+        chunks_deleted,
+        skipped_files: report.skipped.len(),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum StdinFormat {
+    Markdown,
+    Transcript,
+}
 
-    for entry in entries {
-        let n = index_markdown_file(client, config, &entry.path(), label).await?;
-        println!("  {}: {} chunks", entry.file_name().to_string_lossy(), n);
-        total += n;
+/// Poll the sources every `interval` and run an incremental index whenever a
+/// file was added, removed or modified since the last look. Polling instead of
+/// filesystem events, so it behaves the same on network mounts (NFS, CIFS) and
+/// in containers without inotify. A failed run is reported and retried on the
+/// next poll; the loop only ends with the process.
+pub async fn run_watch(config: &Config, interval: Duration) -> Result<()> {
+    println!(
+        "👀 Watching {} source(s), polling every {}s (Ctrl-C to stop)\n",
+        config.sources.len(),
+        interval.as_secs()
+    );
+    let mut indexed: Option<std::collections::BTreeMap<PathBuf, Option<i64>>> = None;
+    loop {
+        let snapshot = collect_all_files(config)
+            .into_iter()
+            .map(|f| {
+                let mtime = file_mtime(&f.path);
+                (f.path, mtime)
+            })
+            .collect();
+        if indexed.as_ref() != Some(&snapshot) {
+            if indexed.is_some() && !config.quiet {
+                println!("🔄 Changes detected, indexing...\n");
+            }
+            let run = match run_incremental_index(config, false, false, None).await {
+                Ok(summary) => summary.finish(config),
+                Err(e) => Err(e),
+            };
+            match run {
+                Ok(()) => indexed = Some(snapshot),
+                Err(e) => eprintln!(
+                    "⚠️  Incremental index failed: {} (retrying in {}s)",
+                    e,
+                    interval.as_secs()
+                ),
+            }
+        }
+        tokio::time::sleep(interval).await;
     }
-    Ok(total)
 }
 
-async fn index_markdown_file(
-    client: &Client,
+/// Index all of stdin as one document at a virtual `source_path`, replacing
+/// whatever was previously stored under that path.
+pub async fn run_stdin_index(
     config: &Config,
-    filepath: &Path,
-    source_label: &str,
-) -> Result<usize> {
-    let text = std::fs::read_to_string(filepath)?;
-    if text.len() < 30 {
-        return Ok(0);
+    label: &str,
+    path: &str,
+    format: StdinFormat,
+    source_date: Option<&str>,
+) -> Result<()> {
+    let mut text = String::new();
+    std::io::Read::read_to_string(&mut std::io::stdin(), &mut text)?;
+
+    let client = db::connect(&config.db_url, None).await?;
+    let config = &settle_query_mode(config, &client).await?;
+
+    let file = SourceFile {
+        path: PathBuf::from(path),
+        kind: match format {
+            StdinFormat::Markdown => FileKind::Markdown,
+            StdinFormat::Transcript => FileKind::Transcript,
+        },
+        label: label.to_string(),
+        source_date: source_date.map(str::to_string),
+        append_only: false,
+    };
+    warm_up_model(config).await?;
+    let mut report = IndexReport::new(false, false);
+    let reuse = stored_embeddings(&client, config, path).await?;
+    let deleted = delete_chunks_for(&client, config, path).await?;
+    let n = index_text(&client, config, &text, &file, None, &reuse, &mut report).await?;
+    ResultCache::invalidate(config);
+
+    if deleted > 0 {
+        println!("✅ {}: {} old → {} new chunks", path, deleted, n);
+    } else {
+        println!("✅ {}: {} chunks", path, n);
     }
+    report.finish(config.strict)?;
+    Ok(())
+}
 
-    let chunks = chunk::chunk_text(&text, 800);
-    let source_date = chunk::extract_date(&filepath.file_name().unwrap_or_default().to_string_lossy());
-    let path_str = filepath.to_string_lossy().to_string();
-    let mut count = 0;
+/// Embed every chunk of this agent stored without an embedding, `concurrency`
+/// batches at a time, walking the rows in id order so a chunk the server
+/// rejects is reported once and left for the next run.
+pub async fn run_embed_pending(config: &Config, concurrency: usize) -> Result<()> {
+    let client = db::connect(&config.db_url, None).await?;
+    let config = &Config {
+        embed_concurrency: concurrency,
+        ..settle_query_mode(config, &client).await?
+    };
 
-    for c in &chunks {
-        let embedding = get_embedding(&config.ollama_url, &config.model, c).await?;
-        let embedding_str = format!(
-            "[{}]",
-            embedding.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(",")
+    let batch = config.embed_batch_size.max(1);
+    let page = batch * concurrency;
+    let (mut embedded, mut rejected) = (0, 0);
+    let mut after = String::new();
+    loop {
+        let mut select = binds(config);
+        let sql = format!(
+            "SELECT id::text, content, encode(content_compressed, 'hex'), title, {} FROM {}
+             WHERE agent_id = {} AND {} IS NULL AND id::text > {}
+             ORDER BY id::text LIMIT {}",
+            if config.store_embed_text {
+                "embed_text"
+            } else {
+                "NULL"
+            },
+            config.table_name(),
+            select.bind(&config.agent_id),
+            config.embedding_column,
+            select.bind(&after),
+            page
         );
-        let date_clause = match &source_date {
-            Some(d) => format!("'{}'", d),
-            None => "NULL".to_string(),
+        let mut ids = Vec::new();
+        let mut texts = Vec::new();
+        for row in search::read_rows(&client, &sql, &select, None).await? {
+            let get = |i: usize| row.get(i).and_then(|v| v.as_deref());
+            let content = match (get(1), get(2)) {
+                (Some(text), _) => text.to_string(),
+                (None, Some(hex)) => compress::decompress_hex(hex)?,
+                (None, None) => continue,
+            };
+            // Same text insert_chunks would have embedded
+            texts.push(match get(4) {
+                Some(stored) => stored.to_string(),
+                None => embed_text(config, get(3), None, &content),
+            });
+            ids.push(get(0).unwrap_or_default().to_string());
+        }
+        let Some(last) = ids.last() else {
+            break;
         };
+        after = last.clone();
 
-        let sql = format!(
-            "INSERT INTO {}.chunks (id, content, source, source_path, source_date, agent_id, embedding)
-             VALUES ('{}', $escape${}$escape$, '{}', '{}', {}, '{}', '{}'::vector)",
-            config.schema,
-            Uuid::new_v4(),
-            c,
-            source_label,
-            path_str,
-            date_clause,
-            config.agent_id,
-            embedding_str,
-        );
-        client.simple_query(&sql).await?;
-        count += 1;
+        let vectors = get_embeddings_batch(config, &texts, batch).await?;
+        if let Some(expected) = config.model_dimension {
+            if let Some(got) = vectors
+                .iter()
+                .flatten()
+                .map(|e| e.len())
+                .find(|&n| n != expected)
+            {
+                anyhow::bail!(
+                    "Model '{}' returned {}-dimensional embeddings but model_dimension is {}",
+                    config.model,
+                    got,
+                    expected
+                );
+            }
+        }
+        for (id, vector) in ids.iter().zip(vectors) {
+            let Some(vector) = vector else {
+                rejected += 1;
+                continue;
+            };
+            let mut update = binds(config);
+            let sql = format!(
+                "UPDATE {} SET {} = {}::{}, updated_at = now() WHERE id = {}::uuid",
+                config.table_name(),
+                config.embedding_column,
+                update.bind(&vector_literal(&vector)),
+                config.vector_type.sql(),
+                update.bind(id)
+            );
+            execute(&client, &sql, &update).await?;
+            embedded += 1;
+        }
+        if !config.quiet {
+            println!("  {} chunk(s) embedded", embedded);
+        }
     }
 
-    Ok(count)
+    if embedded > 0 {
+        ResultCache::invalidate(config);
+    }
+    println!(
+        "\n✅ Embedded {} pending chunk(s) for agent '{}'",
+        embedded, config.agent_id
+    );
+    if rejected > 0 {
+        eprintln!(
+            "⚠️  {} chunk(s) rejected by the embedding server are still pending",
+            rejected
+        );
+        if config.strict {
+            anyhow::bail!("Strict mode: {} chunk(s) were not embedded", rejected);
+        }
+    }
+    Ok(())
 }
 
-async fn index_transcript_dir(client: &Client, config: &Config, source: &Source) -> Result<usize> {
-    let dir = &source.path;
-    if !dir.exists() {
-        eprintln!("  ⚠️  Directory not found: {}", dir.display());
-        return Ok(0);
+/// Move stored chunks from paths under `from` to the same paths under `to`,
+/// after the files themselves were moved, so they needn't be re-embedded.
+/// A file already indexed at its new path keeps those chunks; its old ones
+/// are deleted as duplicates.
+pub async fn run_merge(config: &Config, from: &str, to: &str) -> Result<()> {
+    let from = from.trim_end_matches('/');
+    let mut to = to.trim_end_matches('/').to_string();
+    if config.canonical_paths {
+        if let Ok(p) = Path::new(&to).canonicalize() {
+            to = p.to_string_lossy().into_owned();
+        }
+    }
+    if from.is_empty() || to.is_empty() {
+        anyhow::bail!("--from and --to must be non-empty path prefixes");
+    }
+    if from == to {
+        anyhow::bail!("--from and --to are the same path");
     }
 
-    let mut total = 0;
-    let mut entries: Vec<_> = std::fs::read_dir(dir)?
-        .filter_map(|e| e.ok())
-        .filter(|e| e.path().extension().map_or(false, |ext| ext == "jsonl"))
-        .collect();
-    entries.sort_by_key(|e| e.path());
+    let client = db::connect(&config.db_url, None).await?;
+    let config = &settle_query_mode(config, &client).await?;
+
+    let table = config.table_name();
+    let moved_path = |col: &str, binds: &mut Binds| {
+        format!(
+            "{} || substr({}, {})",
+            binds.bind(&to),
+            col,
+            from.chars().count() + 1
+        )
+    };
 
-    for entry in entries {
-        let n = index_transcript_file(client, config, &entry.path()).await?;
-        println!("  {}: {} chunks", entry.file_name().to_string_lossy(), n);
-        total += n;
+    let mut removing = binds(config);
+    let remove = format!(
+        "DELETE FROM {table} old WHERE old.agent_id = {agent} AND {under}
+         AND EXISTS (SELECT 1 FROM {table} new WHERE new.agent_id = old.agent_id AND new.source_path = {moved})",
+        table = table,
+        agent = removing.bind(&config.agent_id),
+        under = path_under("old.source_path", from, &mut removing),
+        moved = moved_path("old.source_path", &mut removing),
+    );
+    let mut updating = binds(config);
+    let update = format!(
+        "UPDATE {} SET source_path = {}, updated_at = now() WHERE agent_id = {} AND {}",
+        table,
+        moved_path("source_path", &mut updating),
+        updating.bind(&config.agent_id),
+        path_under("source_path", from, &mut updating),
+    );
+
+    client.simple_query("BEGIN").await?;
+    let merged = async {
+        let removed = execute(&client, &remove, &removing).await?;
+        let updated = execute(&client, &update, &updating).await?;
+        client.simple_query("COMMIT").await?;
+        anyhow::Ok((removed, updated))
+    }
+    .await;
+    if merged.is_err() {
+        // Best effort: a lost connection has rolled back already
+        let _ = client.simple_query("ROLLBACK").await;
+    }
+    let (removed, updated) = merged?;
+    ResultCache::invalidate(config);
+
+    println!("🔀 {} → {}", from, to);
+    println!("  Updated: {} chunk(s) now under the new path", updated);
+    println!(
+        "  Removed: {} duplicate chunk(s) of files already indexed there",
+        removed
+    );
+    if updated == 0 && removed == 0 {
+        println!(
+            "  (no chunks for agent '{}' are stored under {})",
+            config.agent_id, from
+        );
     }
-    Ok(total)
+    Ok(())
+}
+
+/// SQL matching `col` equal to the path `prefix` or anything below it (not
+/// `/notes-old` for `/notes`).
+fn path_under(col: &str, prefix: &str, binds: &mut Binds) -> String {
+    format!(
+        "({col} = {prefix} OR (left({col}, {len}) = {prefix} AND substr({col}, {len} + 1, 1) = '/'))",
+        col = col,
+        prefix = binds.bind(prefix),
+        len = prefix.chars().count()
+    )
 }
 
-async fn index_transcript_file(client: &Client, config: &Config, filepath: &Path) -> Result<usize> {
-    let text = std::fs::read_to_string(filepath)?;
-    let chunks = chunk::parse_transcript(&text);
-    let source_date = chunk::extract_date(&filepath.file_stem().unwrap_or_default().to_string_lossy());
-    let path_str = filepath.to_string_lossy().to_string();
-    let mut count = 0;
+/// Which of this agent's chunks `delete` removes; set filters all apply.
+pub struct DeleteFilter {
+    /// Chunks with this `source` label
+    pub source: Option<String>,
+    /// Chunks of this file, or of any file below this directory
+    pub path: Option<String>,
+    /// Chunks dated before this day (`YYYY-MM-DD`); undated chunks are kept
+    pub before: Option<NaiveDate>,
+    /// Every chunk of the agent; only valid without the other filters
+    pub all: bool,
+}
 
-    for c in &chunks {
-        let embedding = get_embedding(&config.ollama_url, &config.model, c).await?;
-        let embedding_str = format!(
-            "[{}]",
-            embedding.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(",")
+/// `WHERE` clause for the chunks `filter` selects, its values in `binds`.
+/// Refuses an empty filter without `all`, so a forgotten flag can't wipe an
+/// agent's memory.
+fn delete_predicate(config: &Config, filter: &DeleteFilter, binds: &mut Binds) -> Result<String> {
+    let mut predicate = format!("agent_id = {}", binds.bind(&config.agent_id));
+    if let Some(source) = &filter.source {
+        predicate.push_str(&format!(" AND source = {}", binds.bind(source)));
+    }
+    if let Some(path) = &filter.path {
+        predicate.push_str(&format!(" AND {}", path_under("source_path", path, binds)));
+    }
+    if let Some(before) = &filter.before {
+        predicate.push_str(&format!(
+            " AND source_date < {}::date",
+            binds.bind(&before.to_string())
+        ));
+    }
+    let narrowed = filter.source.is_some() || filter.path.is_some() || filter.before.is_some();
+    if narrowed == filter.all {
+        anyhow::bail!(
+            "Give --source, --path or --before to choose what to delete, or --all for every chunk of agent '{}'",
+            config.agent_id
         );
-        let date_clause = match &source_date {
-            Some(d) => format!("'{}'", d),
-            None => "NULL".to_string(),
-        };
+    }
+    Ok(predicate)
+}
 
-        let sql = format!(
-            "INSERT INTO {}.chunks (id, content, source, source_path, source_date, agent_id, embedding)
-             VALUES ('{}', $escape${}$escape$, 'transcript', '{}', {}, '{}', '{}'::vector)",
-            config.schema,
-            Uuid::new_v4(),
-            c,
-            path_str,
-            date_clause,
-            config.agent_id,
-            embedding_str,
+/// Delete the chunks `filter` selects and report how many went. Without
+/// `yes` nothing is deleted; the count that would be is reported instead.
+/// `--all` and `--path` also drop the matching incremental-index state, so
+/// a later index starts those files from scratch.
+pub async fn run_delete(config: &Config, mut filter: DeleteFilter, yes: bool) -> Result<()> {
+    if let Some(path) = &mut filter.path {
+        *path = path.trim_end_matches('/').to_string();
+        if path.is_empty() {
+            anyhow::bail!("--path must be a non-empty path");
+        }
+    }
+    let client = db::connect(&config.db_url, None).await?;
+    let config = &settle_query_mode(config, &client).await?;
+    let mut chunks = binds(config);
+    let predicate = delete_predicate(config, &filter, &mut chunks)?;
+    let table = config.table_name();
+
+    if !yes {
+        let sql = format!("SELECT count(*)::text FROM {} WHERE {}", table, predicate);
+        let count = first_value(&client, &sql, &chunks)
+            .await?
+            .unwrap_or_default();
+        anyhow::bail!(
+            "Would delete {} chunk(s) of agent '{}'; run again with --yes to delete them",
+            count,
+            config.agent_id
         );
-        client.simple_query(&sql).await?;
-        count += 1;
     }
 
-    Ok(count)
-}
+    let state = state_table(config);
+    let mut lookup = binds(config);
+    let sql = format!("SELECT to_regclass({})::text", lookup.bind(&state));
+    let has_state = first_value(&client, &sql, &lookup).await?.is_some();
+    let mut state_binds = binds(config);
+    let state_delete = match &filter.path {
+        Some(path) if has_state => Some(path_under("source", path, &mut state_binds)),
+        None if has_state && filter.all => Some("TRUE".to_string()),
+        _ => None,
+    }
+    .map(|state_predicate| {
+        format!(
+            "DELETE FROM {} WHERE agent_id = {} AND {}",
+            state,
+            state_binds.bind(&config.agent_id),
+            state_predicate
+        )
+    });
 
-async fn get_indexed_state(client: &Client, config: &Config) -> Result<std::collections::HashMap<String, i64>> {
-    let sql = format!(
-        "SELECT source_path, EXTRACT(EPOCH FROM MAX(created_at))::bigint
-         FROM {}.chunks WHERE agent_id = '{}' GROUP BY source_path",
-        config.schema, config.agent_id
+    client.simple_query("BEGIN").await?;
+    let deleted = async {
+        let deleted = execute(
+            &client,
+            &format!("DELETE FROM {} WHERE {}", table, predicate),
+            &chunks,
+        )
+        .await?;
+        if let Some(sql) = &state_delete {
+            execute(&client, sql, &state_binds).await?;
+        }
+        client.simple_query("COMMIT").await?;
+        anyhow::Ok(deleted)
+    }
+    .await;
+    if deleted.is_err() {
+        // Best effort: a lost connection has rolled back already
+        let _ = client.simple_query("ROLLBACK").await;
+    }
+    let deleted = deleted?;
+    ResultCache::invalidate(config);
+
+    println!(
+        "🗑️  Deleted {} chunk(s) of agent '{}'",
+        deleted, config.agent_id
     );
-    let msgs = client.simple_query(&sql).await?;
-    let mut state = std::collections::HashMap::new();
+    Ok(())
+}
 
-    for msg in &msgs {
-        if let tokio_postgres::SimpleQueryMessage::Row(row) = msg {
-            if let (Some(path), Some(ts)) = (row.get(0), row.get(1)) {
-                if let Ok(ts) = ts.parse::<i64>() {
-                    state.insert(path.to_string(), ts);
-                }
-            }
-        }
+/// Rows affected, from a statement's `CommandComplete`.
+fn command_count(msgs: &[tokio_postgres::SimpleQueryMessage]) -> u64 {
+    msgs.iter()
+        .find_map(|m| match m {
+            tokio_postgres::SimpleQueryMessage::CommandComplete(n) => Some(*n),
+            _ => None,
+        })
+        .unwrap_or(0)
+}
+
+// --- Helpers ---
+
+/// With `model_warmup`, embed a throwaway string so an on-demand model is
+/// loaded before the first real batch instead of during it.
+async fn warm_up_model(config: &Config) -> Result<()> {
+    if !config.model_warmup || config.defer_embeddings {
+        return Ok(());
     }
-    Ok(state)
+    if !config.quiet {
+        println!("  ⏳ Warming up model '{}'...", config.model);
+    }
+    let started = std::time::Instant::now();
+    get_embedding(config, "warmup")
+        .await
+        .map_err(|e| anyhow::anyhow!("Model warmup failed for '{}': {}", config.model, e))?;
+    if !config.quiet {
+        println!(
+            "  ✅ Model ready in {:.1}s\n",
+            started.elapsed().as_secs_f64()
+        );
+    }
+    Ok(())
 }
 
-async fn delete_chunks_for(client: &Client, config: &Config, source_path: &str) -> Result<usize> {
-    let sql = format!(
-        "DELETE FROM {}.chunks WHERE source_path = '{}' AND agent_id = '{}'",
-        config.schema, source_path, config.agent_id
-    );
-    let msgs = client.simple_query(&sql).await?;
-    // Count CommandComplete messages
-    for msg in &msgs {
-        if let tokio_postgres::SimpleQueryMessage::CommandComplete(n) = msg {
-            return Ok(*n as usize);
+/// Files that were passed over during a run, reported at the end instead of
+/// aborting the whole index.
+pub struct IndexReport {
+    pub skipped: Vec<(String, String)>,
+    /// Chunk size distribution, printed by `finish` when requested
+    chunk_stats: Option<ChunkStats>,
+    /// Time per phase, printed by `finish` for `--profile`
+    profile: Option<Profile>,
+    /// Set while a file's new chunks take the place of stored ones: a chunk
+    /// the embedding server rejects then fails the file, keeping the stored
+    /// rows, instead of the file being stored without that chunk.
+    reject_fails_file: bool,
+}
+
+/// Where an index run spends its time.
+enum Phase {
+    /// Reading, extracting and chunking files
+    Read,
+    /// Embedding requests
+    Embed,
+    /// Chunk inserts
+    Db,
+}
+
+/// Wall time accumulated per phase, for `--profile`.
+struct Profile {
+    started: Instant,
+    read: Duration,
+    embed: Duration,
+    db: Duration,
+    /// Files were prepared on worker threads, so `read` is summed across
+    /// threads and overlaps the other phases
+    parallel: bool,
+}
+
+/// What the chunkers produced during a run, for `--chunk-report`.
+#[derive(Default)]
+struct ChunkStats {
+    /// Character count of every chunk produced
+    sizes: Vec<usize>,
+    /// Chunks at or over `max_chars` (a single paragraph can't be split further)
+    at_cap: usize,
+    /// Markdown chunks dropped as too small
+    dropped: usize,
+}
+
+impl IndexReport {
+    fn new(chunk_report: bool, profile: bool) -> Self {
+        IndexReport {
+            skipped: Vec::new(),
+            chunk_stats: chunk_report.then(ChunkStats::default),
+            profile: profile.then(|| Profile {
+                started: Instant::now(),
+                read: Duration::ZERO,
+                embed: Duration::ZERO,
+                db: Duration::ZERO,
+                parallel: false,
+            }),
+            reject_fails_file: false,
         }
     }
-    Ok(0)
-}
 
-fn collect_all_files(config: &Config) -> Vec<(std::path::PathBuf, String, String)> {
-    let mut files = Vec::new();
+    /// Add the time since `started` to `phase`, when profiling.
+    fn time(&mut self, phase: Phase, started: Instant) {
+        let Some(profile) = &mut self.profile else {
+            return;
+        };
+        let bucket = match phase {
+            Phase::Read => &mut profile.read,
+            Phase::Embed => &mut profile.embed,
+            Phase::Db => &mut profile.db,
+        };
+        *bucket += started.elapsed();
+    }
 
-    for source in &config.sources {
-        match source.source_type.as_str() {
-            "single_file" => {
-                if source.path.exists() {
-                    let label = source.source_label.as_deref().unwrap_or("single_file");
-                    files.push((source.path.clone(), "markdown".to_string(), label.to_string()));
-                }
-            }
-            "markdown_dir" => {
-                if let Ok(entries) = std::fs::read_dir(&source.path) {
-                    let label = source.source_label.as_deref().unwrap_or("daily_note");
-                    for entry in entries.flatten() {
-                        if entry.path().extension().map_or(false, |e| e == "md") {
-                            files.push((entry.path(), "markdown".to_string(), label.to_string()));
-                        }
-                    }
-                }
-            }
-            "transcript_dir" => {
-                if let Ok(entries) = std::fs::read_dir(&source.path) {
-                    for entry in entries.flatten() {
-                        if entry.path().extension().map_or(false, |e| e == "jsonl") {
-                            files.push((entry.path(), "transcript".to_string(), "transcript".to_string()));
-                        }
-                    }
-                }
-            }
-            _ => {}
+    /// Fold in a report kept by a worker thread.
+    fn absorb(&mut self, other: IndexReport) {
+        self.skipped.extend(other.skipped);
+        if let (Some(stats), Some(theirs)) = (&mut self.chunk_stats, other.chunk_stats) {
+            stats.sizes.extend(theirs.sizes);
+            stats.at_cap += theirs.at_cap;
+            stats.dropped += theirs.dropped;
+        }
+        if let (Some(profile), Some(theirs)) = (&mut self.profile, other.profile) {
+            profile.read += theirs.read;
+            profile.embed += theirs.embed;
+            profile.db += theirs.db;
+            profile.parallel = true;
         }
     }
-    files
+
+    fn record_chunks<'a>(
+        &mut self,
+        chunks: impl IntoIterator<Item = &'a str>,
+        dropped: usize,
+        max_chars: usize,
+    ) {
+        let Some(stats) = &mut self.chunk_stats else {
+            return;
+        };
+        for c in chunks {
+            let n = c.chars().count();
+            if n >= max_chars {
+                stats.at_cap += 1;
+            }
+            stats.sizes.push(n);
+        }
+        stats.dropped += dropped;
+    }
+
+    fn print_chunk_report(&self) {
+        let Some(stats) = &self.chunk_stats else {
+            return;
+        };
+        println!("\n📏 Chunk report");
+        if stats.sizes.is_empty() {
+            println!(
+                "  No chunks produced ({} dropped as too small)",
+                stats.dropped
+            );
+            return;
+        }
+        let mut sizes = stats.sizes.clone();
+        sizes.sort_unstable();
+        let pct = |p: usize| sizes[(sizes.len() - 1) * p / 100];
+        let mean = sizes.iter().sum::<usize>() as f64 / sizes.len() as f64;
+        println!("  Chunks:  {} (mean {:.0} chars)", sizes.len(), mean);
+        println!(
+            "  Chars:   min {}  p10 {}  median {}  p90 {}  p99 {}  max {}",
+            sizes[0],
+            pct(10),
+            pct(50),
+            pct(90),
+            pct(99),
+            sizes[sizes.len() - 1]
+        );
+        println!("  At cap:  {} chunk(s) of max_chars or more", stats.at_cap);
+        println!("  Dropped: {} chunk(s) too small to keep", stats.dropped);
+    }
+
+    fn print_profile(&self) {
+        let Some(profile) = &self.profile else {
+            return;
+        };
+        let wall = profile.started.elapsed();
+        let share = |d: Duration| 100.0 * d.as_secs_f64() / wall.as_secs_f64().max(f64::EPSILON);
+        println!("\n⏱️  Profile ({:.2}s wall)", wall.as_secs_f64());
+        for (label, d) in [
+            ("Read/chunk", profile.read),
+            ("Embedding", profile.embed),
+            ("DB inserts", profile.db),
+        ] {
+            println!(
+                "  {:<11} {:>8.2}s  {:>5.1}%",
+                label,
+                d.as_secs_f64(),
+                share(d)
+            );
+        }
+        if profile.parallel {
+            println!("  (read/chunk is summed across worker threads and overlaps embedding)");
+        } else {
+            let other = wall.saturating_sub(profile.read + profile.embed + profile.db);
+            println!(
+                "  {:<11} {:>8.2}s  {:>5.1}%",
+                "Other",
+                other.as_secs_f64(),
+                share(other)
+            );
+        }
+    }
+
+    fn skip(&mut self, path: &Path, reason: impl Into<String>) {
+        let reason = reason.into();
+        eprintln!("  ⏭️  {}: skipped, {}", path.display(), reason);
+        self.skipped.push((path.display().to_string(), reason));
+    }
+
+    fn print_summary(&self) {
+        if self.skipped.is_empty() {
+            return;
+        }
+        eprintln!(
+            "\n⚠️  {} file(s) skipped or incomplete:",
+            self.skipped.len()
+        );
+        for (path, reason) in &self.skipped {
+            eprintln!("  {}: {}", path, reason);
+        }
+    }
+
+    /// Print the summary; under `strict`, anything skipped fails the run.
+    fn finish(&self, strict: bool) -> Result<()> {
+        self.print_chunk_report();
+        self.print_profile();
+        self.print_summary();
+        if strict && !self.skipped.is_empty() {
+            anyhow::bail!(
+                "Strict mode: {} file(s) were not fully indexed",
+                self.skipped.len()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Read a source file, or record why it was skipped. Invalid UTF-8 is decoded
+/// lossily (with a warning) when `lossy_utf8` is set.
+fn read_source_file(config: &Config, path: &Path, report: &mut IndexReport) -> Option<String> {
+    let bytes = match std::fs::read(path) {
+        Ok(b) => b,
+        Err(e) => {
+            report.skip(path, format!("unreadable ({})", e));
+            return None;
+        }
+    };
+    match String::from_utf8(bytes) {
+        Ok(text) => Some(text),
+        Err(e) if config.lossy_utf8 => {
+            eprintln!(
+                "  ⚠️  {}: invalid UTF-8 at byte {}, invalid sequences replaced",
+                path.display(),
+                e.utf8_error().valid_up_to()
+            );
+            Some(String::from_utf8_lossy(e.as_bytes()).into_owned())
+        }
+        Err(e) => {
+            report.skip(
+                path,
+                format!(
+                    "not valid UTF-8 (byte {}); set lossy_utf8: true to index anyway",
+                    e.utf8_error().valid_up_to()
+                ),
+            );
+            None
+        }
+    }
+}
+
+/// Markdown chunks of `text`, honouring `collapse_whitespace` and `stable_chunks`.
+pub fn markdown_chunks(config: &Config, text: &str, max_chars: usize) -> Vec<chunk::Chunk> {
+    markdown_chunks_counted(config, text, max_chars).0
+}
+
+/// `markdown_chunks`, plus how many were dropped as too small. YAML
+/// frontmatter is left out; offsets still count from the start of `text`.
+fn markdown_chunks_counted(
+    config: &Config,
+    text: &str,
+    max_chars: usize,
+) -> (Vec<chunk::Chunk>, usize) {
+    let (_, body) = chunk::parse_frontmatter(text);
+    let skipped = text[..text.len() - body.len()].chars().count();
+    let (mut chunks, dropped) = chunk::chunk_markdown_counted(
+        body,
+        &chunk::ChunkOptions {
+            max_chars,
+            max_tokens: (config.chunk_strategy == ChunkStrategy::Tokens)
+                .then_some(config.max_tokens),
+            overlap_chars: config.chunk_overlap,
+            collapse_whitespace: config.collapse_whitespace,
+            stable: config.stable_chunks,
+        },
+    );
+    for c in &mut chunks {
+        c.start += skipped;
+        c.end += skipped;
+    }
+    (chunks, dropped)
+}
+
+/// `.jsonl` transcripts, plus `.json` chat exports (ChatGPT `conversations.json`).
+fn is_jsonl(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "jsonl")
+}
+
+pub fn is_transcript_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == "jsonl" || ext == "json")
+}
+
+/// Chunk texts `path` would be indexed as, with the parser picked from its
+/// extension; nothing is stored. For `search --like-file`.
+pub fn example_chunks(config: &Config, path: &Path) -> Result<Vec<String>> {
+    if !path.is_file() {
+        anyhow::bail!("Example file {} does not exist", path.display());
+    }
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase());
+    let kind = match ext.as_deref() {
+        Some("epub") => FileKind::Epub,
+        Some("docx") => FileKind::Docx,
+        _ if is_transcript_file(path) => FileKind::Transcript,
+        _ => FileKind::Markdown,
+    };
+    let file = SourceFile {
+        path: path.to_path_buf(),
+        kind,
+        label: String::new(),
+        source_date: None,
+        append_only: false,
+    };
+    let mut report = IndexReport::new(false, false);
+    match prepare_file(config, &file, &mut report) {
+        Some(p) if !p.rows.is_empty() => Ok(p.rows.into_iter().map(|r| r.content).collect()),
+        _ => anyhow::bail!("{} has no text to search with", path.display()),
+    }
+}
+
+/// Chunk markdown `text` for `file` (whose path need not exist on disk). The
+/// source's `source_date` is used when the filename carries no date.
+fn prepare_markdown(
+    config: &Config,
+    text: &str,
+    file: &SourceFile,
+    mtime: Option<i64>,
+    report: &mut IndexReport,
+) -> Option<PreparedFile> {
+    let filepath = file.path.as_path();
+    let len = text.trim().chars().count();
+    if len < config.min_file_chars {
+        report.skip(
+            filepath,
+            format!(
+                "{} chars is below min_file_chars ({})",
+                len, config.min_file_chars
+            ),
+        );
+        return None;
+    }
+
+    let (chunks, dropped) = markdown_chunks_counted(config, text, config.max_chars);
+    report.record_chunks(
+        chunks.iter().map(|c| c.content.as_str()),
+        dropped,
+        config.max_chars,
+    );
+    if chunks.is_empty() && len > 0 {
+        report.skip(filepath, "no chunk above the minimum chunk size");
+    }
+    let filename = filepath.file_name().unwrap_or_default().to_string_lossy();
+    // Extracted documents: offsets would point into the extracted text, not the
+    // file, and a chunk deep inside a chapter is prefixed with the chapter title
+    let extracted = matches!(file.kind, FileKind::Epub | FileKind::Docx);
+    let frontmatter = chunk::parse_frontmatter(text).0;
+    // Frontmatter keys kept with every chunk of the file
+    let kept: Vec<(String, serde_json::Value)> = frontmatter
+        .iter()
+        .flat_map(|fm| {
+            config
+                .frontmatter_keys
+                .iter()
+                .filter_map(move |k| Some((k.clone(), fm.get(k.as_str())?)))
+        })
+        .filter_map(|(k, v)| Some((k, serde_json::to_value(v).ok()?)))
+        .collect();
+    let headings = if extracted || config.store_metadata || config.section_context {
+        chunk::heading_positions(text)
+    } else {
+        Vec::new()
+    };
+    let rows = chunks
+        .into_iter()
+        .map(|c| {
+            let metadata = config.store_metadata.then(|| {
+                let mut m =
+                    serde_json::json!({ "headings": chunk::heading_path(&headings, c.start) });
+                if !extracted {
+                    m["start"] = c.start.into();
+                    m["end"] = c.end.into();
+                }
+                for (k, v) in &kept {
+                    m[k] = v.clone();
+                }
+                m
+            });
+            let section = if config.section_context {
+                chunk::breadcrumb(&headings, c.start)
+            } else {
+                None
+            };
+            if !extracted {
+                return NewChunk {
+                    content: c.content,
+                    start_offset: Some(c.start),
+                    end_offset: Some(c.end),
+                    metadata,
+                    section,
+                };
+            }
+            let chapter = headings.iter().rev().find(|h| h.pos <= c.start);
+            let content = match chapter {
+                Some(h) if !c.content.starts_with('#') => format!("[{}] {}", h.title, c.content),
+                _ => c.content,
+            };
+            NewChunk {
+                content,
+                start_offset: None,
+                end_offset: None,
+                metadata,
+                section,
+            }
+        })
+        .collect();
+
+    Some(PreparedFile {
+        mtime,
+        source_date: chunk::extract_date(&filename)
+            .or_else(|| frontmatter.as_ref().and_then(chunk::frontmatter_date))
+            .or_else(|| file.source_date.clone()),
+        title: Some(chunk::extract_title(
+            text,
+            &filepath.file_stem().unwrap_or_default().to_string_lossy(),
+        )),
+        rows,
+        indexed_bytes: None,
+        content_hash: None,
+    })
+}
+
+/// Parse transcript `text` for `file` (whose path need not exist on disk).
+/// The source's `source_date` is used when the filename carries no date.
+fn prepare_transcript(
+    config: &Config,
+    text: &str,
+    file: &SourceFile,
+    mtime: Option<i64>,
+    report: &mut IndexReport,
+) -> PreparedFile {
+    let filepath = file.path.as_path();
+    let chunks = chunk::parse_transcript_chunks(text, &config.include_roles);
+    report.record_chunks(
+        chunks.iter().map(|c| c.content.as_str()),
+        0,
+        chunk::TRANSCRIPT_CHUNK_CHARS,
+    );
+    if chunks.is_empty() && !text.trim().is_empty() {
+        report.skip(filepath, "no indexable transcript messages");
+    }
+    let rows = chunks
+        .into_iter()
+        .map(|c| {
+            let metadata = config.store_metadata.then(|| {
+                let mut m = serde_json::json!({ "roles": c.roles, "messages": c.messages });
+                if let Some(n) = c.conversation {
+                    m["conversation"] = n.into();
+                }
+                m
+            });
+            NewChunk {
+                content: c.content,
+                start_offset: None,
+                end_offset: None,
+                metadata,
+                section: None,
+            }
+        })
+        .collect();
+
+    PreparedFile {
+        mtime,
+        source_date: chunk::extract_date(
+            &filepath.file_stem().unwrap_or_default().to_string_lossy(),
+        )
+        .or_else(|| file.source_date.clone()),
+        title: None,
+        rows,
+        indexed_bytes: None,
+        content_hash: None,
+    }
+}
+
+/// A file read and chunked but not yet embedded: the CPU-only half of
+/// indexing, which `threads` runs ahead of embedding on a worker pool.
+struct PreparedFile {
+    /// File modification time (µs since the epoch) taken before reading
+    mtime: Option<i64>,
+    source_date: Option<String>,
+    title: Option<String>,
+    rows: Vec<NewChunk>,
+    /// For `append_only` files, bytes of complete lines read, recorded once stored
+    indexed_bytes: Option<i64>,
+    /// SHA-256 of the file as read, with `content_hash`
+    content_hash: Option<String>,
+}
+
+/// A prepared file, with the skips and chunk stats recorded while preparing it.
+type Prepared = (SourceFile, Option<PreparedFile>, IndexReport);
+
+/// Read and chunk `files` on a rayon pool of `threads` workers, a window at a
+/// time, while the caller embeds and stores what is already done. Files arrive
+/// in order; the bounded channel keeps the workers at most a window ahead.
+fn prepare_ahead(
+    config: &Config,
+    files: Vec<SourceFile>,
+    threads: usize,
+    chunk_report: bool,
+    profile: bool,
+) -> Result<tokio::sync::mpsc::Receiver<Prepared>> {
+    use rayon::prelude::*;
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(|e| anyhow::anyhow!("Cannot start {} worker threads: {}", threads, e))?;
+    let window = threads * 4;
+    let (tx, rx) = tokio::sync::mpsc::channel(window);
+    let config = config.clone();
+
+    std::thread::spawn(move || {
+        for batch in files.chunks(window) {
+            let ready: Vec<Prepared> = pool.install(|| {
+                batch
+                    .par_iter()
+                    .map(|file| {
+                        let mut report = IndexReport::new(chunk_report, profile);
+                        let started = Instant::now();
+                        let prepared = prepare_file(&config, file, &mut report);
+                        report.time(Phase::Read, started);
+                        (file.clone(), prepared, report)
+                    })
+                    .collect()
+            });
+            for item in ready {
+                // The receiver is gone once indexing has failed
+                if tx.blocking_send(item).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    Ok(rx)
+}
+
+/// Embed and insert a prepared file's chunks.
+async fn store_prepared(
+    client: &Client,
+    config: &Config,
+    file: &SourceFile,
+    prepared: PreparedFile,
+    reuse: &EmbeddingCache,
+    report: &mut IndexReport,
+) -> Result<usize> {
+    let meta = FileMeta {
+        path: &file.path,
+        source_label: &file.label,
+        source_date: prepared.source_date,
+        title: prepared.title,
+        source_mtime: prepared.mtime,
+        content_hash: prepared.content_hash,
+    };
+    let n = store_file(client, config, &meta, prepared.rows, reuse, report).await?;
+    if let Some(bytes) = prepared.indexed_bytes {
+        set_indexed_bytes(client, config, &file.path.to_string_lossy(), bytes).await?;
+    }
+    Ok(n)
+}
+
+/// Index only the lines appended to an `append_only` file since it was last
+/// indexed, keeping its existing chunks. `None` when that isn't possible (no
+/// recorded length, or the file shrank) and the file must be re-indexed whole.
+async fn index_appended(
+    client: &Client,
+    config: &Config,
+    file: &SourceFile,
+    report: &mut IndexReport,
+) -> Result<Option<usize>> {
+    let path_str = file.path.to_string_lossy().to_string();
+    let Some(done) = get_indexed_bytes(client, config, &path_str).await? else {
+        return Ok(None);
+    };
+    let started = Instant::now();
+    let mtime = file_mtime(&file.path);
+    let Ok(bytes) = std::fs::read(&file.path) else {
+        return Ok(None);
+    };
+    let done = done as usize;
+    if done == 0 || bytes.len() < done || bytes[done - 1] != b'\n' {
+        return Ok(None);
+    }
+    let tail = &bytes[done..];
+    let end = complete_lines_len(tail);
+    let text = String::from_utf8_lossy(&tail[..end]);
+
+    // A tail of tool calls alone is nothing to add, not a file to report as skipped
+    let prepared = if chunk::parse_transcript(&text, &config.include_roles).is_empty() {
+        None
+    } else {
+        prepare_text(config, &text, file, mtime, report)
+    };
+    report.time(Phase::Read, started);
+    report.reject_fails_file = true;
+    let stored = match prepared {
+        Some(p) => store_prepared(client, config, file, p, &EmbeddingCache::new(), report).await,
+        None => Ok(0),
+    };
+    report.reject_fails_file = false;
+    // Recording the new length would pass over lines that were never stored;
+    // the whole file is indexed again instead, or skipped if that fails too
+    let Ok(n) = stored else {
+        return Ok(None);
+    };
+    set_indexed_bytes(client, config, &path_str, (done + end) as i64).await?;
+    // Earlier chunks take the new mtime too, so the file reads as up to date
+    if let Some(us) = mtime {
+        set_source_mtime(client, config, &path_str, us).await?;
+    }
+    Ok(Some(n))
+}
+
+/// Stamp every row of `source_path` with mtime `us` (µs since the epoch).
+async fn set_source_mtime(
+    client: &Client,
+    config: &Config,
+    source_path: &str,
+    us: i64,
+) -> Result<()> {
+    let mut binds = binds(config);
+    let sql = format!(
+        "UPDATE {} SET source_mtime = to_timestamp({}::double precision / 1000000)
+         WHERE agent_id = {} AND source_path = {}",
+        config.table_name(),
+        us,
+        binds.bind(&config.agent_id),
+        binds.bind(source_path)
+    );
+    execute(client, &sql, &binds).await?;
+    Ok(())
+}
+
+/// Whether every row of `source_path` carries the `content_hash` the file on
+/// disk has now. False when any row has none (indexed before `content_hash`,
+/// or partly through `append_only`).
+async fn unchanged_content(client: &Client, config: &Config, source_path: &str) -> Result<bool> {
+    let mut binds = binds(config);
+    let sql = format!(
+        "SELECT CASE WHEN COUNT(content_hash) = COUNT(*) AND MIN(content_hash) = MAX(content_hash)
+                     THEN MAX(content_hash) END
+         FROM {} WHERE agent_id = {} AND source_path = {}",
+        config.table_name(),
+        binds.bind(&config.agent_id),
+        binds.bind(source_path)
+    );
+    let Some(stored) = first_value(client, &sql, &binds).await? else {
+        return Ok(false);
+    };
+    Ok(file_hash(Path::new(source_path)).is_some_and(|hash| hash == stored))
+}
+
+/// Per-file values shared by every chunk row of that file.
+struct FileMeta<'a> {
+    path: &'a Path,
+    source_label: &'a str,
+    source_date: Option<String>,
+    title: Option<String>,
+    /// File modification time (µs since the epoch) when it was read
+    source_mtime: Option<i64>,
+    content_hash: Option<String>,
+}
+
+/// A chunk ready to be embedded and stored. Offsets are `None` when the
+/// content doesn't map back onto the file (transcripts).
+struct NewChunk {
+    content: String,
+    start_offset: Option<usize>,
+    end_offset: Option<usize>,
+    /// Provenance stored in `metadata` when `store_metadata` is on
+    metadata: Option<serde_json::Value>,
+    /// Breadcrumb of enclosing headings, embedded ahead of the content with `section_context`
+    section: Option<String>,
+}
+
+/// `source` label of the per-file rows written when `document_vectors` is on.
+pub const DOCUMENT_SOURCE: &str = "document_summary";
+
+/// Characters of a document embedded for its `document_summary` row.
+const DOCUMENT_VECTOR_CHARS: usize = 8000;
+
+/// Rows per INSERT statement. A file's chunks go in as few statements as
+/// this allows; 1000 rows of 14 columns stay well under Postgres' 65535 bind
+/// parameters.
+const ROWS_PER_INSERT: usize = 1000;
+
+/// Insert a file's chunks and, with `document_vectors`, one more row whose
+/// embedding covers the start of the whole document. Returns the chunk count.
+async fn store_file(
+    client: &Client,
+    config: &Config,
+    meta: &FileMeta<'_>,
+    rows: Vec<NewChunk>,
+    reuse: &EmbeddingCache,
+    report: &mut IndexReport,
+) -> Result<usize> {
+    let document: String = if config.document_vectors {
+        let joined = rows
+            .iter()
+            .map(|r| r.content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        joined.chars().take(DOCUMENT_VECTOR_CHARS).collect()
+    } else {
+        String::new()
+    };
+
+    let n = insert_chunks(client, config, meta, rows, reuse, report).await?;
+    if n > 0 && !document.is_empty() {
+        let doc_meta = FileMeta {
+            path: meta.path,
+            source_label: DOCUMENT_SOURCE,
+            source_date: meta.source_date.clone(),
+            title: meta.title.clone(),
+            source_mtime: meta.source_mtime,
+            content_hash: meta.content_hash.clone(),
+        };
+        let row = NewChunk {
+            content: document,
+            start_offset: None,
+            end_offset: None,
+            metadata: None,
+            section: None,
+        };
+        insert_chunks(client, config, &doc_meta, vec![row], reuse, report).await?;
+    }
+    Ok(n)
+}
+
+/// Embed `rows` in batches and insert them, returning how many were stored.
+async fn insert_chunks(
+    client: &Client,
+    config: &Config,
+    meta: &FileMeta<'_>,
+    rows: Vec<NewChunk>,
+    reuse: &EmbeddingCache,
+    report: &mut IndexReport,
+) -> Result<usize> {
+    if let Some(max) = config.max_chunks_per_file {
+        if rows.len() > max {
+            report.skip(
+                meta.path,
+                format!(
+                    "{} chunks exceeds max_chunks_per_file ({})",
+                    rows.len(),
+                    max
+                ),
+            );
+            return Ok(0);
+        }
+    }
+    let path_str = meta.path.to_string_lossy().to_string();
+    let texts: Vec<String> = rows
+        .iter()
+        .map(|r| {
+            embed_text(
+                config,
+                meta.title.as_deref(),
+                r.section.as_deref(),
+                &r.content,
+            )
+        })
+        .collect();
+    // Only texts without a stored embedding go to the server
+    let mut embeddings: Vec<Option<Vec<f64>>> =
+        texts.iter().map(|t| reuse.get(t).cloned()).collect();
+    let missing: Vec<usize> = (0..texts.len())
+        .filter(|&i| embeddings[i].is_none())
+        .collect();
+    if !missing.is_empty() && !config.defer_embeddings {
+        let missing_texts: Vec<String> = missing.iter().map(|&i| texts[i].clone()).collect();
+        let started = Instant::now();
+        let fresh = get_embeddings_batch(config, &missing_texts, config.embed_batch_size).await?;
+        report.time(Phase::Embed, started);
+        for (i, e) in missing.into_iter().zip(fresh) {
+            embeddings[i] = e;
+        }
+    }
+    if let Some(expected) = config.model_dimension {
+        if let Some(got) = embeddings
+            .iter()
+            .flatten()
+            .map(|e| e.len())
+            .find(|&n| n != expected)
+        {
+            anyhow::bail!(
+                "Model '{}' returned {}-dimensional embeddings but model_dimension is {}",
+                config.model,
+                got,
+                expected
+            );
+        }
+    }
+
+    // The inlined INSERT below quotes every string; prepared and COPY loads bind them
+    let quote = |s: &str| format!("'{}'", s.replace('\'', "''"));
+    let date_clause = meta
+        .source_date
+        .as_deref()
+        .map_or("NULL".to_string(), quote);
+    let title_clause = meta.title.as_deref().map_or("NULL".to_string(), quote);
+    let mtime_clause = match meta.source_mtime {
+        Some(us) => format!("to_timestamp({}::double precision / 1000000)", us),
+        None => "NULL".to_string(),
+    };
+    let rejected = embeddings.iter().filter(|e| e.is_none()).count();
+    if rejected > 0 && !config.defer_embeddings {
+        let reason = format!("{} chunk(s) rejected by the embedding server", rejected);
+        if report.reject_fails_file {
+            anyhow::bail!(reason);
+        }
+        report
+            .skipped
+            .push((meta.path.display().to_string(), reason));
+    }
+    if config.bulk_copy || config.query_mode == QueryMode::Prepared {
+        let started = Instant::now();
+        let count = if config.bulk_copy {
+            copy_chunks(client, config, meta, &rows, embeddings, &texts).await?
+        } else {
+            insert_prepared(client, config, meta, &rows, embeddings, &texts).await?
+        };
+        report.time(Phase::Db, started);
+        telemetry::record_indexed_chunks(count);
+        return Ok(count);
+    }
+    // Optional columns, present only when their feature is on
+    let mut extra_columns = String::new();
+    for (on, column) in [
+        (config.detect_language, "lang"),
+        (config.store_metadata, "metadata"),
+        (config.store_embed_text, "embed_text"),
+        (config.content_hash, "content_hash"),
+    ] {
+        if on {
+            extra_columns.push_str(", ");
+            extra_columns.push_str(column);
+        }
+    }
+    let hash_clause = meta
+        .content_hash
+        .as_deref()
+        .map_or("NULL".to_string(), quote);
+
+    let started = Instant::now();
+    let mut tuples = Vec::with_capacity(rows.len());
+    for ((row, embedding), text) in rows.iter().zip(embeddings).zip(&texts) {
+        // Deferred rows are stored without one, for `embed-pending`
+        let embedding_clause = match embedding {
+            Some(embedding) => format!(
+                "'{}'::{}",
+                vector_literal(&embedding),
+                config.vector_type.sql()
+            ),
+            None if config.defer_embeddings => "NULL".to_string(),
+            None => continue,
+        };
+        let offset = |o: Option<usize>| o.map_or("NULL".to_string(), |o| o.to_string());
+        let mut extra_values = String::new();
+        if config.detect_language {
+            match detect_language(&row.content) {
+                Some(code) => extra_values.push_str(&format!(", '{}'", code)),
+                None => extra_values.push_str(", NULL"),
+            }
+        }
+        if config.store_metadata {
+            match &row.metadata {
+                Some(metadata) => extra_values.push_str(&format!(
+                    ", '{}'::jsonb",
+                    metadata.to_string().replace('\'', "''")
+                )),
+                None => extra_values.push_str(", NULL"),
+            }
+        }
+        if config.store_embed_text {
+            match *text != row.content {
+                true => extra_values.push_str(&format!(", {}", quote(text))),
+                false => extra_values.push_str(", NULL"),
+            }
+        }
+        if config.content_hash {
+            extra_values.push_str(&format!(", {}", hash_clause));
+        }
+        let content_clause = if config.compress_content {
+            format!(
+                "NULL, decode('{}', 'hex')",
+                compress::compress_hex(&row.content)?
+            )
+        } else {
+            format!("{}, NULL", quote(&row.content))
+        };
+
+        tuples.push(format!(
+            "('{}', {}, {}, {}, {}, {}, {}, {}, {}, {}, {}{})",
+            Uuid::new_v4(),
+            content_clause,
+            quote(meta.source_label),
+            quote(&path_str),
+            date_clause,
+            quote(&config.agent_id),
+            embedding_clause,
+            offset(row.start_offset),
+            offset(row.end_offset),
+            title_clause,
+            mtime_clause,
+            extra_values,
+        ));
+    }
+    // One round trip per file, in statements of at most ROWS_PER_INSERT rows
+    for group in tuples.chunks(ROWS_PER_INSERT) {
+        let sql = format!(
+            "INSERT INTO {} (id, content, content_compressed, source, source_path, source_date, agent_id, {}, start_offset, end_offset, title, source_mtime{})
+             VALUES {}",
+            config.table_name(),
+            config.embedding_column,
+            extra_columns,
+            group.join(",\n"),
+        );
+        client
+            .simple_query(&sql)
+            .await
+            .inspect_err(|_| telemetry::record_db_error())?;
+    }
+    let count = tuples.len();
+    report.time(Phase::Db, started);
+    telemetry::record_indexed_chunks(count);
+
+    Ok(count)
+}
+
+/// `insert_chunks` for `bulk_copy`: the same rows and columns, streamed in
+/// one binary COPY.
+async fn copy_chunks(
+    client: &Client,
+    config: &Config,
+    meta: &FileMeta<'_>,
+    rows: &[NewChunk],
+    embeddings: Vec<Option<Vec<f64>>>,
+    texts: &[String],
+) -> Result<usize> {
+    if config.vector_type != VectorType::Vector {
+        anyhow::bail!("bulk_copy needs vector_type: vector (halfvec has no binary COPY support)");
+    }
+    let (columns, values) = chunk_values(config, meta, rows, embeddings, texts, false)?;
+    if values.is_empty() {
+        return Ok(0);
+    }
+    let n = copy::copy_rows(client, &config.table_name(), &columns, &values)
+        .await
+        .inspect_err(|_| telemetry::record_db_error())?;
+    Ok(n as usize)
+}
+
+/// `insert_chunks` for `query_mode: prepared`: the same multi-row INSERTs as
+/// the simple path, with every value bound instead of inlined.
+async fn insert_prepared(
+    client: &Client,
+    config: &Config,
+    meta: &FileMeta<'_>,
+    rows: &[NewChunk],
+    embeddings: Vec<Option<Vec<f64>>>,
+    texts: &[String],
+) -> Result<usize> {
+    let (columns, values) = chunk_values(config, meta, rows, embeddings, texts, true)?;
+    if values.is_empty() {
+        return Ok(0);
+    }
+    // Parameter n of the statement, for row r and column i
+    let placeholder = |n: usize, i: usize| {
+        if columns[i] == config.embedding_column {
+            format!("${}::text::{}", n, config.vector_type.sql())
+        } else {
+            format!("${}", n)
+        }
+    };
+    for group in values.chunks(ROWS_PER_INSERT) {
+        let tuples: Vec<String> = (0..group.len())
+            .map(|r| {
+                let row: Vec<String> = (0..columns.len())
+                    .map(|i| placeholder(r * columns.len() + i + 1, i))
+                    .collect();
+                format!("({})", row.join(", "))
+            })
+            .collect();
+        let params: Vec<&(dyn ToSql + Sync)> = group
+            .iter()
+            .flatten()
+            .map(|v| v.as_ref() as &(dyn ToSql + Sync))
+            .collect();
+        client
+            .execute(
+                &format!(
+                    "INSERT INTO {} ({}) VALUES {}",
+                    config.table_name(),
+                    columns.join(", "),
+                    tuples.join(", ")
+                ),
+                &params,
+            )
+            .await
+            .inspect_err(|_| telemetry::record_db_error())?;
+    }
+    Ok(values.len())
+}
+
+/// Columns and per-chunk values for `copy_chunks` and `insert_prepared`, the
+/// same ones the inlined INSERT writes. Embeddings go as pgvector values, or
+/// with `vector_as_text` as their text form for the statement to cast.
+fn chunk_values(
+    config: &Config,
+    meta: &FileMeta<'_>,
+    rows: &[NewChunk],
+    embeddings: Vec<Option<Vec<f64>>>,
+    texts: &[String],
+    vector_as_text: bool,
+) -> Result<(Vec<String>, Vec<Vec<copy::Value>>)> {
+    let mut columns: Vec<String> = [
+        "id",
+        "content",
+        "content_compressed",
+        "source",
+        "source_path",
+        "source_date",
+        "agent_id",
+        &config.embedding_column,
+        "start_offset",
+        "end_offset",
+        "title",
+        "source_mtime",
+    ]
+    .iter()
+    .map(|c| c.to_string())
+    .collect();
+    for (on, column) in [
+        (config.detect_language, "lang"),
+        (config.store_metadata, "metadata"),
+        (config.store_embed_text, "embed_text"),
+        (config.content_hash, "content_hash"),
+    ] {
+        if on {
+            columns.push(column.to_string());
+        }
+    }
+
+    let offset = |o: Option<usize>| o.map(|o| o as i32);
+    let mut values: Vec<Vec<copy::Value>> = Vec::with_capacity(rows.len());
+    for ((row, embedding), text) in rows.iter().zip(embeddings).zip(texts) {
+        // Deferred rows are stored without one, for `embed-pending`
+        if embedding.is_none() && !config.defer_embeddings {
+            continue;
+        }
+        let (content, compressed) = if config.compress_content {
+            (None, Some(compress::compress(&row.content)?))
+        } else {
+            (Some(row.content.clone()), None)
+        };
+        let mut v: Vec<copy::Value> = vec![
+            Box::new(Uuid::new_v4()),
+            Box::new(content),
+            Box::new(compressed),
+            Box::new(meta.source_label.to_string()),
+            Box::new(meta.path.to_string_lossy().into_owned()),
+            Box::new(meta.source_date.clone().map(copy::Date)),
+            Box::new(config.agent_id.clone()),
+            match (embedding, vector_as_text) {
+                (Some(e), true) => Box::new(Some(vector_literal(&e))),
+                (Some(e), false) => Box::new(Some(pgvector::Vector::from(
+                    e.into_iter().map(|x| x as f32).collect::<Vec<_>>(),
+                ))),
+                (None, _) => Box::new(None::<String>),
+            },
+            Box::new(offset(row.start_offset)),
+            Box::new(offset(row.end_offset)),
+            Box::new(meta.title.clone()),
+            Box::new(meta.source_mtime.map(copy::timestamp)),
+        ];
+        if config.detect_language {
+            v.push(Box::new(detect_language(&row.content)));
+        }
+        if config.store_metadata {
+            v.push(Box::new(row.metadata.clone()));
+        }
+        if config.store_embed_text {
+            v.push(Box::new((*text != row.content).then(|| text.clone())));
+        }
+        if config.content_hash {
+            v.push(Box::new(meta.content_hash.clone()));
+        }
+        values.push(v);
+    }
+    Ok((columns, values))
+}
+
+/// `config` with `query_mode: auto` settled for its database, so `binds`
+/// can tell which way to run statements.
+async fn settle_query_mode(config: &Config, client: &Client) -> Result<Config> {
+    let mut config = config.clone();
+    config.query_mode = if db::use_prepared(&config.db_url, client, config.query_mode).await? {
+        QueryMode::Prepared
+    } else {
+        QueryMode::Simple
+    };
+    Ok(config)
+}
+
+/// What gets embedded for a chunk: the content, after its `section`
+/// breadcrumb line when there is one, and with `prepend_title` all of it as
+/// `[<title>] ...`. Only `content` is ever displayed.
+fn embed_text(
+    config: &Config,
+    title: Option<&str>,
+    section: Option<&str>,
+    content: &str,
+) -> String {
+    let text = match section {
+        Some(section) => format!("{}\n{}", section, content),
+        None => content.to_string(),
+    };
+    match (title, config.prepend_title) {
+        (Some(title), true) => format!("[{}] {}", title, text),
+        _ => text,
+    }
+}
+
+/// ISO 639-3 code of whatlang's best guess at the language of `text`. Its
+/// `is_reliable` flag rejects most chunk-sized English, so every guess is kept.
+fn detect_language(text: &str) -> Option<&'static str> {
+    whatlang::detect(text).map(|info| info.lang().code())
+}
+
+/// source_path → `source_mtime` (µs) its chunks were indexed at; `None` when
+/// any chunk predates the column or the paths disagree.
+async fn get_indexed_state(client: &Client, config: &Config) -> Result<IndexedState> {
+    let mut binds = binds(config);
+    let sql = format!(
+        "SELECT source_path,
+                CASE WHEN COUNT(source_mtime) = COUNT(*) AND MIN(source_mtime) = MAX(source_mtime)
+                     THEN (EXTRACT(EPOCH FROM MAX(source_mtime)) * 1000000)::bigint::text END
+         FROM {} WHERE agent_id = {} GROUP BY source_path",
+        config.table_name(),
+        binds.bind(&config.agent_id)
+    );
+    let mut state = std::collections::HashMap::new();
+
+    for row in search::read_rows(client, &sql, &binds, None).await? {
+        if let [Some(path), mtime] = row.as_slice() {
+            state.insert(
+                path.clone(),
+                mtime.as_deref().and_then(|ts| ts.parse::<i64>().ok()),
+            );
+        }
+    }
+    Ok(state)
+}
+
+type IndexedState = std::collections::HashMap<String, Option<i64>>;
+
+/// `schema.index_state`: per-source bookkeeping such as a git source's last indexed commit.
+fn state_table(config: &Config) -> String {
+    format!("{}.index_state", config.schema)
+}
+
+/// Commit a git source was last indexed at.
+async fn get_source_commit(
+    client: &Client,
+    config: &Config,
+    source: &str,
+) -> Result<Option<String>> {
+    let mut binds = binds(config);
+    let sql = format!(
+        "SELECT commit_sha FROM {} WHERE agent_id = {} AND source = {}",
+        state_table(config),
+        binds.bind(&config.agent_id),
+        binds.bind(source)
+    );
+    first_value(client, &sql, &binds).await
+}
+
+/// Bytes of an `append_only` file indexed so far, keyed by its path.
+async fn get_indexed_bytes(client: &Client, config: &Config, path: &str) -> Result<Option<i64>> {
+    let mut binds = binds(config);
+    let sql = format!(
+        "SELECT indexed_bytes::text FROM {} WHERE agent_id = {} AND source = {}",
+        state_table(config),
+        binds.bind(&config.agent_id),
+        binds.bind(path)
+    );
+    Ok(first_value(client, &sql, &binds)
+        .await?
+        .and_then(|s| s.parse().ok()))
+}
+
+async fn set_indexed_bytes(client: &Client, config: &Config, path: &str, bytes: i64) -> Result<()> {
+    let mut binds = binds(config);
+    let sql = format!(
+        "INSERT INTO {} (agent_id, source, indexed_bytes) VALUES ({}, {}, {})
+         ON CONFLICT (agent_id, source) DO UPDATE SET indexed_bytes = EXCLUDED.indexed_bytes, updated_at = now()",
+        state_table(config),
+        binds.bind(&config.agent_id),
+        binds.bind(path),
+        bytes
+    );
+    execute(client, &sql, &binds).await?;
+    Ok(())
+}
+
+async fn set_source_commit(
+    client: &Client,
+    config: &Config,
+    source: &str,
+    sha: &str,
+) -> Result<()> {
+    let mut binds = binds(config);
+    let sql = format!(
+        "INSERT INTO {} (agent_id, source, commit_sha) VALUES ({}, {}, {})
+         ON CONFLICT (agent_id, source) DO UPDATE SET commit_sha = EXCLUDED.commit_sha, updated_at = now()",
+        state_table(config),
+        binds.bind(&config.agent_id),
+        binds.bind(source),
+        binds.bind(sha)
+    );
+    execute(client, &sql, &binds).await?;
+    Ok(())
+}
+
+/// Embeddings already stored for a path, keyed by the exact text that was
+/// embedded, so re-indexing an edited file only embeds the chunks that changed.
+type EmbeddingCache = std::collections::HashMap<String, Vec<f64>>;
+
+async fn stored_embeddings(
+    client: &Client,
+    config: &Config,
+    source_path: &str,
+) -> Result<EmbeddingCache> {
+    let mut binds = binds(config);
+    let sql = format!(
+        "SELECT content, encode(content_compressed, 'hex'), title, {col}::text, {}
+         FROM {} WHERE source_path = {} AND agent_id = {} AND {col} IS NOT NULL",
+        if config.store_embed_text {
+            "embed_text"
+        } else {
+            "NULL"
+        },
+        config.table_name(),
+        binds.bind(source_path),
+        binds.bind(&config.agent_id),
+        col = config.embedding_column
+    );
+    let mut cache = EmbeddingCache::new();
+
+    for row in search::read_rows(client, &sql, &binds, None).await? {
+        let get = |i: usize| row.get(i).and_then(|v| v.as_deref());
+        let content = match (get(0), get(1)) {
+            (Some(text), _) => text.to_string(),
+            (None, Some(hex)) => compress::decompress_hex(hex)?,
+            (None, None) => continue,
+        };
+        // Same key insert_chunks embeds under; a section breadcrumb is only
+        // known from a stored embed_text
+        let key = match get(4) {
+            Some(stored) => stored.to_string(),
+            None => embed_text(config, get(2), None, &content),
+        };
+        if let Some(vector) = get(3) {
+            cache.insert(key, parse_vector(vector));
+        }
+    }
+    Ok(cache)
+}
+
+async fn delete_chunks_for(client: &Client, config: &Config, source_path: &str) -> Result<usize> {
+    let mut binds = binds(config);
+    let sql = format!(
+        "DELETE FROM {} WHERE source_path = {} AND agent_id = {}",
+        config.table_name(),
+        binds.bind(source_path),
+        binds.bind(&config.agent_id)
+    );
+    Ok(execute(client, &sql, &binds).await? as usize)
+}
+
+/// `Binds` for bookkeeping queries: bound parameters once `query_mode` has
+/// settled on prepared statements, escaped literals otherwise.
+fn binds(config: &Config) -> Binds {
+    Binds::new(config.query_mode == QueryMode::Prepared)
+}
+
+/// Run a statement built with `binds`, returning the rows it affected.
+async fn execute(client: &Client, sql: &str, binds: &Binds) -> Result<u64> {
+    let count = if binds.prepared {
+        client.execute(sql, &binds.params()).await
+    } else {
+        client
+            .simple_query(sql)
+            .await
+            .map(|msgs| command_count(&msgs))
+    };
+    count.map_err(|e| {
+        telemetry::record_db_error();
+        e.into()
+    })
+}
+
+/// First column of the first row of a query built with `binds`.
+async fn first_value(client: &Client, sql: &str, binds: &Binds) -> Result<Option<String>> {
+    let rows = search::read_rows(client, sql, binds, None).await?;
+    Ok(rows
+        .into_iter()
+        .next()
+        .and_then(|row| row.into_iter().next().flatten()))
+}
+
+/// How a file's contents are turned into chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileKind {
+    Markdown,
+    Transcript,
+    Epub,
+    Docx,
+}
+
+/// A file to index, with the parser, label and fallback date its source assigns it.
+#[derive(Clone)]
+struct SourceFile {
+    path: PathBuf,
+    kind: FileKind,
+    label: String,
+    source_date: Option<String>,
+    /// Only ever appended to; incremental runs index just the new lines
+    append_only: bool,
+}
+
+async fn index_file(
+    client: &Client,
+    config: &Config,
+    file: &SourceFile,
+    reuse: &EmbeddingCache,
+    report: &mut IndexReport,
+) -> Result<usize> {
+    let started = Instant::now();
+    let prepared = prepare_file(config, file, report);
+    report.time(Phase::Read, started);
+    let Some(prepared) = prepared else {
+        return Ok(0);
+    };
+    store_prepared(client, config, file, prepared, reuse, report).await
+}
+
+/// Re-index `file` in place of the rows stored under `paths`, keeping the
+/// embeddings of unchanged chunks, in one transaction: a failure part way
+/// (the embedding server going away or rejecting a chunk, a rejected INSERT)
+/// rolls back to the old rows instead of leaving the file half indexed.
+/// Returns the rows deleted and added.
+async fn replace_file(
+    client: &Client,
+    config: &Config,
+    file: &SourceFile,
+    paths: &[String],
+    report: &mut IndexReport,
+) -> Result<(usize, usize)> {
+    client.simple_query("BEGIN").await?;
+    report.reject_fails_file = true;
+    let replaced = async {
+        let mut reuse = EmbeddingCache::new();
+        let mut deleted = 0;
+        for path in paths {
+            reuse.extend(stored_embeddings(client, config, path).await?);
+            deleted += delete_chunks_for(client, config, path).await?;
+        }
+        let added = index_file(client, config, file, &reuse, report).await?;
+        anyhow::Ok((deleted, added))
+    }
+    .await;
+    report.reject_fails_file = false;
+    match replaced {
+        Ok(counts) => {
+            client
+                .simple_query("COMMIT")
+                .await
+                .inspect_err(|_| telemetry::record_db_error())?;
+            Ok(counts)
+        }
+        Err(e) => {
+            // Best effort: a lost connection has rolled back already
+            let _ = client.simple_query("ROLLBACK").await;
+            Err(e)
+        }
+    }
+}
+
+/// `replace_file`, with a failure reported as a skipped file instead of
+/// ending the run; its rows are as they were and the next run retries it.
+/// A lost connection still ends the run, as every later file would fail too.
+async fn replace_or_skip(
+    client: &Client,
+    config: &Config,
+    file: &SourceFile,
+    paths: &[String],
+    report: &mut IndexReport,
+) -> Result<Option<(usize, usize)>> {
+    match replace_file(client, config, file, paths, report).await {
+        Ok(counts) => Ok(Some(counts)),
+        Err(e) if client.is_closed() => Err(e),
+        Err(e) => {
+            report.skip(&file.path, format!("{:#} (previous chunks kept)", e));
+            Ok(None)
+        }
+    }
+}
+
+async fn index_text(
+    client: &Client,
+    config: &Config,
+    text: &str,
+    file: &SourceFile,
+    mtime: Option<i64>,
+    reuse: &EmbeddingCache,
+    report: &mut IndexReport,
+) -> Result<usize> {
+    let started = Instant::now();
+    let prepared = prepare_text(config, text, file, mtime, report);
+    report.time(Phase::Read, started);
+    let Some(prepared) = prepared else {
+        return Ok(0);
+    };
+    store_prepared(client, config, file, prepared, reuse, report).await
+}
+
+/// Read (or extract) and chunk a file on disk.
+fn prepare_file(
+    config: &Config,
+    file: &SourceFile,
+    report: &mut IndexReport,
+) -> Option<PreparedFile> {
+    // Taken before reading, so an edit made while we read still looks newer next run
+    let mtime = file_mtime(&file.path);
+    let text = match file.kind {
+        FileKind::Epub | FileKind::Docx => {
+            let extracted = if file.kind == FileKind::Epub {
+                extract::epub_text(&file.path)
+            } else {
+                extract::docx_text(&file.path)
+            };
+            match extracted {
+                Ok(text) => text,
+                Err(e) => {
+                    report.skip(&file.path, format!("text extraction failed ({})", e));
+                    return None;
+                }
+            }
+        }
+        FileKind::Markdown | FileKind::Transcript => read_source_file(config, &file.path, report)?,
+    };
+    let mut prepared = prepare_text(config, &text, file, mtime, report)?;
+    if file.append_only {
+        prepared.indexed_bytes = Some(complete_lines_len(text.as_bytes()) as i64);
+    }
+    if config.content_hash {
+        // The text read is the file itself unless it was extracted or lossily decoded
+        prepared.content_hash = match file.kind {
+            FileKind::Markdown | FileKind::Transcript => Some(sha256_hex(text.as_bytes())),
+            FileKind::Epub | FileKind::Docx => file_hash(&file.path),
+        };
+    }
+    Some(prepared)
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// SHA-256 of the file at `path`, as stored in `content_hash`.
+fn file_hash(path: &Path) -> Option<String> {
+    std::fs::read(path).ok().map(|bytes| sha256_hex(&bytes))
+}
+
+/// Length of `bytes` up to and including the last newline: a line still being
+/// written is left for the next run.
+fn complete_lines_len(bytes: &[u8]) -> usize {
+    bytes.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1)
+}
+
+fn prepare_text(
+    config: &Config,
+    text: &str,
+    file: &SourceFile,
+    mtime: Option<i64>,
+    report: &mut IndexReport,
+) -> Option<PreparedFile> {
+    match file.kind {
+        FileKind::Markdown | FileKind::Epub | FileKind::Docx => {
+            prepare_markdown(config, text, file, mtime, report)
+        }
+        FileKind::Transcript => Some(prepare_transcript(config, text, file, mtime, report)),
+    }
+}
+
+/// Modification time in microseconds since the epoch.
+fn file_mtime(path: &Path) -> Option<i64> {
+    let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    Some(
+        modified
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()?
+            .as_micros() as i64,
+    )
+}
+
+/// Files a source contributes, sorted by path. Full and incremental indexing
+/// both go through here so each source type is handled identically. With
+/// `canonical`, paths are resolved (see `normalize_path`).
+fn source_files(source: &Source, canonical: bool) -> Vec<SourceFile> {
+    let label = source.label();
+    let mut files = Vec::new();
+
+    match source.source_type {
+        SourceType::SingleFile => {
+            if source.path.is_file() {
+                // A single transcript file is parsed as a transcript, not as markdown
+                let kind = if is_transcript_file(&source.path) {
+                    FileKind::Transcript
+                } else {
+                    FileKind::Markdown
+                };
+                files.push(SourceFile {
+                    path: source.path.clone(),
+                    kind,
+                    label: label.clone(),
+                    source_date: source.source_date.clone(),
+                    append_only: source.append_only && is_jsonl(&source.path),
+                });
+            }
+        }
+        SourceType::MarkdownDir => {
+            let mut paths = Vec::new();
+            markdown_files(&source.path, source.recursive, &mut paths);
+            for path in paths {
+                files.push(SourceFile {
+                    label: file_label(source, &path).unwrap_or_else(|| label.clone()),
+                    path,
+                    kind: FileKind::Markdown,
+                    source_date: None,
+                    append_only: false,
+                });
+            }
+        }
+        SourceType::Epub | SourceType::Docx => {
+            let (ext, kind) = if source.source_type == SourceType::Epub {
+                ("epub", FileKind::Epub)
+            } else {
+                ("docx", FileKind::Docx)
+            };
+            let paths: Vec<PathBuf> = if source.path.is_dir() {
+                std::fs::read_dir(&source.path)
+                    .map(|entries| entries.flatten().map(|e| e.path()).collect())
+                    .unwrap_or_default()
+            } else {
+                vec![source.path.clone()]
+            };
+            for path in paths {
+                if path.is_file()
+                    && path
+                        .extension()
+                        .is_some_and(|e| e.eq_ignore_ascii_case(ext))
+                {
+                    files.push(SourceFile {
+                        path,
+                        kind,
+                        label: label.clone(),
+                        source_date: source.source_date.clone(),
+                        append_only: false,
+                    });
+                }
+            }
+        }
+        SourceType::Git => match git::tracked_files(&source.path) {
+            Ok(paths) => {
+                for path in paths.into_iter().filter(|p| p.is_file()) {
+                    files.push(SourceFile {
+                        label: file_label(source, &path).unwrap_or_else(|| label.clone()),
+                        path,
+                        kind: FileKind::Markdown,
+                        source_date: None,
+                        append_only: false,
+                    });
+                }
+            }
+            Err(e) => eprintln!("  ⚠️  {}: {}", source.path.display(), e),
+        },
+        SourceType::TranscriptDir => {
+            if let Ok(entries) = std::fs::read_dir(&source.path) {
+                for entry in entries.flatten() {
+                    if is_transcript_file(&entry.path()) {
+                        files.push(SourceFile {
+                            path: entry.path(),
+                            kind: FileKind::Transcript,
+                            label: label.clone(),
+                            source_date: None,
+                            append_only: source.append_only && is_jsonl(&entry.path()),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    if canonical {
+        for f in &mut files {
+            f.path = normalize_path(&f.path);
+        }
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    files
+}
+
+/// `.md` files in `dir`, and with `recursive` in its subdirectories too.
+/// Hidden directories (`.git`, `.obsidian`, ...) and symlinked ones are skipped.
+fn markdown_files(dir: &Path, recursive: bool, out: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().is_some_and(|e| e == "md") {
+            out.push(path);
+        } else if recursive
+            && entry.file_type().is_ok_and(|t| t.is_dir())
+            && !entry.file_name().to_string_lossy().starts_with('.')
+        {
+            markdown_files(&path, true, out);
+        }
+    }
+}
+
+/// The `label_from` label for a file of `source`, or `None` when the source
+/// has none or the file sits directly in the source directory.
+fn file_label(source: &Source, path: &Path) -> Option<String> {
+    let dir = path.strip_prefix(&source.path).ok()?.parent()?;
+    let name = match source.label_from? {
+        LabelFrom::Dirname => dir.file_name()?,
+        LabelFrom::TopDir => dir.components().next()?.as_os_str(),
+    };
+    Some(name.to_string_lossy().into_owned())
+}
+
+/// `path` with symlinks and `.`/`..` resolved, so a file reached through two
+/// routes is stored under one `source_path`. A path that no longer exists
+/// (a deleted file) resolves through its parent directory; if that fails
+/// too, the path is returned as given.
+fn normalize_path(path: &Path) -> PathBuf {
+    if let Ok(p) = path.canonicalize() {
+        return p;
+    }
+    match (
+        path.parent().and_then(|d| d.canonicalize().ok()),
+        path.file_name(),
+    ) {
+        (Some(dir), Some(name)) => dir.join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Files of every source, each path once even when two sources reach it.
+fn collect_all_files(config: &Config) -> Vec<SourceFile> {
+    unique_files(
+        config
+            .sources
+            .iter()
+            .flat_map(|s| source_files(s, config.canonical_paths))
+            .collect(),
+    )
+}
+
+fn unique_files(mut files: Vec<SourceFile>) -> Vec<SourceFile> {
+    let mut seen = std::collections::HashSet::new();
+    files.retain(|f| seen.insert(f.path.clone()));
+    files
+}
+
+/// Labels `label_from` sources currently give their files.
+pub fn folder_labels(config: &Config) -> std::collections::BTreeSet<String> {
+    config
+        .sources
+        .iter()
+        .filter(|s| s.label_from.is_some())
+        .flat_map(|s| source_files(s, false))
+        .map(|f| f.label)
+        .collect()
+}
+
+/// How many files a full index would read.
+pub fn indexable_file_count(config: &Config) -> usize {
+    collect_all_files(config).len()
+}
+
+/// If `path` looks like an already-indexed file that was renamed only in its
+/// date (e.g. `session-2026-01-30.jsonl` → `session-2026-01-31.jsonl`), return
+/// the old `source_path`. The old path must be gone from disk, live in the same
+/// directory and be the only such match; purely dated names like `2026-01-30.md`
+/// never match, since any two of those would look alike.
+fn renamed_from(
+    path: &Path,
+    indexed_state: &IndexedState,
+    on_disk: &std::collections::HashSet<String>,
+) -> Option<String> {
+    let re = regex::Regex::new(r"\d{4}-\d{2}-\d{2}").ok()?;
+    let undated = |p: &Path| -> Option<String> {
+        let name = p.file_name()?.to_string_lossy().to_string();
+        re.is_match(&name)
+            .then(|| re.replace_all(&name, "").to_string())
+    };
+    let key = undated(path)?;
+    let stem = Path::new(&key)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())?;
+    if stem
+        .trim_matches(|c: char| c == '-' || c == '_' || c == '.' || c == ' ')
+        .is_empty()
+    {
+        return None;
+    }
+
+    let mut candidates = indexed_state.keys().filter(|old| {
+        let old_path = Path::new(old.as_str());
+        !on_disk.contains(old.as_str())
+            && !old_path.exists()
+            && old_path.parent() == path.parent()
+            && undated(old_path).as_deref() == Some(key.as_str())
+    });
+    let found = candidates.next()?;
+    candidates.next().is_none().then(|| found.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use std::time::SystemTime;
+    use wiremock::matchers::body_string_contains;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Write `text` to `dir/name`, dated `age_secs` ago, so each rewrite gets
+    /// an mtime of its own however fast the test runs.
+    fn write_note(dir: &Path, name: &str, text: &str, age_secs: u64) {
+        let path = dir.join(name);
+        std::fs::write(&path, text).unwrap();
+        let mtime = SystemTime::now() - Duration::from_secs(age_secs);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+    }
+
+    /// A markdown file at `path`, which need not exist.
+    fn markdown_file(path: &str) -> SourceFile {
+        SourceFile {
+            path: PathBuf::from(path),
+            kind: FileKind::Markdown,
+            label: "daily_note".to_string(),
+            source_date: None,
+            append_only: false,
+        }
+    }
+
+    /// Stored chunk text of the file called `name`, in file order.
+    async fn stored(config: &Config, name: &str) -> Vec<String> {
+        let client = db::connect(&config.db_url, Some(10)).await.unwrap();
+        let sql = format!(
+            "SELECT content FROM {} WHERE agent_id = $1 AND source_path LIKE '%/' || $2 ORDER BY start_offset",
+            config.table_name()
+        );
+        let rows = client
+            .query(&sql, &[&config.agent_id, &name])
+            .await
+            .unwrap();
+        rows.iter().map(|r| r.get(0)).collect()
+    }
+
+    fn delete_filter(
+        source: Option<&str>,
+        path: Option<&str>,
+        before: Option<&str>,
+        all: bool,
+    ) -> DeleteFilter {
+        DeleteFilter {
+            source: source.map(str::to_string),
+            path: path.map(str::to_string),
+            before: before.map(|d| d.parse().unwrap()),
+            all,
+        }
+    }
+
+    #[test]
+    fn delete_predicate_adds_one_clause_per_filter() {
+        let mut config = testing::config("", "");
+        config.agent_id = "o'neil".to_string();
+        let predicate =
+            |f: DeleteFilter| delete_predicate(&config, &f, &mut Binds::new(false)).unwrap();
+        let agent = "agent_id = 'o''neil'";
+        let under = "(source_path = '/notes/it''s' OR (left(source_path, 11) = '/notes/it''s' \
+                     AND substr(source_path, 11 + 1, 1) = '/'))";
+
+        assert_eq!(predicate(delete_filter(None, None, None, true)), agent);
+        assert_eq!(
+            predicate(delete_filter(Some("chat's"), None, None, false)),
+            format!("{} AND source = 'chat''s'", agent)
+        );
+        assert_eq!(
+            predicate(delete_filter(None, Some("/notes/it's"), None, false)),
+            format!("{} AND {}", agent, under)
+        );
+        assert_eq!(
+            predicate(delete_filter(None, None, Some("2025-01-01"), false)),
+            format!("{} AND source_date < '2025-01-01'::date", agent)
+        );
+        assert_eq!(
+            predicate(delete_filter(
+                Some("chat"),
+                Some("/notes/it's"),
+                Some("2025-01-01"),
+                false
+            )),
+            format!(
+                "{} AND source = 'chat' AND {} AND source_date < '2025-01-01'::date",
+                agent, under
+            )
+        );
+    }
+
+    #[test]
+    fn delete_predicate_binds_every_value_when_prepared() {
+        let mut config = testing::config("", "");
+        config.agent_id = "o'neil".to_string();
+        let prepared = |f: DeleteFilter| {
+            let mut binds = Binds::new(true);
+            let sql = delete_predicate(&config, &f, &mut binds).unwrap();
+            (sql, binds.params().len())
+        };
+
+        assert_eq!(
+            prepared(delete_filter(None, None, None, true)),
+            ("agent_id = $1::text".to_string(), 1)
+        );
+        assert_eq!(
+            prepared(delete_filter(Some("chat's"), None, None, false)),
+            ("agent_id = $1::text AND source = $2::text".to_string(), 2)
+        );
+        assert_eq!(
+            prepared(delete_filter(None, Some("/notes/it's"), None, false)),
+            (
+                "agent_id = $1::text AND (source_path = $2::text OR (left(source_path, 11) = $2::text \
+                 AND substr(source_path, 11 + 1, 1) = '/'))"
+                    .to_string(),
+                2
+            )
+        );
+        assert_eq!(
+            prepared(delete_filter(None, None, Some("2025-01-01"), false)),
+            (
+                "agent_id = $1::text AND source_date < $2::text::date".to_string(),
+                2
+            )
+        );
+        let (sql, values) = prepared(delete_filter(
+            Some("chat"),
+            Some("/notes"),
+            Some("2025-01-01"),
+            false,
+        ));
+        assert_eq!(values, 4);
+        assert!(
+            sql.starts_with(
+                "agent_id = $1::text AND source = $2::text AND (source_path = $3::text"
+            ),
+            "{}",
+            sql
+        );
+        assert!(
+            sql.ends_with(" AND source_date < $4::text::date"),
+            "{}",
+            sql
+        );
+    }
+
+    #[test]
+    fn delete_predicate_needs_exactly_a_filter_or_all() {
+        let config = testing::config("", "");
+        for filter in [
+            delete_filter(None, None, None, false),
+            delete_filter(Some("chat"), None, None, true),
+            delete_filter(None, Some("/notes"), None, true),
+            delete_filter(None, None, Some("2025-01-01"), true),
+        ] {
+            let err = delete_predicate(&config, &filter, &mut Binds::new(true))
+                .unwrap_err()
+                .to_string();
+            assert!(
+                err.starts_with("Give --source, --path or --before"),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn delete_removes_only_the_selected_chunks() {
+        let Some(db_url) = testing::db_url() else {
+            return;
+        };
+        for mode in [QueryMode::Simple, QueryMode::Prepared] {
+            let mut config = testing::config(&db_url, "");
+            config.agent_id.push_str("-o'neil");
+            config.query_mode = mode;
+            let client = db::connect(&config.db_url, Some(10)).await.unwrap();
+            let rows = [
+                ("daily_note", "/notes/2024-12-31.md", Some("2024-12-31")),
+                ("daily_note", "/notes/sub/2025-01-02.md", Some("2025-01-02")),
+                ("daily_note", "/notes-old/2024-06-01.md", Some("2024-06-01")),
+                ("chat's", "/chats/log.jsonl", None),
+            ];
+            for (label, path, date) in rows {
+                client
+                    .execute(
+                        &format!(
+                            "INSERT INTO {} (agent_id, content, source, source_path, source_date) \
+                             VALUES ($1, 'x', $2, $3, $4::text::date)",
+                            config.table_name()
+                        ),
+                        &[&config.agent_id, &label, &path, &date],
+                    )
+                    .await
+                    .unwrap();
+            }
+            set_indexed_bytes(&client, &config, "/notes/sub/2025-01-02.md", 10)
+                .await
+                .unwrap();
+            set_indexed_bytes(&client, &config, "/notes-old/2024-06-01.md", 10)
+                .await
+                .unwrap();
+            let paths = || async {
+                let sql = format!(
+                    "SELECT source_path FROM {} WHERE agent_id = $1 ORDER BY 1",
+                    config.table_name()
+                );
+                let rows = client.query(&sql, &[&config.agent_id]).await.unwrap();
+                rows.iter().map(|r| r.get(0)).collect::<Vec<String>>()
+            };
+
+            // Without --yes nothing goes
+            let err = run_delete(
+                &config,
+                delete_filter(Some("chat's"), None, None, false),
+                false,
+            )
+            .await
+            .unwrap_err();
+            assert!(
+                err.to_string().starts_with("Would delete 1 chunk(s)"),
+                "{:?}: {}",
+                mode,
+                err
+            );
+            assert_eq!(paths().await.len(), 4);
+
+            // A directory, not its look-alike sibling, with its index state
+            run_delete(
+                &config,
+                delete_filter(None, Some("/notes/"), None, false),
+                true,
+            )
+            .await
+            .unwrap();
+            assert_eq!(
+                paths().await,
+                ["/chats/log.jsonl", "/notes-old/2024-06-01.md"],
+                "{:?}",
+                mode
+            );
+            assert_eq!(
+                get_indexed_bytes(&client, &config, "/notes/sub/2025-01-02.md")
+                    .await
+                    .unwrap(),
+                None
+            );
+            assert_eq!(
+                get_indexed_bytes(&client, &config, "/notes-old/2024-06-01.md")
+                    .await
+                    .unwrap(),
+                Some(10)
+            );
+
+            // Undated chunks survive a date cutoff
+            run_delete(
+                &config,
+                delete_filter(None, None, Some("2025-01-01"), false),
+                true,
+            )
+            .await
+            .unwrap();
+            assert_eq!(paths().await, ["/chats/log.jsonl"], "{:?}", mode);
+
+            run_delete(
+                &config,
+                delete_filter(Some("chat's"), None, None, false),
+                true,
+            )
+            .await
+            .unwrap();
+            assert!(paths().await.is_empty(), "{:?}", mode);
+
+            testing::clear_agent(&config).await;
+        }
+    }
+
+    #[test]
+    fn invalid_utf8_is_skipped_or_decoded_lossily() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("latin1.md");
+        std::fs::write(&path, b"caf\xe9 notes").unwrap();
+        let mut config = testing::config("", "");
+
+        let mut report = IndexReport::new(false, false);
+        assert_eq!(read_source_file(&config, &path, &mut report), None);
+        assert_eq!(report.skipped.len(), 1);
+        assert!(
+            report.skipped[0].1.starts_with("not valid UTF-8 (byte 3)"),
+            "{:?}",
+            report.skipped
+        );
+
+        config.lossy_utf8 = true;
+        let mut report = IndexReport::new(false, false);
+        assert_eq!(
+            read_source_file(&config, &path, &mut report).as_deref(),
+            Some("caf\u{fffd} notes")
+        );
+        assert!(report.skipped.is_empty());
+
+        let mut report = IndexReport::new(false, false);
+        assert_eq!(
+            read_source_file(&config, &dir.path().join("missing.md"), &mut report),
+            None
+        );
+        assert!(report.skipped[0].1.starts_with("unreadable"));
+    }
+
+    #[test]
+    fn chunk_strategy_tokens_budgets_markdown_by_tokens() {
+        let mut config = testing::config("", "");
+        let text = "The queue migration finished and latency dropped by a third. ".repeat(20);
+        assert_eq!(markdown_chunks(&config, &text, config.max_chars).len(), 1);
+
+        config.chunk_strategy = ChunkStrategy::Tokens;
+        config.max_tokens = 30;
+        let chunks = markdown_chunks(&config, &text, config.max_chars);
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| chunk::token_count(&c.content) <= 30));
+    }
+
+    #[test]
+    fn section_context_changes_the_embedded_text_only() {
+        let mut config = testing::config("", "");
+        config.section_context = true;
+        config.max_chars = 60;
+        let text = "# Day\n\n## Meetings\n\nStandup ran long because of the outage review.\n";
+        let file = markdown_file("2025-01-06.md");
+        let prepared = prepare_markdown(
+            &config,
+            text,
+            &file,
+            None,
+            &mut IndexReport::new(false, false),
+        )
+        .unwrap();
+        let row = prepared.rows.last().unwrap();
+        assert_eq!(
+            row.content,
+            "Standup ran long because of the outage review."
+        );
+        assert_eq!(
+            embed_text(&config, None, row.section.as_deref(), &row.content),
+            "# Day > ## Meetings\nStandup ran long because of the outage review."
+        );
+    }
+
+    #[test]
+    fn filename_date_wins_over_frontmatter_date() {
+        let mut config = testing::config("", "");
+        config.store_metadata = true;
+        let text =
+            "---\ndate: 2024-12-24\ntags: [travel, family]\ntitle: Holidays\nmood: fine\n---\n\
+            # Trip\n\nTrain tickets are booked for the morning of the 23rd.\n";
+        let prepare = |name: &str| {
+            prepare_markdown(
+                &config,
+                text,
+                &markdown_file(name),
+                None,
+                &mut IndexReport::new(false, false),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            prepare("notes/2025-01-02.md").source_date.as_deref(),
+            Some("2025-01-02")
+        );
+        let undated = prepare("notes/holidays.md");
+        assert_eq!(undated.source_date.as_deref(), Some("2024-12-24"));
+        assert_eq!(undated.title.as_deref(), Some("Holidays"));
+        let row = &undated.rows[0];
+        assert!(!row.content.contains("tags:"), "{:?}", row.content);
+        let metadata = row.metadata.as_ref().unwrap();
+        assert_eq!(metadata["tags"], serde_json::json!(["travel", "family"]));
+        assert_eq!(metadata["title"], "Holidays");
+        assert!(metadata.get("mood").is_none());
+    }
+
+    #[tokio::test]
+    async fn invalid_utf8_file_does_not_stop_the_run() {
+        let Some(db_url) = testing::db_url() else {
+            return;
+        };
+        let embedder = testing::embedder().await;
+        let notes = tempfile::tempdir().unwrap();
+        let config = testing::notes_config(&db_url, &embedder, notes.path());
+        write_note(notes.path(), "2025-01-06.md", testing::MONDAY, 100);
+        std::fs::write(
+            notes.path().join("2025-01-07.md"),
+            b"# Tuesday\n\nLunch at the caf\xe9 with the design team.\n",
+        )
+        .unwrap();
+
+        let run = run_incremental_index(&config, false, false, None)
+            .await
+            .unwrap();
+        assert_eq!((run.new_files, run.skipped_files), (1, 1));
+        assert!(stored(&config, "2025-01-07.md").await.is_empty());
+
+        testing::clear_agent(&config).await;
+    }
+
+    /// A JSONL transcript of `lines` alternating user and assistant messages.
+    fn transcript(lines: &[&str]) -> String {
+        let roles = ["user", "assistant"].into_iter().cycle();
+        lines
+            .iter()
+            .zip(roles)
+            .map(|(line, role)| {
+                format!("{}\n", serde_json::json!({ "role": role, "content": line }))
+            })
+            .collect()
+    }
+
+    /// Stored chunks of `config`'s agent per `source` label.
+    async fn chunks_per_source(config: &Config) -> Vec<(String, i64)> {
+        let client = db::connect(&config.db_url, Some(10)).await.unwrap();
+        let sql = format!(
+            "SELECT source, count(*) FROM {} WHERE agent_id = $1 GROUP BY source ORDER BY source",
+            config.table_name()
+        );
+        let rows = client.query(&sql, &[&config.agent_id]).await.unwrap();
+        rows.iter().map(|r| (r.get(0), r.get(1))).collect()
+    }
+
+    #[tokio::test]
+    async fn incremental_runs_treat_every_source_type_alike() {
+        let Some(db_url) = testing::db_url() else {
+            return;
+        };
+        let embedder = testing::embedder().await;
+        let root = tempfile::tempdir().unwrap();
+        let (notes, chats) = (root.path().join("notes"), root.path().join("chats"));
+        std::fs::create_dir_all(&notes).unwrap();
+        std::fs::create_dir_all(&chats).unwrap();
+        let mut config = testing::embedder_config(&db_url, &embedder);
+        config
+            .sources
+            .push(testing::source(&notes, SourceType::MarkdownDir));
+        let mut single = testing::source(&root.path().join("plan.md"), SourceType::SingleFile);
+        single.source_date = Some("2025-02-01".to_string());
+        config.sources.push(single);
+        config.sources.push(testing::source(
+            &root.path().join("log.jsonl"),
+            SourceType::SingleFile,
+        ));
+        config
+            .sources
+            .push(testing::source(&chats, SourceType::TranscriptDir));
+
+        let write_all = |suffix: &str, age_secs: u64| {
+            write_note(
+                &notes,
+                "2025-01-06.md",
+                &format!("{}\n{}\n", testing::MONDAY, suffix),
+                age_secs,
+            );
+            write_note(
+                root.path(),
+                "plan.md",
+                &format!(
+                    "# Plan\n\nShip the onboarding redesign in March. {}\n",
+                    suffix
+                ),
+                age_secs,
+            );
+            let chat = transcript(&["How do I rotate the staging database password?", suffix]);
+            write_note(root.path(), "log.jsonl", &chat, age_secs);
+            write_note(&chats, "session-2025-01-06.jsonl", &chat, age_secs);
+        };
+        let counts = |s: &IncrementalSummary| {
+            (
+                s.new_files,
+                s.updated_files,
+                s.removed_files,
+                s.skipped_files,
+            )
+        };
+
+        write_all("First version of the details.", 300);
+        let run = run_incremental_index(&config, false, false, None)
+            .await
+            .unwrap();
+        assert_eq!(counts(&run), (4, 0, 0, 0));
+        let labels: Vec<String> = chunks_per_source(&config)
+            .await
+            .into_iter()
+            .map(|(l, _)| l)
+            .collect();
+        assert_eq!(labels, ["daily_note", "single_file", "transcript"]);
+
+        let run = run_incremental_index(&config, false, false, None)
+            .await
+            .unwrap();
+        assert_eq!(counts(&run), (0, 0, 0, 0));
+
+        write_all("Second version, with other details.", 200);
+        let run = run_incremental_index(&config, false, false, None)
+            .await
+            .unwrap();
+        assert_eq!(counts(&run), (0, 4, 0, 0));
+        assert!(stored(&config, "plan.md")
+            .await
+            .concat()
+            .contains("Second version"));
+        assert!(stored(&config, "log.jsonl")
+            .await
+            .concat()
+            .contains("Second version"));
+
+        // Renamed only in its date: the same transcript, so replaced, not duplicated
+        let before = chunks_per_source(&config).await;
+        std::fs::rename(
+            chats.join("session-2025-01-06.jsonl"),
+            chats.join("session-2025-01-08.jsonl"),
+        )
+        .unwrap();
+        let run = run_incremental_index(&config, false, false, None)
+            .await
+            .unwrap();
+        assert_eq!(counts(&run), (0, 1, 0, 0));
+        assert!(stored(&config, "session-2025-01-06.jsonl").await.is_empty());
+        assert!(!stored(&config, "session-2025-01-08.jsonl").await.is_empty());
+        assert_eq!(chunks_per_source(&config).await, before);
+
+        testing::clear_agent(&config).await;
+    }
+
+    /// Texts `embedder` has been asked to embed so far.
+    async fn embedded_texts(embedder: &MockServer) -> usize {
+        let requests = embedder.received_requests().await.unwrap_or_default();
+        requests
+            .iter()
+            .map(|r| {
+                let body: serde_json::Value = r.body_json().unwrap_or_default();
+                body["input"].as_array().map_or(1, Vec::len)
+            })
+            .sum()
+    }
+
+    #[tokio::test]
+    async fn stable_chunks_re_embed_only_around_an_edit() {
+        let Some(db_url) = testing::db_url() else {
+            return;
+        };
+        let embedder = testing::embedder().await;
+        let notes = tempfile::tempdir().unwrap();
+        let mut config = testing::notes_config(&db_url, &embedder, notes.path());
+        config.stable_chunks = true;
+        config.max_chars = 400;
+        let mut paragraphs: Vec<String> = (0..60)
+            .map(|i| {
+                format!(
+                    "Paragraph {} notes the follow-ups from meeting {} and who owns them.",
+                    i,
+                    i * 7 % 13
+                )
+            })
+            .collect();
+        write_note(notes.path(), "2025-01-06.md", &paragraphs.join("\n\n"), 200);
+        run_incremental_index(&config, false, false, None)
+            .await
+            .unwrap();
+        let (chunks, sent) = (
+            stored(&config, "2025-01-06.md").await.len(),
+            embedded_texts(&embedder).await,
+        );
+        assert_eq!(sent, chunks);
+
+        paragraphs.insert(
+            30,
+            "An inserted paragraph about a decision made later that day.".to_string(),
+        );
+        write_note(notes.path(), "2025-01-06.md", &paragraphs.join("\n\n"), 100);
+        let run = run_incremental_index(&config, false, false, None)
+            .await
+            .unwrap();
+        assert_eq!(run.updated_files, 1);
+        let re_embedded = embedded_texts(&embedder).await - sent;
+        assert!(
+            (1..=2).contains(&re_embedded),
+            "{} of {} chunks re-embedded",
+            re_embedded,
+            chunks
+        );
+
+        testing::clear_agent(&config).await;
+    }
+
+    #[tokio::test]
+    async fn quotes_in_paths_content_and_agent_id_are_stored_verbatim() {
+        let Some(db_url) = testing::db_url() else {
+            return;
+        };
+        let embedder = testing::embedder().await;
+        let root = tempfile::tempdir().unwrap();
+        let notes = root.path().join("it's notes");
+        std::fs::create_dir_all(&notes).unwrap();
+        let quoted =
+            "# O'Brien's \"plan\"\n\nIt's done; DROP TABLE chunks; -- the 'old' \\ \"new\" way.\n";
+        for mode in [QueryMode::Simple, QueryMode::Prepared] {
+            let mut config = testing::notes_config(&db_url, &embedder, &notes);
+            config.agent_id.push_str("-o'brien");
+            config.query_mode = mode;
+            write_note(&notes, "2025-01-06 o'clock.md", quoted, 100);
+            write_note(&notes, "2025-01-07 o.md", testing::TUESDAY, 100);
+
+            let run = run_incremental_index(&config, false, false, None)
+                .await
+                .unwrap();
+            assert_eq!((run.new_files, run.skipped_files), (2, 0), "{:?}", mode);
+            assert_eq!(
+                stored(&config, "2025-01-06 o'clock.md").await,
+                [quoted.trim()],
+                "{:?}",
+                mode
+            );
+            assert_eq!(
+                stored(&config, "2025-01-07 o.md").await.len(),
+                1,
+                "{:?}",
+                mode
+            );
+
+            std::fs::remove_file(notes.join("2025-01-06 o'clock.md")).unwrap();
+            let run = run_incremental_index(&config, false, false, None)
+                .await
+                .unwrap();
+            assert_eq!(run.removed_files, 1, "{:?}", mode);
+            assert!(stored(&config, "2025-01-06 o'clock.md").await.is_empty());
+            assert_eq!(
+                stored(&config, "2025-01-07 o.md").await.len(),
+                1,
+                "{:?}",
+                mode
+            );
+
+            testing::clear_agent(&config).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn embed_pending_and_merge_bind_quoted_values() {
+        let Some(db_url) = testing::db_url() else {
+            return;
+        };
+        let embedder = testing::embedder().await;
+        let root = tempfile::tempdir().unwrap();
+        let (notes, moved) = (
+            root.path().join("it's notes"),
+            root.path().join("it's moved"),
+        );
+        std::fs::create_dir_all(&notes).unwrap();
+        write_note(&notes, "2025-01-06.md", testing::MONDAY, 100);
+        write_note(&notes, "2025-01-07.md", testing::TUESDAY, 100);
+        for mode in [QueryMode::Simple, QueryMode::Prepared] {
+            let mut config = testing::notes_config(&db_url, &embedder, &notes);
+            config.agent_id.push_str("-o'brien");
+            config.query_mode = mode;
+            config.defer_embeddings = true;
+            let client = db::connect(&config.db_url, Some(10)).await.unwrap();
+            let rows = |column: &str, condition: &str| {
+                let sql = format!(
+                    "SELECT {} FROM {} WHERE agent_id = $1 AND {} ORDER BY 1",
+                    column,
+                    config.table_name(),
+                    condition
+                );
+                let client = &client;
+                let agent_id = config.agent_id.clone();
+                async move {
+                    let rows = client.query(&sql, &[&agent_id]).await.unwrap();
+                    rows.iter().map(|r| r.get(0)).collect::<Vec<String>>()
+                }
+            };
+
+            run_incremental_index(&config, false, false, None)
+                .await
+                .unwrap();
+            let pending = rows("content", "TRUE").await;
+            assert_eq!(pending.len(), 2, "{:?}", mode);
+            assert!(rows("content", "embedding IS NOT NULL").await.is_empty());
+            run_embed_pending(&config, 2).await.unwrap();
+            let embedded = rows("content", "embedding IS NOT NULL").await;
+            assert_eq!(embedded, pending, "{:?}", mode);
+
+            run_merge(&config, notes.to_str().unwrap(), moved.to_str().unwrap())
+                .await
+                .unwrap();
+            let paths = rows("source_path", "TRUE").await;
+            let expected: Vec<String> = ["2025-01-06.md", "2025-01-07.md"]
+                .iter()
+                .map(|name| moved.join(name).to_string_lossy().into_owned())
+                .collect();
+            assert_eq!(paths, expected, "{:?}", mode);
+
+            testing::clear_agent(&config).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn chunks_of_deleted_files_are_removed() {
+        let Some(db_url) = testing::db_url() else {
+            return;
+        };
+        let embedder = testing::embedder().await;
+        let notes = tempfile::tempdir().unwrap();
+        let config = testing::notes_config(&db_url, &embedder, notes.path());
+        write_note(notes.path(), "2025-01-06.md", testing::MONDAY, 100);
+        write_note(notes.path(), "2025-01-07.md", testing::TUESDAY, 100);
+        run_incremental_index(&config, false, false, None)
+            .await
+            .unwrap();
+        // Stored from elsewhere (stdin, a dropped source): not ours to remove
+        let client = db::connect(&config.db_url, Some(10)).await.unwrap();
+        let sql = format!(
+            "INSERT INTO {} (agent_id, content, source, source_path) VALUES ($1, 'piped in', 'stdin', '/elsewhere/piped.md')",
+            config.table_name()
+        );
+        client.execute(&sql, &[&config.agent_id]).await.unwrap();
+        let tuesday = stored(&config, "2025-01-07.md").await.len();
+
+        std::fs::remove_file(notes.path().join("2025-01-07.md")).unwrap();
+        let run = run_incremental_index(&config, false, false, None)
+            .await
+            .unwrap();
+        assert_eq!((run.removed_files, run.chunks_deleted), (1, tuesday));
+        assert!(stored(&config, "2025-01-07.md").await.is_empty());
+        assert_eq!(stored(&config, "2025-01-06.md").await.len(), 1);
+        assert_eq!(stored(&config, "piped.md").await, ["piped in"]);
+
+        testing::clear_agent(&config).await;
+    }
+
+    #[tokio::test]
+    async fn indexing_embeds_within_embed_concurrency() {
+        let Some(db_url) = testing::db_url() else {
+            return;
+        };
+        let embedder = testing::SlowEmbedder::start(Duration::from_millis(20), true).await;
+        let dir = tempfile::tempdir().unwrap();
+        let paragraphs: Vec<String> = (0..12)
+            .map(|i| {
+                format!(
+                    "Paragraph {} about release {} and the rollout checklist for it.",
+                    i, i
+                )
+            })
+            .collect();
+        write_note(dir.path(), "2025-01-06.md", &paragraphs.join("\n\n"), 60);
+        let mut config = testing::config(&db_url, &embedder.url);
+        config.quiet = true;
+        config
+            .sources
+            .push(testing::source(dir.path(), SourceType::MarkdownDir));
+        config.max_chars = 80;
+        config.embed_batch_size = 1;
+        config.embed_concurrency = 3;
+
+        run_incremental_index(&config, false, false, None)
+            .await
+            .unwrap();
+        assert_eq!(embedder.max_in_flight(), 3);
+
+        // Each chunk stored with its own text's vector
+        let client = db::connect(&config.db_url, Some(10)).await.unwrap();
+        let sql = format!(
+            "SELECT content, embedding::text FROM {} WHERE agent_id = $1 ORDER BY start_offset",
+            config.table_name()
+        );
+        let rows = client.query(&sql, &[&config.agent_id]).await.unwrap();
+        assert_eq!(rows.len(), paragraphs.len());
+        for row in rows {
+            let content: String = row.get(0);
+            let vector = parse_vector(row.get::<_, &str>(1));
+            assert_eq!(vector, testing::vector_for(&content), "{}", content);
+        }
+
+        testing::clear_agent(&config).await;
+    }
+
+    #[tokio::test]
+    async fn touched_but_unchanged_files_are_not_re_embedded() {
+        let Some(db_url) = testing::db_url() else {
+            return;
+        };
+        let embedder = testing::embedder().await;
+        let notes = tempfile::tempdir().unwrap();
+        let mut config = testing::notes_config(&db_url, &embedder, notes.path());
+        config.content_hash = true;
+        write_note(notes.path(), "2025-01-06.md", testing::MONDAY, 300);
+        run_incremental_index(&config, false, false, None)
+            .await
+            .unwrap();
+        let sent = embedded_texts(&embedder).await;
+
+        // Re-saved as is, as a sync client or backup would
+        write_note(notes.path(), "2025-01-06.md", testing::MONDAY, 200);
+        for _ in 0..2 {
+            let run = run_incremental_index(&config, false, false, None)
+                .await
+                .unwrap();
+            assert_eq!((run.new_files, run.updated_files), (0, 0));
+        }
+        assert_eq!(embedded_texts(&embedder).await, sent);
+        let client = db::connect(&config.db_url, Some(10)).await.unwrap();
+        let state = get_indexed_state(&client, &config).await.unwrap();
+        let path = normalize_path(&notes.path().join("2025-01-06.md"))
+            .to_string_lossy()
+            .to_string();
+        assert_eq!(
+            state[&path],
+            file_mtime(&notes.path().join("2025-01-06.md"))
+        );
+
+        write_note(notes.path(), "2025-01-06.md", testing::TUESDAY, 100);
+        let run = run_incremental_index(&config, false, false, None)
+            .await
+            .unwrap();
+        assert_eq!(run.updated_files, 1);
+        assert!(embedded_texts(&embedder).await > sent);
+
+        testing::clear_agent(&config).await;
+    }
+
+    #[tokio::test]
+    async fn failed_embedding_keeps_the_files_previous_chunks() {
+        let Some(db_url) = testing::db_url() else {
+            return;
+        };
+        let embedder = testing::embedder().await;
+        // Not a list of vectors: no retry or smaller batch gets past this
+        Mock::given(body_string_contains("unembeddable"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "embeddings": "none" })),
+            )
+            .with_priority(1)
+            .mount(&embedder)
+            .await;
+        let notes = tempfile::tempdir().unwrap();
+        let config = testing::notes_config(&db_url, &embedder, notes.path());
+        write_note(notes.path(), "2025-01-06.md", testing::MONDAY, 300);
+        write_note(notes.path(), "2025-01-07.md", testing::TUESDAY, 300);
+        let first = run_incremental_index(&config, false, false, None)
+            .await
+            .unwrap();
+        assert_eq!((first.new_files, first.skipped_files), (2, 0));
+        let monday = stored(&config, "2025-01-06.md").await;
+        assert!(!monday.is_empty());
+
+        write_note(
+            notes.path(),
+            "2025-01-06.md",
+            "# Monday\n\nAn unembeddable rewrite of the day.\n",
+            200,
+        );
+        write_note(
+            notes.path(),
+            "2025-01-07.md",
+            "# Tuesday\n\nThe onboarding illustrations shipped.\n",
+            200,
+        );
+        let second = run_incremental_index(&config, false, false, None)
+            .await
+            .unwrap();
+        assert_eq!((second.updated_files, second.skipped_files), (1, 1));
+        assert_eq!(stored(&config, "2025-01-06.md").await, monday);
+        assert!(stored(&config, "2025-01-07.md")
+            .await
+            .concat()
+            .contains("illustrations shipped"));
+
+        // Still modified as far as the next run can tell, so it is retried
+        write_note(
+            notes.path(),
+            "2025-01-06.md",
+            "# Monday\n\nThe disk was cleaned up.\n",
+            100,
+        );
+        let third = run_incremental_index(&config, false, false, None)
+            .await
+            .unwrap();
+        assert_eq!((third.updated_files, third.skipped_files), (1, 0));
+        assert!(stored(&config, "2025-01-06.md")
+            .await
+            .concat()
+            .contains("disk was cleaned up"));
+
+        testing::clear_agent(&config).await;
+    }
+
+    /// Make every INSERT of a chunk of `config`'s agent containing `marker`
+    /// fail, until the returned statement is run.
+    async fn fail_inserts_containing(config: &Config, marker: &str) -> String {
+        let client = db::connect(&config.db_url, Some(10)).await.unwrap();
+        let function = format!("public.{}", config.agent_id.replace('-', "_"));
+        client
+            .batch_execute(&format!(
+                "CREATE FUNCTION {function}() RETURNS trigger LANGUAGE plpgsql AS $$
+                 BEGIN RAISE EXCEPTION 'insert refused by test'; END $$;
+                 CREATE TRIGGER {name} BEFORE INSERT ON {table} FOR EACH ROW
+                 WHEN (NEW.agent_id = '{agent}' AND NEW.content LIKE '%{marker}%')
+                 EXECUTE FUNCTION {function}()",
+                function = function,
+                name = config.agent_id.replace('-', "_"),
+                table = config.table_name(),
+                agent = config.agent_id,
+                marker = marker,
+            ))
+            .await
+            .unwrap();
+        format!(
+            "DROP TRIGGER {} ON {}; DROP FUNCTION {}()",
+            config.agent_id.replace('-', "_"),
+            config.table_name(),
+            function
+        )
+    }
+
+    #[tokio::test]
+    async fn failed_insert_rolls_back_to_the_previous_chunks() {
+        let Some(db_url) = testing::db_url() else {
+            return;
+        };
+        let embedder = testing::embedder().await;
+        let root = tempfile::tempdir().unwrap();
+        let notes = root.path().join("notes");
+        std::fs::create_dir_all(&notes).unwrap();
+        let mut config = testing::notes_config(&db_url, &embedder, &notes);
+        let mut chat = testing::source(&root.path().join("chat.jsonl"), SourceType::SingleFile);
+        chat.append_only = true;
+        config.sources.push(chat);
+
+        let first_chat = transcript(&[
+            "How do I rotate the staging database password?",
+            "With the vault CLI.",
+        ]);
+        write_note(&notes, "2025-01-06.md", testing::MONDAY, 300);
+        write_note(root.path(), "chat.jsonl", &first_chat, 300);
+        let run = run_incremental_index(&config, false, false, None)
+            .await
+            .unwrap();
+        assert_eq!((run.new_files, run.skipped_files), (2, 0));
+        let (monday, chat) = (
+            stored(&config, "2025-01-06.md").await,
+            stored(&config, "chat.jsonl").await,
+        );
+
+        let refused = format!("{}\nA REFUSED paragraph at the end.\n", testing::MONDAY);
+        write_note(&notes, "2025-01-06.md", &refused, 200);
+        let appended = format!("{}{}", first_chat, transcript(&["And the REFUSED token?"]));
+        write_note(root.path(), "chat.jsonl", &appended, 200);
+        // Nothing that can panic runs while the trigger is in place
+        let client = db::connect(&config.db_url, Some(10)).await.unwrap();
+        let restore = fail_inserts_containing(&config, "REFUSED").await;
+        let run = run_incremental_index(&config, false, false, None).await;
+        client.batch_execute(&restore).await.unwrap();
+        let run = run.unwrap();
+
+        // The deletes of the same transactions were undone with the inserts
+        assert_eq!((run.updated_files, run.skipped_files), (0, 2));
+        assert_eq!(stored(&config, "2025-01-06.md").await, monday);
+        assert_eq!(stored(&config, "chat.jsonl").await, chat);
+
+        // Nothing was recorded as indexed, so the next run retries both
+        let run = run_incremental_index(&config, false, false, None)
+            .await
+            .unwrap();
+        assert_eq!((run.updated_files, run.skipped_files), (2, 0));
+        assert!(stored(&config, "2025-01-06.md")
+            .await
+            .concat()
+            .contains("REFUSED paragraph"));
+        assert!(stored(&config, "chat.jsonl")
+            .await
+            .concat()
+            .contains("REFUSED token"));
+
+        testing::clear_agent(&config).await;
+    }
+
+    #[tokio::test]
+    async fn rejected_chunk_fails_the_whole_file() {
+        let Some(db_url) = testing::db_url() else {
+            return;
+        };
+        let embedder = testing::embedder().await;
+        Mock::given(body_string_contains("rejectable"))
+            .respond_with(ResponseTemplate::new(500))
+            .with_priority(1)
+            .mount(&embedder)
+            .await;
+        let notes = tempfile::tempdir().unwrap();
+        let mut config = testing::notes_config(&db_url, &embedder, notes.path());
+        config.max_chars = 120;
+        write_note(notes.path(), "2025-01-06.md", testing::MONDAY, 300);
+        run_incremental_index(&config, false, false, None)
+            .await
+            .unwrap();
+        let monday = stored(&config, "2025-01-06.md").await;
+
+        let rewrite = format!(
+            "# Monday\n\n{}\n\nOne rejectable paragraph among the others.\n\n{}\n",
+            "The deploy was rolled back after the disk filled up again.",
+            "Postmortem scheduled for Wednesday with the platform team."
+        );
+        write_note(notes.path(), "2025-01-06.md", &rewrite, 200);
+        for _ in 0..2 {
+            // The other chunks embed fine, but the file is not stored without
+            // the rejected one, nor marked as indexed
+            let run = run_incremental_index(&config, false, false, None)
+                .await
+                .unwrap();
+            assert_eq!((run.updated_files, run.skipped_files), (0, 1));
+            assert_eq!(stored(&config, "2025-01-06.md").await, monday);
+        }
+
+        testing::clear_agent(&config).await;
+    }
 }
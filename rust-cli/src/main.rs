@@ -1,98 +1,818 @@
+mod cache;
 mod chunk;
+mod compress;
 mod config;
+mod copy;
+mod db;
+mod doctor;
 mod embed;
+mod extract;
+mod git;
+mod http;
 mod index;
+mod mcp;
+mod migrate;
+mod rerank;
 mod search;
+mod telemetry;
+#[cfg(test)]
+mod testing;
+mod tls;
 
 use anyhow::Result;
+use chrono::NaiveDate;
 use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Exit status of `search --fail-if-empty` with no results (1 is any error, 2 bad usage).
+const EXIT_NO_RESULTS: i32 = 3;
 
 #[derive(Parser)]
-#[command(name = "agent-memory", version, about = "Agent-agnostic vector memory CLI")]
+#[command(
+    name = "agent-memory",
+    version,
+    about = "Agent-agnostic vector memory CLI"
+)]
 struct Cli {
     /// Path to config.yaml
     #[arg(short, long)]
     config: Option<String>,
 
+    /// Load environment variables from this file instead of `.env` in the cwd / config directory
+    #[arg(long, global = true)]
+    env_file: Option<String>,
+
+    /// Serve Prometheus metrics on this port for the lifetime of the process
+    #[arg(long, global = true)]
+    metrics_port: Option<u16>,
+
+    /// Address the metrics endpoint binds; use 0.0.0.0 to let other machines scrape it
+    #[arg(
+        long,
+        global = true,
+        default_value = "127.0.0.1",
+        requires = "metrics_port"
+    )]
+    metrics_host: std::net::IpAddr,
+
+    /// Only print errors and the final summary
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+/// Config overrides for the commands that chunk, embed and store sources.
+#[derive(clap::Args)]
+struct IndexArgs {
+    /// Embedding dimension of the model (overrides config model_dimension)
+    #[arg(long)]
+    model_dimension: Option<usize>,
+
+    /// Exit with an error if indexing skipped any file or chunk
+    #[arg(long)]
+    strict: bool,
+
+    /// Chunks per embedding request (overrides config embed_batch_size)
+    #[arg(long)]
+    embedding_batch: Option<usize>,
+
+    /// Read and chunk files on this many threads while embedding (overrides config threads)
+    #[arg(long)]
+    threads: Option<usize>,
+
+    /// Skip files producing more chunks than this (overrides config max_chunks_per_file)
+    #[arg(long)]
+    limit_chunk_count: Option<usize>,
+
+    /// Load the embedding model with a throwaway request before indexing
+    #[arg(long)]
+    model_warmup: bool,
+
+    /// Squeeze blank lines and repeated spaces before chunking markdown
+    #[arg(long)]
+    collapse_whitespace: bool,
+
+    /// Index without embedding; run `embed-pending` later to fill in the vectors
+    #[arg(long)]
+    defer_embeddings: bool,
+}
+
+impl IndexArgs {
+    fn apply(self, cfg: &mut config::Config) {
+        cfg.strict |= self.strict;
+        cfg.collapse_whitespace |= self.collapse_whitespace;
+        cfg.model_warmup |= self.model_warmup;
+        cfg.defer_embeddings |= self.defer_embeddings;
+        if self.model_dimension.is_some() {
+            cfg.model_dimension = self.model_dimension;
+        }
+        if self.limit_chunk_count.is_some() {
+            cfg.max_chunks_per_file = self.limit_chunk_count;
+        }
+        if let Some(n) = self.embedding_batch {
+            cfg.embed_batch_size = n;
+        }
+        if self.threads.is_some() {
+            cfg.threads = self.threads;
+        }
+    }
+}
+
+// Parsed once per run; Search's many flags make it the largest variant
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 enum Commands {
     /// Search memories semantically
     Search {
         /// Search query
-        query: String,
+        #[arg(required_unless_present_any = ["queries", "queries_file", "like_file"])]
+        query: Option<String>,
+
+        /// Another phrasing to search for (repeatable); results are fused
+        #[arg(long = "query", value_name = "QUERY")]
+        queries: Vec<String>,
+
+        /// Read more queries from this file, one per line
+        #[arg(long, value_name = "PATH")]
+        queries_file: Option<PathBuf>,
+
+        /// Search by example: chunk this file, embed its chunks and rank by their mean
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["query", "queries", "queries_file", "rerank", "offline", "hybrid"])]
+        like_file: Option<PathBuf>,
+
+        /// How results of several queries are combined
+        #[arg(long, value_enum, default_value = "rrf")]
+        fusion: search::Fusion,
+
+        /// Also rank by a full-text keyword match and fuse both rankings (needs db/013)
+        #[arg(long)]
+        hybrid: bool,
+
+        /// Share of the fused score given to keyword matches with --hybrid (0 to 1)
+        #[arg(long, default_value = "0.5", value_parser = parse_weight, requires = "hybrid")]
+        keyword_weight: f64,
 
         /// Number of results
         #[arg(short, long, default_value = "5")]
         top: i64,
 
-        /// Output as JSON
+        /// Skip this many results (best-effort paging; ranks can shift if the index changes)
+        #[arg(long, default_value = "0", value_parser = clap::value_parser!(i64).range(0..))]
+        offset: i64,
+
+        /// Output as JSON (same as --format json)
         #[arg(long)]
         json: bool,
+
+        /// Output format
+        #[arg(long, value_enum, default_value = "text", conflicts_with = "json")]
+        format: search::OutputFormat,
+
+        /// Print each result through this template (implies --format template), e.g.
+        /// "{similarity} {path}: {title}". Fields: rank, similarity, source, date, path, title, content
+        #[arg(long, conflicts_with_all = ["json", "group_by_source"], required_if_eq("format", "template"))]
+        template: Option<String>,
+
+        /// Group hits by source file
+        #[arg(long)]
+        group_by_source: bool,
+
+        /// Re-score candidates with `rerank_model` and re-sort
+        #[arg(long)]
+        rerank: bool,
+
+        /// Query every store in `federated_targets` and merge the results
+        #[arg(long)]
+        federated: bool,
+
+        /// Comma-separated columns to fetch and output (add `embedding` for debugging)
+        #[arg(long, default_value = search::DEFAULT_FIELDS)]
+        fields: String,
+
+        /// Show source paths relative to this directory (defaults to the config file's directory)
+        #[arg(long, value_name = "BASE")]
+        relative_to: Option<PathBuf>,
+
+        /// Rank chunks, or whole documents (needs `document_vectors` at index time)
+        #[arg(long, value_enum, default_value = "chunk")]
+        level: search::SearchLevel,
+
+        /// Blend similarity with source_date recency (0 = off, 1 = recency only)
+        #[arg(long, default_value = "0", value_parser = parse_weight)]
+        recency_weight: f64,
+
+        /// Only chunks detected as this language (ISO 639-3, e.g. eng, deu; needs `detect_language`)
+        #[arg(long, value_name = "CODE", value_parser = parse_lang)]
+        lang: Option<String>,
+
+        /// Only chunks whose source_date is on or after this day (YYYY-MM-DD)
+        #[arg(long, value_name = "DATE", value_parser = parse_date)]
+        since: Option<NaiveDate>,
+
+        /// Only chunks whose source_date is on or before this day (YYYY-MM-DD)
+        #[arg(long, value_name = "DATE", value_parser = parse_date)]
+        until: Option<NaiveDate>,
+
+        /// Only chunks containing this text, case-insensitively (repeatable; all must match)
+        #[arg(long, value_name = "TEXT")]
+        content_contains: Vec<String>,
+
+        /// Collapse hits with identical text, keeping the best and counting the rest
+        #[arg(long)]
+        dedup: bool,
+
+        /// Drop hits with fewer than this many characters of content
+        #[arg(long, default_value = "0")]
+        min_content_chars: usize,
+
+        /// Drop hits whose cosine similarity to the query is below this (-1 to 1)
+        #[arg(long, value_name = "SIM", value_parser = parse_similarity)]
+        min_similarity: Option<f64>,
+
+        /// Print each query's embedding (dimension, norm, first/last components) to stderr first
+        #[arg(long, visible_alias = "preview-query-embedding")]
+        show_embedding: bool,
+
+        /// Trim displayed content and collapse blank-line runs (JSON output is always exact)
+        #[arg(long, value_name = "BOOL", default_value_t = true, action = clap::ArgAction::Set)]
+        trim_results: bool,
+
+        /// Use only cached query embeddings (needs `query_cache`); fail instead of calling Ollama
+        #[arg(long)]
+        offline: bool,
+
+        /// Run the search even if `result_cache` holds a fresh page for it, and don't store this one
+        #[arg(long)]
+        no_cache: bool,
+
+        /// Also report how many chunks match before the top-k cut (extra COUNT query)
+        #[arg(long)]
+        count: bool,
+
+        /// With JSON output, wrap results as {query, top_k, model, metric, filters, results}
+        #[arg(long)]
+        json_envelope: bool,
+
+        /// Exit with status 3 when no result is left after filtering (for shell conditionals)
+        #[arg(long)]
+        fail_if_empty: bool,
+
+        /// Show source paths exactly as stored
+        #[arg(long, conflicts_with = "relative_to")]
+        absolute_paths: bool,
     },
     /// Full re-index of all configured sources
-    Index,
+    Index {
+        /// Print the distribution of chunk sizes after indexing
+        #[arg(long)]
+        chunk_report: bool,
+
+        /// Print time spent reading/chunking, embedding and inserting
+        #[arg(long)]
+        profile: bool,
+
+        /// Start over instead of skipping files an interrupted run already indexed
+        #[arg(long)]
+        no_resume: bool,
+
+        /// Commit once every N chunks (between files) instead of after every insert
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+        batch_commit: Option<u64>,
+
+        /// Load each file's chunks with binary COPY instead of INSERTs (overrides config bulk_copy)
+        #[arg(long)]
+        copy: bool,
+
+        #[command(flatten)]
+        index_args: IndexArgs,
+    },
     /// Incremental index (only new/modified files)
-    IndexIncremental,
+    IndexIncremental {
+        /// Print the distribution of chunk sizes after indexing
+        #[arg(long)]
+        chunk_report: bool,
+
+        /// Print time spent reading/chunking, embedding and inserting
+        #[arg(long)]
+        profile: bool,
+
+        /// For git sources, diff from this commit instead of the last indexed one
+        #[arg(long, value_name = "SHA")]
+        since_commit: Option<String>,
+
+        #[command(flatten)]
+        index_args: IndexArgs,
+    },
+    /// Keep the index current: poll the sources and index changes as they appear
+    Watch {
+        /// Seconds between scans of the sources (works on NFS/CIFS and without inotify)
+        #[arg(
+            long,
+            visible_alias = "watch-interval",
+            value_name = "SECS",
+            default_value = "5",
+            value_parser = clap::value_parser!(u64).range(1..)
+        )]
+        poll_interval: u64,
+
+        #[command(flatten)]
+        index_args: IndexArgs,
+    },
+    /// Index text piped on stdin, replacing anything stored under the same --path
+    IndexStdin {
+        /// Source label to store
+        #[arg(long, default_value = "stdin")]
+        label: String,
+
+        /// Virtual source_path (defaults to stdin://<label>)
+        #[arg(long)]
+        path: Option<String>,
+
+        /// How to parse the input
+        #[arg(long = "type", value_enum, default_value = "markdown")]
+        format: index::StdinFormat,
+
+        /// Date (YYYY-MM-DD) to store when the path has none
+        #[arg(long)]
+        source_date: Option<String>,
+
+        #[command(flatten)]
+        index_args: IndexArgs,
+    },
+    /// Embed chunks stored without an embedding (indexed with `defer_embeddings`)
+    EmbedPending {
+        /// Embedding requests in flight at once (overrides config embed_concurrency)
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        concurrency: Option<u32>,
+
+        /// Chunks per embedding request (overrides config embed_batch_size)
+        #[arg(long)]
+        embedding_batch: Option<usize>,
+    },
+    /// Rewrite stored source paths after moving files, instead of re-indexing them
+    Merge {
+        /// Old path prefix (a directory or file as it was indexed)
+        #[arg(long, value_name = "PREFIX")]
+        from: String,
+
+        /// New path prefix the files now live under
+        #[arg(long, value_name = "PREFIX")]
+        to: String,
+    },
+    /// Delete stored chunks of this agent by source label, path or date
+    ///
+    /// Files still under a configured source are indexed again by the next
+    /// `index`; remove them from the config (or disk) to keep them out.
+    Delete {
+        /// Only chunks with this source label
+        #[arg(long, value_name = "LABEL")]
+        source: Option<String>,
+
+        /// Only chunks of this file, or of files below this directory
+        #[arg(long, value_name = "SOURCE_PATH")]
+        path: Option<String>,
+
+        /// Only chunks dated before this day (YYYY-MM-DD); undated chunks are kept
+        #[arg(long, value_name = "DATE", value_parser = parse_date)]
+        before: Option<NaiveDate>,
+
+        /// Delete every chunk of the agent
+        #[arg(long, conflicts_with_all = ["source", "path", "before"])]
+        all: bool,
+
+        /// Actually delete; without it only the number of matching chunks is reported
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Run an MCP server on stdin/stdout with `search_memory` and `index_incremental` tools
+    Serve,
+    /// Serve `GET /search` and `POST /index/incremental` over HTTP for local agents to share
+    ServeHttp {
+        /// Port to listen on (0 picks a free one)
+        #[arg(long, default_value_t = 8765)]
+        port: u16,
+
+        /// Address to bind; use 0.0.0.0 to accept connections from other machines
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+    },
+    /// Add the columns and tables newer versions expect to an existing chunk table
+    Migrate,
     /// Show health/stats
     Health,
+    /// Check config, sources, database and embedding model, with hints for anything broken
+    Doctor,
+    /// List the source labels in use, with counts, flagging likely typos
+    Sources {
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show what is stored, per source
+    Stats {
+        /// Instead, show how similar the nearest chunks are to this query
+        #[arg(long, value_name = "QUERY")]
+        histogram: Option<String>,
+
+        /// Chunks to include in the histogram
+        #[arg(short, long, default_value = "100")]
+        top: i64,
+    },
+    /// Print randomly chosen stored chunks, to spot junk the chunker let through
+    Sample {
+        /// Number of chunks
+        #[arg(short, long, default_value = "10")]
+        n: i64,
+
+        /// Only chunks with this source label
+        #[arg(long, value_name = "LABEL")]
+        source: Option<String>,
+
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show how a single file would be chunked (no embedding, no DB)
+    Preview {
+        /// File to chunk (.jsonl/.json is parsed as a transcript)
+        path: PathBuf,
+
+        /// Max characters per chunk (defaults to config max_chars)
+        #[arg(long)]
+        max_chars: Option<usize>,
+
+        /// Budget chunks in characters or tokens (defaults to config chunk_strategy)
+        #[arg(long, value_enum)]
+        strategy: Option<config::ChunkStrategy>,
+
+        /// Squeeze blank lines and repeated spaces before chunking
+        #[arg(long)]
+        collapse_whitespace: bool,
+    },
+    /// Embed a string and print its vector (debugging aid)
+    Embed {
+        /// Text to embed
+        text: String,
+
+        /// Also embed this text and print the cosine similarity
+        #[arg(long)]
+        compare: Option<String>,
+
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the cosine similarity between two texts
+    Compare {
+        /// First text
+        a: String,
+
+        /// Second text
+        b: String,
+
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
-    let cfg = config::Config::load(cli.config.as_deref())?;
+    if let Commands::Doctor = cli.command {
+        // Runs its own config load so a broken config is reported, not fatal
+        return doctor::run(cli.config.as_deref(), cli.env_file.as_deref()).await;
+    }
+    let mut cfg = config::Config::load(cli.config.as_deref(), cli.env_file.as_deref())?;
+    cfg.quiet |= cli.quiet;
+    if let Some(port) = cli.metrics_port {
+        telemetry::install(cli.metrics_host, port)?;
+    }
+
+    run(cli.command, cfg).await.map_err(db::explain)
+}
 
-    match cli.command {
-        Commands::Search { query, top, json } => {
-            search::search(&cfg, &query, top, json).await?;
+async fn run(command: Commands, mut cfg: config::Config) -> Result<()> {
+    match command {
+        Commands::Search {
+            query,
+            queries,
+            queries_file,
+            like_file,
+            fusion,
+            hybrid,
+            keyword_weight,
+            dedup,
+            lang,
+            since,
+            until,
+            content_contains,
+            top,
+            offset,
+            json,
+            format,
+            template,
+            group_by_source,
+            rerank,
+            federated,
+            fields,
+            relative_to,
+            absolute_paths,
+            level,
+            json_envelope,
+            fail_if_empty,
+            recency_weight,
+            min_content_chars,
+            min_similarity,
+            trim_results,
+            show_embedding,
+            offline,
+            no_cache,
+            count,
+        } => {
+            if let (Some(since), Some(until)) = (&since, &until) {
+                if since > until {
+                    anyhow::bail!("--since {} is after --until {}", since, until);
+                }
+            }
+            let opts = search::SearchOptions {
+                top_k: top,
+                offset,
+                format: match (json, &template) {
+                    (true, _) => search::OutputFormat::Json,
+                    (false, Some(_)) => search::OutputFormat::Template,
+                    (false, None) => format,
+                },
+                group_by_source,
+                rerank,
+                federated,
+                fields: search::parse_fields(&fields)?,
+                relative_to: if absolute_paths {
+                    None
+                } else {
+                    Some(relative_to.unwrap_or_else(|| cfg.config_dir.clone()))
+                },
+                level,
+                min_content_chars,
+                min_similarity,
+                recency_weight,
+                json_envelope,
+                offline,
+                count,
+                fusion,
+                dedup,
+                template: template
+                    .as_deref()
+                    .map(search::parse_template)
+                    .transpose()?,
+                lang,
+                since,
+                until,
+                content_contains,
+                trim_results,
+                show_embedding,
+                example: match &like_file {
+                    Some(path) => index::example_chunks(&cfg, path)?,
+                    None => Vec::new(),
+                },
+                no_cache,
+                hybrid,
+                keyword_weight,
+            };
+            let mut all: Vec<String> = query.into_iter().chain(queries).collect();
+            if let Some(path) = &queries_file {
+                let text = std::fs::read_to_string(path).map_err(|e| {
+                    anyhow::anyhow!("Cannot read queries file {}: {}", path.display(), e)
+                })?;
+                all.extend(
+                    text.lines()
+                        .map(str::trim)
+                        .filter(|l| !l.is_empty())
+                        .map(str::to_string),
+                );
+            }
+            if let Some(path) = &like_file {
+                all.push(format!("like {}", path.display()));
+            }
+            if all.is_empty() {
+                anyhow::bail!("No queries given");
+            }
+            let found = search::search(&cfg, &all, &opts).await?;
+            if fail_if_empty && found == 0 {
+                std::io::Write::flush(&mut std::io::stdout())?;
+                std::process::exit(EXIT_NO_RESULTS);
+            }
         }
-        Commands::Index => {
-            println!("🐑⚡ Full index for agent '{}'...\n", cfg.agent_id);
-            index::run_full_index(&cfg).await?;
+        Commands::Index {
+            chunk_report,
+            profile,
+            no_resume,
+            batch_commit,
+            copy,
+            index_args,
+        } => {
+            index_args.apply(&mut cfg);
+            cfg.bulk_copy |= copy;
+            if !cfg.quiet {
+                println!("🐑⚡ Full index for agent '{}'...\n", cfg.agent_id);
+            }
+            index::run_full_index(
+                &cfg,
+                chunk_report,
+                profile,
+                !no_resume,
+                batch_commit.map(|n| n as usize),
+            )
+            .await?;
+        }
+        Commands::IndexIncremental {
+            chunk_report,
+            profile,
+            since_commit,
+            index_args,
+        } => {
+            index_args.apply(&mut cfg);
+            if !cfg.quiet {
+                println!("🐑⚡ Incremental index for agent '{}'...\n", cfg.agent_id);
+            }
+            index::run_incremental_index(&cfg, chunk_report, profile, since_commit.as_deref())
+                .await?
+                .finish(&cfg)?;
+        }
+        Commands::Watch {
+            poll_interval,
+            index_args,
+        } => {
+            index_args.apply(&mut cfg);
+            index::run_watch(&cfg, std::time::Duration::from_secs(poll_interval)).await?;
+        }
+        Commands::IndexStdin {
+            label,
+            path,
+            format,
+            source_date,
+            index_args,
+        } => {
+            index_args.apply(&mut cfg);
+            if let Some(date) = &source_date {
+                config::check_date(date)?;
+            }
+            cfg.check_label(&label)?;
+            let path = path.unwrap_or_else(|| format!("stdin://{}", label));
+            index::run_stdin_index(&cfg, &label, &path, format, source_date.as_deref()).await?;
+        }
+        Commands::EmbedPending {
+            concurrency,
+            embedding_batch,
+        } => {
+            if let Some(n) = embedding_batch {
+                cfg.embed_batch_size = n;
+            }
+            index::run_embed_pending(
+                &cfg,
+                concurrency.map_or(cfg.embed_concurrency, |c| c as usize),
+            )
+            .await?;
         }
-        Commands::IndexIncremental => {
-            println!("🐑⚡ Incremental index for agent '{}'...\n", cfg.agent_id);
-            index::run_incremental_index(&cfg).await?;
+        Commands::Merge { from, to } => {
+            index::run_merge(&cfg, &from, &to).await?;
+        }
+        Commands::Delete {
+            source,
+            path,
+            before,
+            all,
+            yes,
+        } => {
+            let filter = index::DeleteFilter {
+                source,
+                path,
+                before,
+                all,
+            };
+            index::run_delete(&cfg, filter, yes).await?;
+        }
+        Commands::Serve => {
+            mcp::run(&cfg).await?;
+        }
+        Commands::ServeHttp { port, host } => {
+            http::run(&cfg, &host, port).await?;
+        }
+        Commands::Migrate => {
+            println!("🐑⚡ Migrating {}...\n", cfg.table_name());
+            migrate::run(&cfg).await?;
         }
         Commands::Health => {
             health(&cfg).await?;
         }
+        Commands::Doctor => unreachable!("handled before loading config"),
+        Commands::Stats { histogram, top } => match histogram {
+            Some(query) => search::similarity_histogram(&cfg, &query, top).await?,
+            None => stats(&cfg).await?,
+        },
+        Commands::Sources { json } => {
+            sources(&cfg, json).await?;
+        }
+        Commands::Sample { n, source, json } => {
+            sample(&cfg, n, source.as_deref(), json).await?;
+        }
+        Commands::Embed {
+            text,
+            compare,
+            json,
+        } => {
+            embed_text(&cfg, &text, compare.as_deref(), json).await?;
+        }
+        Commands::Compare { a, b, json } => {
+            compare(&cfg, &a, &b, json).await?;
+        }
+        Commands::Preview {
+            path,
+            max_chars,
+            strategy,
+            collapse_whitespace,
+        } => {
+            cfg.collapse_whitespace |= collapse_whitespace;
+            if let Some(strategy) = strategy {
+                cfg.chunk_strategy = strategy;
+            }
+            preview(&cfg, &path, max_chars.unwrap_or(cfg.max_chars))?;
+        }
     }
 
     Ok(())
 }
 
+/// A `--lang` code whatlang can detect, so a typo doesn't silently match nothing.
+fn parse_lang(s: &str) -> std::result::Result<String, String> {
+    let code = s.trim().to_ascii_lowercase();
+    match whatlang::Lang::from_code(&code) {
+        Some(_) => Ok(code),
+        None => Err(format!(
+            "expected an ISO 639-3 language code (e.g. eng, deu, fra), got {:?}",
+            s
+        )),
+    }
+}
+
+/// A `--since`/`--until`/`--before` calendar date, `YYYY-MM-DD`.
+fn parse_date(s: &str) -> std::result::Result<NaiveDate, String> {
+    let date = s.trim();
+    // chrono alone would also take `2025-1-2` and five-digit years
+    config::check_date(date).map_err(|_| format!("expected a date as YYYY-MM-DD, got {:?}", s))?;
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| format!("{:?} is not a calendar date", s))
+}
+
+/// A `--min-similarity` in -1.0..=1.0, the range of cosine similarity.
+fn parse_similarity(s: &str) -> std::result::Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(v) if (-1.0..=1.0).contains(&v) => Ok(v),
+        _ => Err(format!("expected a number between -1 and 1, got {:?}", s)),
+    }
+}
+
+/// A `--recency-weight` in 0.0..=1.0.
+fn parse_weight(s: &str) -> std::result::Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(w) if (0.0..=1.0).contains(&w) => Ok(w),
+        _ => Err(format!("expected a number between 0 and 1, got {:?}", s)),
+    }
+}
+
 async fn health(config: &config::Config) -> Result<()> {
-    let (client, connection) =
-        tokio_postgres::connect(&config.db_url, tokio_postgres::NoTls).await?;
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("DB connection error: {}", e);
-        }
-    });
-
-    let msgs = client
-        .simple_query(&format!(
-            "SELECT count(*) FROM {}.chunks WHERE agent_id = '{}'",
-            config.schema, config.agent_id
-        ))
-        .await?;
-    let count = if let Some(tokio_postgres::SimpleQueryMessage::Row(row)) = msgs.first() {
-        row.get(0).unwrap_or("0")
-    } else {
-        "0"
+    let timeout = config.query_timeout_secs;
+    let client = db::connect(config.read_url(), timeout).await?;
+    let mut binds =
+        search::Binds::new(db::use_prepared(config.read_url(), &client, config.query_mode).await?);
+
+    let sql = format!(
+        "SELECT count(*)::text, (count(*) FILTER (WHERE {} IS NULL))::text FROM {} WHERE agent_id = {}",
+        config.embedding_column,
+        config.table_name(),
+        binds.bind(&config.agent_id)
+    );
+    let counts = search::read_rows(&client, &sql, &binds, timeout).await?;
+    let count_at = |i: usize| {
+        counts
+            .first()
+            .and_then(|row| row.get(i).cloned().flatten())
+            .unwrap_or_else(|| "0".to_string())
     };
+    let (count, pending) = (count_at(0), count_at(1));
 
-    let msgs2 = client
-        .simple_query(&format!(
-            "SELECT DISTINCT agent_id FROM {}.chunks ORDER BY agent_id",
-            config.schema
-        ))
-        .await?;
+    let msgs2 = search::read_query(
+        &client,
+        &format!(
+            "SELECT DISTINCT agent_id FROM {} ORDER BY agent_id",
+            config.table_name()
+        ),
+        timeout,
+    )
+    .await?;
     let agents: Vec<String> = msgs2
         .iter()
         .filter_map(|m| {
@@ -104,13 +824,435 @@ async fn health(config: &config::Config) -> Result<()> {
         })
         .collect();
 
+    let mut binds = search::Binds::new(binds.prepared);
+    let sql = format!(
+        "SELECT '1' FROM information_schema.columns
+         WHERE table_schema = {} AND table_name = {} AND column_name = 'content_tsv'",
+        binds.bind(&config.schema),
+        binds.bind(&config.table)
+    );
+    let keyword_ready = !search::read_rows(&client, &sql, &binds, timeout)
+        .await?
+        .is_empty();
+
+    // A configured dimension is trusted as-is so health works offline
+    let dimension = match config.model_dimension {
+        Some(d) => format!("{} (configured)", d),
+        None => match embed::get_embedding(config, "dimension probe").await {
+            Ok(v) => format!("{} (probed)", v.len()),
+            Err(e) => format!("unknown ({})", e),
+        },
+    };
+
     println!("🐑⚡ Agent Memory — Health");
     println!("  Agent:  {}", config.agent_id);
     println!("  Chunks: {}", count);
-    println!("  Schema: {}", config.schema);
+    if pending != "0" {
+        println!(
+            "  Pending: {} without an embedding (run `embed-pending`)",
+            pending
+        );
+    }
+    println!("  Table:  {}", config.table_name());
     println!("  Agents: {}", agents.join(", "));
     println!("  Ollama: {}", config.ollama_url);
     println!("  Model:  {}", config.model);
+    println!("  Dim:    {}", dimension);
+    if !keyword_ready {
+        println!("  ⚠️  No content_tsv column: `search --hybrid` needs db/013_add_content_tsv.sql (or `migrate`)");
+    }
+
+    Ok(())
+}
+
+async fn stats(config: &config::Config) -> Result<()> {
+    let client = db::connect(config.read_url(), None).await?;
+    let mut binds =
+        search::Binds::new(db::use_prepared(config.read_url(), &client, config.query_mode).await?);
+
+    let sql = format!(
+        "SELECT source, count(*)::text, count(DISTINCT source_path)::text, min(source_date)::text, max(source_date)::text
+         FROM {} WHERE agent_id = {} GROUP BY source ORDER BY count(*) DESC",
+        config.table_name(),
+        binds.bind(&config.agent_id)
+    );
+    let rows = search::read_rows(&client, &sql, &binds, None).await?;
+
+    println!("🐑⚡ Agent Memory — Stats for '{}'\n", config.agent_id);
+    let mut total = 0;
+    for row in &rows {
+        let get = |i: usize| row.get(i).and_then(|v| v.as_deref());
+        let chunks: i64 = get(1).unwrap_or("0").parse().unwrap_or(0);
+        total += chunks;
+        println!(
+            "  {:<20} {:>7} chunks  {:>5} files  {} → {}",
+            get(0).unwrap_or(""),
+            chunks,
+            get(2).unwrap_or("0"),
+            get(3).unwrap_or("n/a"),
+            get(4).unwrap_or("n/a")
+        );
+    }
+    println!("\n  Total: {} chunks", total);
+    Ok(())
+}
+
+/// Labels in the table with their chunk and file counts, each marked as
+/// configured, allowed by `source_labels`, or a likely variant of one that is.
+async fn sources(config: &config::Config, json: bool) -> Result<()> {
+    let timeout = config.query_timeout_secs;
+    let client = db::connect(config.read_url(), timeout).await?;
+    let mut binds =
+        search::Binds::new(db::use_prepared(config.read_url(), &client, config.query_mode).await?);
+    let sql = format!(
+        "SELECT source, count(*)::text, count(DISTINCT source_path)::text FROM {} WHERE agent_id = {}
+         GROUP BY source ORDER BY source",
+        config.table_name(),
+        binds.bind(&config.agent_id)
+    );
+    let rows = search::read_rows(&client, &sql, &binds, timeout).await?;
+
+    let mut configured: Vec<String> = config.sources.iter().map(|s| s.label()).collect();
+    configured.extend(index::folder_labels(config));
+    let known: Vec<&String> = configured.iter().chain(&config.source_labels).collect();
+    let mut labels = Vec::new();
+    for row in &rows {
+        let get = |i: usize| row.get(i).and_then(|v| v.as_deref());
+        let label = get(0).unwrap_or("").to_string();
+        let status = if label == index::DOCUMENT_SOURCE {
+            "document vectors".to_string()
+        } else if known.iter().any(|k| **k == label) {
+            if configured.contains(&label) {
+                "configured"
+            } else {
+                "allowed"
+            }
+            .to_string()
+        } else if let Some(close) = known
+            .iter()
+            .find(|k| config::label_key(k) == config::label_key(&label))
+        {
+            format!("looks like '{}'", close)
+        } else if !config.source_labels.is_empty() {
+            "not in source_labels".to_string()
+        } else {
+            "not configured".to_string()
+        };
+        labels.push(serde_json::json!({
+            "source": label,
+            "chunks": get(1).and_then(|s| s.parse::<i64>().ok()).unwrap_or(0),
+            "files": get(2).and_then(|s| s.parse::<i64>().ok()).unwrap_or(0),
+            "status": status,
+        }));
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&labels)?);
+        return Ok(());
+    }
+    println!("🐑⚡ Source labels for '{}'\n", config.agent_id);
+    if labels.is_empty() {
+        println!("  (no chunks)");
+    }
+    for l in &labels {
+        let status = l["status"].as_str().unwrap_or("");
+        let flag = if status.starts_with("looks like") || status == "not in source_labels" {
+            "⚠️ "
+        } else {
+            ""
+        };
+        println!(
+            "  {:<20} {:>7} chunks  {:>5} files  {}{}",
+            l["source"].as_str().unwrap_or(""),
+            l["chunks"],
+            l["files"],
+            flag,
+            status
+        );
+    }
+    Ok(())
+}
+
+/// Tables estimated above this many rows are sampled with `TABLESAMPLE`
+/// instead of sorting every row by `random()`.
+const SAMPLE_SCAN_ROWS: f64 = 100_000.0;
+
+async fn sample(config: &config::Config, n: i64, source: Option<&str>, json: bool) -> Result<()> {
+    let timeout = config.query_timeout_secs;
+    let client = db::connect(config.read_url(), timeout).await?;
+
+    let estimate: f64 = search::read_query(
+        &client,
+        &format!(
+            "SELECT reltuples FROM pg_class WHERE oid = to_regclass('{}')",
+            config.table_name()
+        ),
+        timeout,
+    )
+    .await?
+    .iter()
+    .find_map(|m| match m {
+        tokio_postgres::SimpleQueryMessage::Row(row) => row.get(0).and_then(|s| s.parse().ok()),
+        _ => None,
+    })
+    .unwrap_or(0.0);
+    // Oversample tenfold so the agent/source filter still leaves enough rows
+    let tablesample = if estimate > SAMPLE_SCAN_ROWS {
+        let percent = (n as f64 * 10.0 * 100.0 / estimate).clamp(0.01, 100.0);
+        format!("TABLESAMPLE SYSTEM ({})", percent)
+    } else {
+        String::new()
+    };
+    let mut binds =
+        search::Binds::new(db::use_prepared(config.read_url(), &client, config.query_mode).await?);
+    let agent = binds.bind(&config.agent_id);
+    let source_clause = match source {
+        Some(label) => format!(" AND source = {}", binds.bind(label)),
+        None => String::new(),
+    };
+    let sql = format!(
+        "SELECT content, encode(content_compressed, 'hex'), source, source_path, source_date::text, title,
+                start_offset::text, end_offset::text
+         FROM {} {} WHERE agent_id = {}{} ORDER BY random() LIMIT {}",
+        config.table_name(),
+        tablesample,
+        agent,
+        source_clause,
+        n
+    );
+
+    let mut rows = Vec::new();
+    for row in search::read_rows(&client, &sql, &binds, timeout).await? {
+        let get = |i: usize| row.get(i).and_then(|v| v.as_deref());
+        let content = match (get(0), get(1)) {
+            (Some(text), _) => text.to_string(),
+            (None, Some(hex)) => compress::decompress_hex(hex)?,
+            (None, None) => String::new(),
+        };
+        rows.push(serde_json::json!({
+            "content": content,
+            "chars": content.chars().count(),
+            "source": get(2),
+            "source_path": get(3),
+            "source_date": get(4),
+            "title": get(5),
+            "start_offset": get(6).and_then(|s| s.parse::<i64>().ok()),
+            "end_offset": get(7).and_then(|s| s.parse::<i64>().ok()),
+        }));
+    }
 
+    if json {
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+        return Ok(());
+    }
+    if rows.is_empty() {
+        match source {
+            Some(label) => println!(
+                "No chunks with source '{}' for agent '{}'.",
+                label, config.agent_id
+            ),
+            None => println!("No chunks for agent '{}'.", config.agent_id),
+        }
+        return Ok(());
+    }
+    println!(
+        "🎲 {} random chunk(s) for agent '{}'\n",
+        rows.len(),
+        config.agent_id
+    );
+    for r in &rows {
+        let text = |k: &str| r[k].as_str().unwrap_or("n/a").to_string();
+        let span = match (r["start_offset"].as_i64(), r["end_offset"].as_i64()) {
+            (Some(s), Some(e)) => format!(" | chars {}..{}", s, e),
+            _ => String::new(),
+        };
+        println!(
+            "--- {} | {} | {}{} | {} chars ---",
+            text("source"),
+            text("source_path"),
+            text("source_date"),
+            span,
+            r["chars"]
+        );
+        if let Some(title) = r["title"].as_str() {
+            println!("# {}", title);
+        }
+        println!("{}\n", text("content"));
+    }
     Ok(())
 }
+
+fn preview(config: &config::Config, path: &Path, max_chars: usize) -> Result<()> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Cannot read {}: {}", path.display(), e))?;
+
+    let is_transcript = index::is_transcript_file(path);
+    let (chunks, source_date) = if is_transcript {
+        let chunks = chunk::parse_transcript(&text, &config.include_roles)
+            .into_iter()
+            .map(|content| (content, None))
+            .collect::<Vec<_>>();
+        (
+            chunks,
+            chunk::extract_date(&path.file_stem().unwrap_or_default().to_string_lossy()),
+        )
+    } else {
+        let chunks = index::markdown_chunks(config, &text, max_chars)
+            .into_iter()
+            .map(|c| (c.content, Some((c.start, c.end))))
+            .collect::<Vec<_>>();
+        (
+            chunks,
+            chunk::extract_date(&path.file_name().unwrap_or_default().to_string_lossy()).or_else(
+                || {
+                    chunk::parse_frontmatter(&text)
+                        .0
+                        .as_ref()
+                        .and_then(chunk::frontmatter_date)
+                },
+            ),
+        )
+    };
+
+    println!("🐑⚡ Chunk preview — {}", path.display());
+    println!(
+        "  Mode:  {}",
+        if is_transcript {
+            "transcript"
+        } else {
+            "markdown"
+        }
+    );
+    if !is_transcript {
+        if config.chunk_strategy == config::ChunkStrategy::Tokens {
+            println!("  Max:   {} tokens", config.max_tokens);
+        } else {
+            println!("  Max:   {} chars", max_chars);
+        }
+        if config.collapse_whitespace {
+            println!("  Whitespace: collapsed");
+        }
+    }
+    println!("  Date:  {}", source_date.as_deref().unwrap_or("n/a"));
+    println!("  Chunks: {}\n", chunks.len());
+
+    let by_tokens = !is_transcript && config.chunk_strategy == config::ChunkStrategy::Tokens;
+    for (i, (c, span)) in chunks.iter().enumerate() {
+        let mut size = format!("{} chars", c.chars().count());
+        if by_tokens {
+            size.push_str(&format!(", {} tokens", chunk::token_count(c)));
+        }
+        match span {
+            Some((start, end)) => println!("--- [{}] {} @ {}..{} ---", i + 1, size, start, end),
+            None => println!("--- [{}] {} ---", i + 1, size),
+        }
+        println!("{}\n", c);
+    }
+
+    Ok(())
+}
+
+async fn embed_text(
+    config: &config::Config,
+    text: &str,
+    compare: Option<&str>,
+    json: bool,
+) -> Result<()> {
+    let started = Instant::now();
+    let embedding = embed::get_embedding(config, text).await?;
+    let elapsed = started.elapsed();
+
+    let similarity = match compare {
+        Some(other) => {
+            let other_embedding = embed::get_embedding(config, other).await?;
+            Some(embed::cosine_similarity(&embedding, &other_embedding))
+        }
+        None => None,
+    };
+
+    if json {
+        let mut out = serde_json::json!({
+            "model": config.model,
+            "dimension": embedding.len(),
+            "elapsed_ms": elapsed.as_millis() as u64,
+            "embedding": embedding,
+        });
+        if let Some(sim) = similarity {
+            out["similarity"] = serde_json::json!(sim);
+        }
+        println!("{}", serde_json::to_string_pretty(&out)?);
+    } else {
+        println!("🐑⚡ Embedding — {}", config.model);
+        println!("  Dimension: {}", embedding.len());
+        println!("  Time:      {} ms", elapsed.as_millis());
+        if let Some(sim) = similarity {
+            println!("  Cosine similarity vs --compare: {:.4}", sim);
+        }
+        println!(
+            "  Vector:    [{}]",
+            embedding
+                .iter()
+                .map(|v| format!("{:.6}", v))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+async fn compare(config: &config::Config, a: &str, b: &str, json: bool) -> Result<()> {
+    let embedding_a = embed::get_embedding(config, a).await?;
+    let embedding_b = embed::get_embedding(config, b).await?;
+    let similarity = embed::cosine_similarity(&embedding_a, &embedding_b);
+
+    if json {
+        let out = serde_json::json!({
+            "model": config.model,
+            "a": a,
+            "b": b,
+            "similarity": similarity,
+        });
+        println!("{}", serde_json::to_string_pretty(&out)?);
+    } else {
+        println!(
+            "🐑⚡ {:.4} cosine similarity ({})",
+            similarity, config.model
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_must_be_real_calendar_days() {
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        assert_eq!(parse_date(" 2025-01-31 ").unwrap(), date(2025, 1, 31));
+        assert_eq!(parse_date("2024-02-29").unwrap(), date(2024, 2, 29));
+        assert_eq!(parse_date("2000-02-29").unwrap(), date(2000, 2, 29));
+        for bad in [
+            "2023-02-29",
+            "1900-02-29",
+            "2025-04-31",
+            "2025-13-01",
+            "2025-00-10",
+            "2025-01-00",
+        ] {
+            let err = parse_date(bad).unwrap_err();
+            assert!(err.contains("is not a calendar date"), "{}: {}", bad, err);
+        }
+        for bad in ["01/02/2025", "2025-1-2", "20250102", ""] {
+            let err = parse_date(bad).unwrap_err();
+            assert!(
+                err.starts_with("expected a date as YYYY-MM-DD"),
+                "{}: {}",
+                bad,
+                err
+            );
+        }
+    }
+}
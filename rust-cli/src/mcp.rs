@@ -0,0 +1,362 @@
+//! `agent-memory serve`: a Model Context Protocol server on stdin/stdout, so
+//! agents can search and refresh memory as tools instead of shelling out and
+//! parsing text. Messages are newline-delimited JSON-RPC 2.0; stdout carries
+//! nothing else, so warnings and skipped-file reports go to stderr.
+
+use anyhow::Result;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::config::Config;
+use crate::{db, index, search};
+
+/// Protocol revisions this server speaks, newest first. A client asking for
+/// another one is offered the newest.
+const PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// Answer requests until stdin closes. Requests are handled one at a time.
+pub async fn run(config: &Config) -> Result<()> {
+    // Per-file progress lines would land in the protocol stream
+    let mut config = config.clone();
+    config.quiet = true;
+
+    serve(
+        &config,
+        BufReader::new(tokio::io::stdin()),
+        tokio::io::stdout(),
+    )
+    .await
+}
+
+/// The message loop of `run`, over any line reader and writer.
+async fn serve(
+    config: &Config,
+    input: impl AsyncBufRead + Unpin,
+    mut output: impl AsyncWrite + Unpin,
+) -> Result<()> {
+    let mut lines = input.lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match serde_json::from_str::<Value>(&line) {
+            Ok(message) => handle(config, message).await,
+            Err(e) => Some(error(Value::Null, -32700, format!("Parse error: {}", e))),
+        };
+        if let Some(reply) = reply {
+            output.write_all(format!("{}\n", reply).as_bytes()).await?;
+            output.flush().await?;
+        }
+    }
+    Ok(())
+}
+
+/// The reply to one message: `None` for notifications (`initialized`,
+/// `cancelled`) and for responses from the client.
+async fn handle(config: &Config, message: Value) -> Option<Value> {
+    let id = message.get("id")?.clone();
+    let method = message.get("method")?.as_str().unwrap_or_default();
+    let params = message.get("params").cloned().unwrap_or(Value::Null);
+    let result = match method {
+        "initialize" => Ok(initialize(&params)),
+        "ping" => Ok(json!({})),
+        "tools/list" => Ok(json!({ "tools": tools() })),
+        "tools/call" => call_tool(config, &params).await,
+        _ => Err((-32601, format!("Method not found: {}", method))),
+    };
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message)) => error(id, code, message),
+    })
+}
+
+fn error(id: Value, code: i64, message: String) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+fn initialize(params: &Value) -> Value {
+    let requested = params.get("protocolVersion").and_then(|v| v.as_str());
+    let version = requested
+        .filter(|v| PROTOCOL_VERSIONS.contains(v))
+        .unwrap_or(PROTOCOL_VERSIONS[0]);
+    json!({
+        "protocolVersion": version,
+        "capabilities": { "tools": {} },
+        "serverInfo": { "name": "agent-memory", "version": env!("CARGO_PKG_VERSION") },
+    })
+}
+
+/// `tools/list`: name, description and JSON Schema of each tool's arguments.
+fn tools() -> Value {
+    json!([
+        {
+            "name": "search_memory",
+            "description": "Semantic search over this agent's indexed notes and transcripts. \
+                            Returns the best-matching chunks, most similar first.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string", "description": "What to look for, in natural language" },
+                    "top_k": { "type": "integer", "minimum": 1, "default": 5, "description": "Number of results" },
+                    "since": { "type": "string", "description": "Only chunks dated on or after this day (YYYY-MM-DD)" },
+                    "until": { "type": "string", "description": "Only chunks dated on or before this day (YYYY-MM-DD)" },
+                    "lang": { "type": "string", "description": "Only chunks in this language (ISO 639-3, e.g. eng)" },
+                    "min_similarity": {
+                        "type": "number",
+                        "minimum": -1,
+                        "maximum": 1,
+                        "description": "Drop results less similar than this"
+                    },
+                    "content_contains": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "Only chunks containing every one of these texts (case-insensitive)"
+                    },
+                    "hybrid": { "type": "boolean", "description": "Also rank by exact keyword matches" }
+                },
+                "required": ["query"]
+            }
+        },
+        {
+            "name": "index_incremental",
+            "description": "Index new and modified files from the configured sources and drop deleted ones. \
+                            Returns how many files and chunks changed.",
+            "inputSchema": { "type": "object", "properties": {} }
+        }
+    ])
+}
+
+/// `tools/call`. A failing tool is a result with `isError`, which the model
+/// gets to see; only an unknown tool is a protocol error.
+async fn call_tool(config: &Config, params: &Value) -> std::result::Result<Value, (i64, String)> {
+    let name = params
+        .get("name")
+        .and_then(|n| n.as_str())
+        .unwrap_or_default();
+    let args = params
+        .get("arguments")
+        .cloned()
+        .unwrap_or_else(|| json!({}));
+    let outcome = match name {
+        "search_memory" => search_memory(config, &args).await,
+        "index_incremental" => index::run_incremental_index(config, false, false, None)
+            .await
+            .and_then(|summary| Ok(serde_json::to_value(summary)?)),
+        _ => return Err((-32602, format!("Unknown tool: {}", name))),
+    };
+    Ok(match outcome {
+        Ok(value) => json!({
+            "content": [{ "type": "text", "text": value.to_string() }],
+            "structuredContent": value,
+            "isError": false,
+        }),
+        Err(e) => json!({
+            "content": [{ "type": "text", "text": format!("{:#}", db::explain(e)) }],
+            "isError": true,
+        }),
+    })
+}
+
+async fn search_memory(config: &Config, args: &Value) -> Result<Value> {
+    let text = |key: &str| args.get(key).and_then(|v| v.as_str()).map(str::to_string);
+    let query = text("query")
+        .filter(|q| !q.trim().is_empty())
+        .ok_or_else(|| anyhow::anyhow!("`query` is required"))?;
+    let checked = |key: &str, parse: fn(&str) -> std::result::Result<String, String>| {
+        text(key)
+            .map(|v| parse(&v).map_err(|e| anyhow::anyhow!("`{}`: {}", key, e)))
+            .transpose()
+    };
+    let date = |key: &str| {
+        text(key)
+            .map(|v| crate::parse_date(&v).map_err(|e| anyhow::anyhow!("`{}`: {}", key, e)))
+            .transpose()
+    };
+    let min_similarity = match args.get("min_similarity").and_then(|v| v.as_f64()) {
+        Some(v) if !(-1.0..=1.0).contains(&v) => {
+            anyhow::bail!("`min_similarity` must be between -1 and 1")
+        }
+        v => v,
+    };
+    let top_k = match args.get("top_k") {
+        Some(v) => v.as_i64().filter(|&top_k| top_k >= 1).ok_or_else(|| {
+            anyhow::anyhow!("`top_k` must be an integer of at least 1, got {}", v)
+        })?,
+        None => 5,
+    };
+    let mut opts = search::SearchOptions::json(top_k);
+    opts.min_similarity = min_similarity;
+    opts.lang = checked("lang", crate::parse_lang)?;
+    opts.since = date("since")?;
+    opts.until = date("until")?;
+    opts.content_contains = args
+        .get("content_contains")
+        .and_then(|v| v.as_array())
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|i| i.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default();
+    opts.hybrid = args
+        .get("hybrid")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    let (results, _) = search::run(config, &[query], &opts).await?;
+    Ok(json!({ "results": results }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    /// Feed `requests` to the server as stdin lines and return its replies.
+    async fn replay(config: &Config, requests: &[&str]) -> Vec<Value> {
+        let input = requests.join("\n");
+        let mut output = Vec::new();
+        serve(config, input.as_bytes(), &mut output).await.unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn handshake_listing_and_errors_follow_json_rpc() {
+        let config = testing::config("host=127.0.0.1 port=1", "http://127.0.0.1:1/api/embeddings");
+        let replies = replay(
+            &config,
+            &[
+                r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"2025-03-26","capabilities":{},"clientInfo":{"name":"test","version":"0"}}}"#,
+                r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#,
+                "",
+                r#"{"jsonrpc":"2.0","id":2,"method":"tools/list"}"#,
+                r#"{"jsonrpc":"2.0","id":"three","method":"ping"}"#,
+                r#"{"jsonrpc":"2.0","id":4,"method":"tools/call","params":{"name":"search_memory","arguments":{"query":" "}}}"#,
+                r#"{"jsonrpc":"2.0","id":5,"method":"tools/call","params":{"name":"search_memory","arguments":{"query":"x","since":"2025-02-30"}}}"#,
+                r#"{"jsonrpc":"2.0","id":6,"method":"tools/call","params":{"name":"forget_everything"}}"#,
+                r#"{"jsonrpc":"2.0","id":7,"method":"resources/list"}"#,
+                r#"{"jsonrpc":"2.0","id":8,"method":"tools/call","params":{"name":"search_memory","arguments":{"query":"x","top_k":0}}}"#,
+                "{not json",
+            ],
+        )
+        .await;
+        assert_eq!(replies.len(), 9, "{:#?}", replies);
+        let ids: Vec<&Value> = replies.iter().map(|r| &r["id"]).collect();
+        assert_eq!(
+            ids,
+            [
+                &json!(1),
+                &json!(2),
+                &json!("three"),
+                &json!(4),
+                &json!(5),
+                &json!(6),
+                &json!(7),
+                &json!(8),
+                &Value::Null
+            ]
+        );
+        assert!(replies.iter().all(|r| r["jsonrpc"] == "2.0"));
+
+        let init = &replies[0]["result"];
+        assert_eq!(init["protocolVersion"], "2025-03-26");
+        assert_eq!(init["serverInfo"]["name"], "agent-memory");
+        assert!(init["capabilities"]["tools"].is_object());
+
+        let tools = replies[1]["result"]["tools"].as_array().unwrap();
+        let names: Vec<&str> = tools.iter().map(|t| t["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["search_memory", "index_incremental"]);
+        assert_eq!(tools[0]["inputSchema"]["required"], json!(["query"]));
+
+        assert_eq!(replies[2]["result"], json!({}));
+
+        // Bad arguments are tool results the model can read, not protocol errors
+        assert_eq!(replies[3]["result"]["isError"], true);
+        assert!(replies[3]["result"]["content"][0]["text"]
+            .as_str()
+            .unwrap()
+            .contains("`query` is required"));
+        assert_eq!(replies[4]["result"]["isError"], true);
+        assert!(replies[4]["result"]["content"][0]["text"]
+            .as_str()
+            .unwrap()
+            .contains("`since`"));
+
+        assert_eq!(replies[5]["error"]["code"], -32602);
+        assert_eq!(replies[6]["error"]["code"], -32601);
+        assert_eq!(replies[7]["result"]["isError"], true);
+        assert!(replies[7]["result"]["content"][0]["text"]
+            .as_str()
+            .unwrap()
+            .contains("`top_k`"));
+        assert_eq!(replies[8]["error"]["code"], -32700);
+    }
+
+    #[test]
+    fn unknown_protocol_versions_are_offered_the_newest() {
+        let reply = initialize(&json!({ "protocolVersion": "1999-01-01" }));
+        assert_eq!(reply["protocolVersion"], PROTOCOL_VERSIONS[0]);
+        assert_eq!(
+            initialize(&Value::Null)["protocolVersion"],
+            PROTOCOL_VERSIONS[0]
+        );
+    }
+
+    #[tokio::test]
+    async fn recorded_session_indexes_then_searches() {
+        let Some(db_url) = testing::db_url() else {
+            return;
+        };
+        let embedder = testing::embedder().await;
+        let notes = testing::notes(&[
+            ("2025-01-06.md", testing::MONDAY),
+            ("2025-01-09.md", testing::TUESDAY),
+        ]);
+        let config = testing::notes_config(&db_url, &embedder, notes.path());
+
+        let replies = replay(
+            &config,
+            &[
+                r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"2025-06-18"}}"#,
+                r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#,
+                r#"{"jsonrpc":"2.0","id":2,"method":"tools/call","params":{"name":"index_incremental","arguments":{}}}"#,
+                r#"{"jsonrpc":"2.0","id":3,"method":"tools/call","params":{"name":"search_memory","arguments":{"query":"deploy disk space","top_k":1}}}"#,
+                r#"{"jsonrpc":"2.0","id":4,"method":"tools/call","params":{"name":"search_memory","arguments":{"query":"deploy disk space","since":"2025-01-08"}}}"#,
+            ],
+        )
+        .await;
+        assert_eq!(replies.len(), 4, "{:#?}", replies);
+
+        let indexed = &replies[1]["result"];
+        assert_eq!(indexed["isError"], false, "{}", indexed);
+        assert_eq!(indexed["structuredContent"]["new_files"], 2);
+
+        let found = &replies[2]["result"];
+        assert_eq!(found["isError"], false, "{}", found);
+        let results = found["structuredContent"]["results"].as_array().unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0]["content"]
+            .as_str()
+            .unwrap()
+            .contains("out of disk space"));
+        // The text block carries the same JSON for clients without structured content
+        let text: Value =
+            serde_json::from_str(found["content"][0]["text"].as_str().unwrap()).unwrap();
+        assert_eq!(text, found["structuredContent"]);
+
+        let later = replies[3]["result"]["structuredContent"]["results"]
+            .as_array()
+            .unwrap();
+        assert!(!later.is_empty());
+        assert!(later.iter().all(|r| r["source_path"]
+            .as_str()
+            .unwrap()
+            .ends_with("2025-01-09.md")));
+
+        testing::clear_agent(&config).await;
+    }
+}
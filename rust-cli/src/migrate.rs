@@ -0,0 +1,161 @@
+//! `agent-memory migrate`: bring an existing chunk table up to the shape this
+//! version expects. Mirrors `db/002`–`db/013` for the configured schema and
+//! table, recording what ran in `schema.schema_migrations`.
+
+use anyhow::Result;
+use tokio_postgres::SimpleQueryMessage;
+
+use crate::config::Config;
+use crate::db;
+
+/// `(version, name, sql)`, oldest first. Every statement is idempotent so a
+/// table created by a newer `db/001_init.sql` just records them as applied.
+/// `{schema}` is the schema, `{table}` the qualified table and `{name}` the
+/// bare table name (for index names).
+const MIGRATIONS: &[(i32, &str, &str)] = &[
+    (
+        2,
+        "add_agent_id",
+        "ALTER TABLE {table} ADD COLUMN IF NOT EXISTS agent_id TEXT NOT NULL DEFAULT 'default';
+         CREATE INDEX IF NOT EXISTS idx_{name}_agent_id ON {table}(agent_id);",
+    ),
+    (
+        3,
+        "add_chunk_offsets",
+        "ALTER TABLE {table}
+             ADD COLUMN IF NOT EXISTS start_offset INTEGER,
+             ADD COLUMN IF NOT EXISTS end_offset INTEGER;",
+    ),
+    (
+        4,
+        "add_title",
+        "ALTER TABLE {table} ADD COLUMN IF NOT EXISTS title TEXT;",
+    ),
+    (
+        5,
+        "add_content_compressed",
+        "ALTER TABLE {table} ADD COLUMN IF NOT EXISTS content_compressed BYTEA;
+         ALTER TABLE {table} ALTER COLUMN content DROP NOT NULL;",
+    ),
+    (
+        6,
+        "add_source_mtime",
+        "ALTER TABLE {table} ADD COLUMN IF NOT EXISTS source_mtime TIMESTAMPTZ;",
+    ),
+    (
+        7,
+        "add_index_state",
+        "CREATE TABLE IF NOT EXISTS {schema}.index_state (
+             agent_id TEXT NOT NULL,
+             source TEXT NOT NULL,
+             commit_sha TEXT,
+             updated_at TIMESTAMPTZ DEFAULT now(),
+             PRIMARY KEY (agent_id, source)
+         );",
+    ),
+    (
+        8,
+        "add_lang",
+        "ALTER TABLE {table} ADD COLUMN IF NOT EXISTS lang TEXT;
+         CREATE INDEX IF NOT EXISTS idx_{name}_lang ON {table}(lang);",
+    ),
+    (
+        9,
+        "add_indexed_bytes",
+        "ALTER TABLE {schema}.index_state ADD COLUMN IF NOT EXISTS indexed_bytes BIGINT;",
+    ),
+    (
+        10,
+        "add_metadata",
+        "ALTER TABLE {table} ADD COLUMN IF NOT EXISTS metadata JSONB;",
+    ),
+    (
+        11,
+        "add_embed_text",
+        "ALTER TABLE {table} ADD COLUMN IF NOT EXISTS embed_text TEXT;",
+    ),
+    (
+        12,
+        "add_content_hash",
+        "ALTER TABLE {table} ADD COLUMN IF NOT EXISTS content_hash TEXT;",
+    ),
+    (
+        13,
+        "add_content_tsv",
+        "ALTER TABLE {table} ADD COLUMN IF NOT EXISTS content_tsv tsvector
+             GENERATED ALWAYS AS (to_tsvector('simple', coalesce(content, ''))) STORED;
+         CREATE INDEX IF NOT EXISTS idx_{name}_content_tsv ON {table} USING gin(content_tsv);",
+    ),
+];
+
+/// Apply every migration not yet recorded for this table, each in its own
+/// transaction together with its `schema_migrations` row.
+pub async fn run(config: &Config) -> Result<()> {
+    let mut client = db::connect(&config.db_url, None).await?;
+
+    let table = config.table_name();
+    let exists = client
+        .simple_query(&format!("SELECT to_regclass('{}')", table))
+        .await?
+        .iter()
+        .any(|m| matches!(m, SimpleQueryMessage::Row(row) if row.get(0).is_some()));
+    if !exists {
+        anyhow::bail!(
+            "Table {} does not exist; create it with db/001_init.sql (migrate only upgrades existing tables)",
+            table
+        );
+    }
+
+    let log = format!("{}.schema_migrations", config.schema);
+    client
+        .simple_query(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                 table_name TEXT NOT NULL,
+                 version INTEGER NOT NULL,
+                 name TEXT NOT NULL,
+                 applied_at TIMESTAMPTZ DEFAULT now(),
+                 PRIMARY KEY (table_name, version)
+             )",
+            log
+        ))
+        .await?;
+    let applied: std::collections::HashSet<i32> = client
+        .simple_query(&format!(
+            "SELECT version FROM {} WHERE table_name = '{}'",
+            log, table
+        ))
+        .await?
+        .iter()
+        .filter_map(|m| match m {
+            SimpleQueryMessage::Row(row) => row.get(0).and_then(|v| v.parse().ok()),
+            _ => None,
+        })
+        .collect();
+
+    let mut ran = 0;
+    for &(version, name, sql) in MIGRATIONS.iter().filter(|(v, _, _)| !applied.contains(v)) {
+        let sql = sql
+            .replace("{schema}", &config.schema)
+            .replace("{table}", &table)
+            .replace("{name}", &config.table);
+        let tx = client.transaction().await?;
+        tx.simple_query(&sql)
+            .await
+            .map_err(|e| anyhow::anyhow!("Migration {:03} {} failed: {}", version, name, e))?;
+        tx.simple_query(&format!(
+            "INSERT INTO {} (table_name, version, name) VALUES ('{}', {}, '{}')",
+            log, table, version, name
+        ))
+        .await?;
+        tx.commit().await?;
+        println!("  ✅ {:03} {}", version, name);
+        ran += 1;
+    }
+
+    if ran == 0 {
+        println!("{} is up to date.", table);
+    } else {
+        println!("\nApplied {} migration(s) to {}.", ran, table);
+    }
+    Ok(())
+}
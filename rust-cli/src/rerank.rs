@@ -0,0 +1,182 @@
+//! Second-pass reranking: ask a generative model to score each (query, chunk)
+//! pair and re-sort the vector-search candidates by that score, and/or blend
+//! relevance with how recent each chunk's `source_date` is.
+
+use anyhow::Result;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::search::SearchResult;
+
+/// Scoring requests in flight at once.
+const RERANK_CONCURRENCY: usize = 4;
+
+#[derive(Serialize)]
+struct GenerateRequest<'a> {
+    model: &'a str,
+    prompt: String,
+    stream: bool,
+    options: GenerateOptions,
+}
+
+#[derive(Serialize)]
+struct GenerateOptions {
+    temperature: f64,
+}
+
+#[derive(Deserialize)]
+struct GenerateResponse {
+    response: String,
+}
+
+/// Score `candidates` against `query`, sort by score (similarity breaks ties)
+/// and keep the best `top_k`. Ranks are renumbered from `offset`.
+pub async fn rerank(
+    config: &Config,
+    query: &str,
+    candidates: Vec<SearchResult>,
+    top_k: usize,
+    offset: i64,
+) -> Result<Vec<SearchResult>> {
+    let Some(model) = config.rerank_model.as_deref() else {
+        anyhow::bail!("--rerank needs `rerank_model` set in config (e.g. an Ollama chat model)");
+    };
+    let url = generate_endpoint(&config.ollama_url);
+    let client = reqwest::Client::new();
+
+    // Futures built up front rather than in a `.map` on the stream, whose
+    // closure over `&SearchResult` keeps the whole search from being `Send`
+    let requests: Vec<_> = candidates
+        .iter()
+        .map(|c| score(&client, &url, model, query, &c.content))
+        .collect();
+    let scores: Vec<Result<f64>> = stream::iter(requests)
+        .buffered(RERANK_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut scored = Vec::with_capacity(candidates.len());
+    for (mut c, s) in candidates.into_iter().zip(scores) {
+        c.rerank_score = Some(s?);
+        scored.push(c);
+    }
+    scored.sort_by(|a, b| {
+        b.rerank_score
+            .partial_cmp(&a.rerank_score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(
+                b.similarity
+                    .partial_cmp(&a.similarity)
+                    .unwrap_or(std::cmp::Ordering::Equal),
+            )
+    });
+    scored.truncate(top_k);
+    for (i, r) in scored.iter_mut().enumerate() {
+        r.rank = offset + i as i64 + 1;
+    }
+    Ok(scored)
+}
+
+/// Relevance of `passage` to `query` in 0.0..=1.0, from the model's 0–10 rating.
+async fn score(
+    client: &reqwest::Client,
+    url: &str,
+    model: &str,
+    query: &str,
+    passage: &str,
+) -> Result<f64> {
+    let prompt = format!(
+        "Rate how relevant the passage is to the query on a scale from 0 (unrelated) to 10 \
+         (directly answers it). Reply with the number only.\n\nQuery: {}\nPassage: {}\n\nScore:",
+        query.replace('\n', " "),
+        passage
+    );
+    let resp = client
+        .post(url)
+        .json(&GenerateRequest {
+            model,
+            prompt,
+            stream: false,
+            options: GenerateOptions { temperature: 0.0 },
+        })
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<GenerateResponse>()
+        .await?;
+
+    let re = regex::Regex::new(r"\d+(\.\d+)?")?;
+    let value: f64 = re
+        .find(&resp.response)
+        .and_then(|m| m.as_str().parse().ok())
+        .unwrap_or(0.0);
+    Ok((value / 10.0).clamp(0.0, 1.0))
+}
+
+/// Re-sort by `(1 - weight) * relevance + weight * recency`, where relevance is
+/// the rerank score if present (else similarity) and recency halves every
+/// `half_life_days` of age. Undated chunks keep their relevance as the score.
+pub fn by_recency(
+    candidates: Vec<SearchResult>,
+    weight: f64,
+    half_life_days: f64,
+    top_k: usize,
+    offset: i64,
+) -> Vec<SearchResult> {
+    let today = days_since_epoch_now();
+    let mut scored = candidates;
+    for c in &mut scored {
+        let relevance = c.rerank_score.unwrap_or(c.similarity);
+        let score = match c.source_date.as_deref().and_then(parse_days) {
+            Some(day) => {
+                let age = (today - day).max(0) as f64;
+                let recency = 0.5f64.powf(age / half_life_days.max(f64::MIN_POSITIVE));
+                (1.0 - weight) * relevance + weight * recency
+            }
+            None => relevance,
+        };
+        c.recency_score = Some(score);
+    }
+    scored.sort_by(|a, b| {
+        b.recency_score
+            .partial_cmp(&a.recency_score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    scored.truncate(top_k);
+    for (i, r) in scored.iter_mut().enumerate() {
+        r.rank = offset + i as i64 + 1;
+    }
+    scored
+}
+
+fn days_since_epoch_now() -> i64 {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    (secs / 86_400) as i64
+}
+
+/// Days since 1970-01-01 for a `YYYY-MM-DD` date (proleptic Gregorian).
+pub fn parse_days(date: &str) -> Option<i64> {
+    let mut parts = date.get(..10)?.split('-');
+    let y: i64 = parts.next()?.parse().ok()?;
+    let m: i64 = parts.next()?.parse().ok()?;
+    let d: i64 = parts.next()?.parse().ok()?;
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (m + if m > 2 { -3 } else { 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    Some(era * 146_097 + doe - 719_468)
+}
+
+/// `.../api/embeddings` → `.../api/generate` on the same Ollama server.
+fn generate_endpoint(ollama_url: &str) -> String {
+    let base = match ollama_url.find("/api/") {
+        Some(i) => &ollama_url[..i],
+        None => ollama_url.trim_end_matches('/'),
+    };
+    format!("{}/api/generate", base)
+}
@@ -1,80 +1,1925 @@
 use anyhow::Result;
-use serde::Serialize;
-use tokio_postgres::NoTls;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{Client, SimpleQueryMessage};
 
-use crate::config::Config;
-use crate::embed::get_embedding;
+use crate::cache::{QueryCache, ResultCache};
+use crate::compress;
+use crate::config::{Config, QueryMode};
+use crate::db;
+use crate::embed::{get_embedding, get_embeddings_batch, parse_vector, vector_literal};
+use crate::index;
+use crate::rerank;
+use crate::telemetry;
 
-#[derive(Debug, Serialize)]
+/// Distance behind every similarity score: pgvector's `<=>`, as `1 - distance`.
+const METRIC: &str = "cosine";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SearchResult {
+    /// 1-based position across pages; pass the last one as `--offset` to fetch the next page.
+    pub rank: i64,
     pub content: String,
     pub source: String,
     pub source_path: Option<String>,
     pub source_date: Option<String>,
     pub similarity: f64,
+    pub start_offset: Option<i64>,
+    pub end_offset: Option<i64>,
+    pub title: Option<String>,
+    /// Raw vector, present only when requested via `--fields embedding`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub embedding: Option<Vec<f64>>,
+    /// Stored provenance (`store_metadata`), present only when requested via `--fields metadata`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+    /// Text the embedding was computed from when it differs from `content`
+    /// (`store_embed_text`), present only when requested via `--fields embed_text`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub embed_text: Option<String>,
+    /// Reciprocal-rank-fusion score, present only for multi-query RRF and `--hybrid` searches.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fusion_score: Option<f64>,
+    /// Full-text `ts_rank_cd` of the content against the query, present only
+    /// for hits the keyword side of `--hybrid` found.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keyword_score: Option<f64>,
+    /// Reranker relevance in 0.0..=1.0, present only with `--rerank`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rerank_score: Option<f64>,
+    /// Relevance blended with `source_date` recency, present only with `--recency-weight`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recency_score: Option<f64>,
+    /// Lower-ranked hits with the same text folded into this one, present only with `--dedup`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicates: Option<usize>,
+    /// Which `federated_targets` entry the hit came from, present only with `--federated`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
 }
 
-pub async fn search(config: &Config, query: &str, top_k: i64, json_output: bool) -> Result<()> {
-    let embedding = get_embedding(&config.ollama_url, &config.model, query).await?;
-    let embedding_str = format!(
-        "[{}]",
-        embedding
-            .iter()
-            .map(|v| v.to_string())
-            .collect::<Vec<_>>()
-            .join(",")
-    );
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable listing
+    Text,
+    /// One pretty-printed JSON array
+    Json,
+    /// One compact JSON object per line
+    Jsonl,
+    /// Each result rendered through `--template`
+    Template,
+}
 
-    let (client, connection) = tokio_postgres::connect(&config.db_url, NoTls).await?;
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("DB connection error: {}", e);
+/// How the result lists of several queries are combined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Fusion {
+    /// Reciprocal rank fusion over one result list per query
+    Rrf,
+    /// Search once with the mean of the query vectors
+    Average,
+}
+
+/// The usual RRF constant: damps the gap between the very top ranks.
+const RRF_K: f64 = 60.0;
+
+/// Text search configuration of the `content_tsv` column (`db/013`): no
+/// stemming or stop words, so error codes and names match exactly.
+const TSV_CONFIG: &str = "simple";
+
+/// What `search` ranks: chunks, or the per-file rows from `document_vectors`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchLevel {
+    Chunk,
+    Document,
+}
+
+pub struct SearchOptions {
+    pub top_k: i64,
+    pub offset: i64,
+    pub format: OutputFormat,
+    pub group_by_source: bool,
+    pub rerank: bool,
+    pub federated: bool,
+    /// Columns to fetch and print, from `FIELDS`.
+    pub fields: Vec<String>,
+    /// Source paths under this directory are shown relative to it.
+    pub relative_to: Option<PathBuf>,
+    pub level: SearchLevel,
+    /// Hits whose content is shorter than this many characters are dropped.
+    pub min_content_chars: usize,
+    /// Hits with a lower cosine similarity than this are dropped.
+    pub min_similarity: Option<f64>,
+    /// 0.0..=1.0 share of the ranking given to `source_date` recency; 0 disables it.
+    pub recency_weight: f64,
+    /// Only use cached query embeddings; never call the embedding server.
+    pub offline: bool,
+    /// Also count every chunk the filters match, ignoring the limit.
+    pub count: bool,
+    /// Wrap `--format json` output in an object echoing the query parameters.
+    pub json_envelope: bool,
+    /// How results are combined when more than one query is given.
+    pub fusion: Fusion,
+    /// Collapse hits whose text is identical (ignoring whitespace) into the best one.
+    pub dedup: bool,
+    /// Line format for `--format template`.
+    pub template: Option<Template>,
+    /// Only chunks whose detected language is this ISO 639-3 code.
+    pub lang: Option<String>,
+    /// Only chunks dated on or after this `YYYY-MM-DD` day; undated ones are dropped.
+    pub since: Option<NaiveDate>,
+    /// Only chunks dated on or before this `YYYY-MM-DD` day; undated ones are dropped.
+    pub until: Option<NaiveDate>,
+    /// Only chunks containing every one of these substrings (case-insensitive).
+    pub content_contains: Vec<String>,
+    /// Tidy content whitespace in human and template output.
+    pub trim_results: bool,
+    /// Print the query embeddings to stderr before searching.
+    pub show_embedding: bool,
+    /// Chunks of a `--like-file` example, embedded and averaged in place of the query.
+    pub example: Vec<String>,
+    /// Skip `result_cache`, neither reading nor storing this search.
+    pub no_cache: bool,
+    /// Also rank by a full-text match on `content_tsv` and fuse both rankings with RRF.
+    pub hybrid: bool,
+    /// 0.0..=1.0 share of the fused score given to the keyword rankings with `hybrid`.
+    pub keyword_weight: f64,
+}
+
+impl SearchOptions {
+    /// `top_k` results as JSON with the default fields and every other option
+    /// off; the servers (`serve`, `serve-http`) start from this.
+    pub fn json(top_k: i64) -> Self {
+        SearchOptions {
+            top_k: top_k.max(1),
+            offset: 0,
+            format: OutputFormat::Json,
+            group_by_source: false,
+            rerank: false,
+            federated: false,
+            fields: DEFAULT_FIELDS.split(',').map(str::to_string).collect(),
+            relative_to: None,
+            level: SearchLevel::Chunk,
+            min_content_chars: 0,
+            min_similarity: None,
+            recency_weight: 0.0,
+            offline: false,
+            count: false,
+            json_envelope: false,
+            fusion: Fusion::Rrf,
+            dedup: false,
+            template: None,
+            lang: None,
+            since: None,
+            until: None,
+            content_contains: Vec::new(),
+            trim_results: false,
+            show_embedding: false,
+            example: Vec::new(),
+            no_cache: false,
+            hybrid: false,
+            keyword_weight: 0.5,
         }
-    });
+    }
 
-    // Use simple_query to avoid prepared statement issues with Supabase pooler
-    let query_sql = format!(
-        "SELECT content, source, source_path, source_date::text,
-                1 - (embedding <=> '{}'::vector) as similarity
-         FROM {}.chunks
-         WHERE agent_id = '{}'
-         ORDER BY embedding <=> '{}'::vector
-         LIMIT {}",
-        embedding_str, config.schema, config.agent_id, embedding_str, top_k
-    );
+    fn filter(&self) -> CandidateFilter<'_> {
+        CandidateFilter {
+            level: self.level,
+            lang: self.lang.as_deref(),
+            since: self.since,
+            until: self.until,
+            contains: &self.content_contains,
+        }
+    }
+}
 
-    let messages = client.simple_query(&query_sql).await?;
+/// Fields accepted by `--fields`. `rank` and `similarity` are always computed
+/// but can still be left out of the output.
+pub const FIELDS: &[&str] = &[
+    "rank",
+    "content",
+    "source",
+    "source_path",
+    "source_date",
+    "similarity",
+    "start_offset",
+    "end_offset",
+    "title",
+    "embedding",
+    "metadata",
+    "embed_text",
+];
 
-    let mut results: Vec<SearchResult> = Vec::new();
-    for msg in &messages {
-        if let tokio_postgres::SimpleQueryMessage::Row(row) = msg {
-            let similarity: f64 = row.get(4).unwrap_or("0").parse().unwrap_or(0.0);
-            results.push(SearchResult {
-                content: row.get(0).unwrap_or("").to_string(),
-                source: row.get(1).unwrap_or("").to_string(),
-                source_path: row.get(2).map(|s| s.to_string()),
-                source_date: row.get(3).map(|s| s.to_string()),
-                similarity,
-            });
+/// Everything but `embedding`, `metadata` and `embed_text`, matching the output before `--fields` existed.
+pub const DEFAULT_FIELDS: &str =
+    "rank,content,source,source_path,source_date,similarity,start_offset,end_offset,title";
+
+/// Parse a comma-separated `--fields` list, rejecting names not in `FIELDS`.
+pub fn parse_fields(list: &str) -> Result<Vec<String>> {
+    let fields: Vec<String> = list
+        .split(',')
+        .map(|f| f.trim().to_string())
+        .filter(|f| !f.is_empty())
+        .collect();
+    if let Some(bad) = fields.iter().find(|f| !FIELDS.contains(&f.as_str())) {
+        anyhow::bail!(
+            "Unknown field '{}'. Valid fields: {}",
+            bad,
+            FIELDS.join(", ")
+        );
+    }
+    if fields.is_empty() {
+        anyhow::bail!("--fields needs at least one of: {}", FIELDS.join(", "));
+    }
+    Ok(fields)
+}
+
+/// Placeholders accepted by `--template`.
+pub const TEMPLATE_FIELDS: &[&str] = &[
+    "rank",
+    "similarity",
+    "source",
+    "date",
+    "path",
+    "title",
+    "content",
+];
+
+/// A parsed `--template`: literal text and `{field}` placeholders.
+pub struct Template(Vec<TemplatePart>);
+
+enum TemplatePart {
+    Text(String),
+    Field(&'static str),
+}
+
+/// Parse a `--template` string. `{{`/`}}` are literal braces, and `\n`/`\t`
+/// are a newline and a tab so templates can be written on one shell line.
+pub fn parse_template(template: &str) -> Result<Template> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '\\' if matches!(chars.peek(), Some('n') | Some('t')) => {
+                text.push(if chars.next() == Some('n') {
+                    '\n'
+                } else {
+                    '\t'
+                });
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => anyhow::bail!(
+                            "Unclosed '{{' in template (write '{{{{' for a literal brace)"
+                        ),
+                    }
+                }
+                let field = TEMPLATE_FIELDS
+                    .iter()
+                    .find(|&&f| f == name.trim())
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "Unknown template field '{{{}}}'. Valid fields: {}",
+                            name,
+                            TEMPLATE_FIELDS.join(", ")
+                        )
+                    })?;
+                if !text.is_empty() {
+                    parts.push(TemplatePart::Text(std::mem::take(&mut text)));
+                }
+                parts.push(TemplatePart::Field(field));
+            }
+            '}' => anyhow::bail!("Unmatched '}}' in template (write '}}}}' for a literal brace)"),
+            _ => text.push(c),
         }
     }
+    if !text.is_empty() {
+        parts.push(TemplatePart::Text(text));
+    }
+    Ok(Template(parts))
+}
 
-    if json_output {
-        println!("{}", serde_json::to_string_pretty(&results)?);
+impl Template {
+    /// Render one result; fields the hit doesn't have render as empty.
+    fn render(&self, r: &SearchResult) -> String {
+        let mut out = String::new();
+        for part in &self.0 {
+            match part {
+                TemplatePart::Text(t) => out.push_str(t),
+                TemplatePart::Field(f) => match *f {
+                    "rank" => out.push_str(&r.rank.to_string()),
+                    "similarity" => out.push_str(&format!("{:.4}", r.similarity)),
+                    "source" => out.push_str(&r.source),
+                    "date" => out.push_str(r.source_date.as_deref().unwrap_or("")),
+                    "path" => out.push_str(r.source_path.as_deref().unwrap_or("")),
+                    "title" => out.push_str(r.title.as_deref().unwrap_or("")),
+                    _ => out.push_str(&r.content),
+                },
+            }
+        }
+        out
+    }
+}
+
+/// Per-target query parameters shared by every `fetch_candidates` call of one search.
+#[derive(Clone, Copy)]
+struct CandidateQuery<'a> {
+    embedding_str: &'a str,
+    /// Rank by a full-text match of this text instead of by distance
+    keyword: Option<&'a str>,
+    limit: i64,
+    offset: i64,
+    fields: &'a [String],
+    filter: CandidateFilter<'a>,
+    timeout_secs: Option<u64>,
+    /// Vector column searched (`embedding_column`)
+    column: &'a str,
+    /// SQL type the query vector is cast to (`vector_type`)
+    vector_type: &'static str,
+    /// `binary_prefilter` shortlist size
+    prefilter: Option<usize>,
+    query_mode: QueryMode,
+}
+
+/// Which of an agent's rows are candidates, before vector ordering.
+#[derive(Clone, Copy)]
+struct CandidateFilter<'a> {
+    level: SearchLevel,
+    lang: Option<&'a str>,
+    /// Inclusive `source_date` bounds
+    since: Option<NaiveDate>,
+    until: Option<NaiveDate>,
+    /// Substrings the content must all contain, ignoring case
+    contains: &'a [String],
+}
+
+/// Results sharing a `source_path` (or `source` label when there is no path).
+#[derive(Debug, Serialize)]
+pub struct SourceGroup<'a> {
+    pub source: String,
+    pub best_similarity: f64,
+    pub results: Vec<&'a SearchResult>,
+}
+
+/// Run a search and print the results; returns how many were printed.
+pub async fn search(config: &Config, queries: &[String], opts: &SearchOptions) -> Result<usize> {
+    let top_k = opts.top_k;
+    let multi = queries.len() > 1;
+    // Shown in headers
+    let query = queries.join(" | ");
+    let query = query.as_str();
+    let (results, total) = run(config, queries, opts).await?;
+
+    let heading = if multi {
+        let fusion = if opts.fusion == Fusion::Rrf {
+            "rrf"
+        } else {
+            "average"
+        };
+        format!("Queries ({}): \"{}\"", fusion, queries.join("\" + \""))
+    } else {
+        format!("Query: \"{}\"", query)
+    };
+
+    if let (Some(min), OutputFormat::Text, true) =
+        (opts.min_similarity, opts.format, results.is_empty())
+    {
+        println!("🔍 {}: no matches above similarity {}", heading, min);
+        return Ok(0);
+    }
+
+    if opts.group_by_source {
+        let groups = group_by_source(&results);
+        if opts.format == OutputFormat::Json {
+            print_json(config, queries, opts, total, grouped_json(&groups))?;
+        } else if opts.format == OutputFormat::Jsonl {
+            print_jsonl(&groups)?;
+        } else {
+            println!(
+                "🔍 {} (top {}{}, {} sources)",
+                heading,
+                top_k,
+                of_total(total),
+                groups.len()
+            );
+            println!("{}\n", provenance(config));
+            for g in &groups {
+                println!(
+                    "=== {} | best sim={:.4} | {} hits ===",
+                    g.source,
+                    g.best_similarity,
+                    g.results.len()
+                );
+                for r in &g.results {
+                    println!(
+                        "--- sim={:.4} | {} ---",
+                        r.similarity,
+                        r.source_date.as_deref().unwrap_or("n/a")
+                    );
+                    let display: String = r.content.chars().take(500).collect();
+                    println!("{}\n", display);
+                }
+            }
+        }
+    } else if opts.format == OutputFormat::Json {
+        let results = select_fields(&results, &opts.fields)?;
+        print_json(
+            config,
+            queries,
+            opts,
+            total,
+            serde_json::Value::Array(results),
+        )?;
+    } else if opts.format == OutputFormat::Jsonl {
+        print_jsonl(&select_fields(&results, &opts.fields)?)?;
+    } else if let (OutputFormat::Template, Some(template)) = (opts.format, &opts.template) {
+        for r in &results {
+            println!("{}", template.render(r));
+        }
     } else {
-        println!("🔍 Query: \"{}\" (top {})\n", query, top_k);
-        for (i, r) in results.iter().enumerate() {
+        if opts.offset > 0 {
+            println!(
+                "🔍 {} (top {}{}, offset {})",
+                heading,
+                top_k,
+                of_total(total),
+                opts.offset
+            );
+        } else {
+            println!("🔍 {} (top {}{})", heading, top_k, of_total(total));
+        }
+        println!("{}\n", provenance(config));
+        for r in &results {
+            let source = match &r.origin {
+                Some(origin) => format!("{}:{}", origin, r.source),
+                None => r.source.clone(),
+            };
+            let dups = match r.duplicates {
+                Some(n) if n > 0 => format!(" | +{} duplicate(s)", n),
+                _ => String::new(),
+            };
             println!(
-                "--- [{}] sim={:.4} | {} | {} ---",
-                i + 1,
+                "--- [{}] sim={:.4} | {} | {}{} ---",
+                r.rank,
                 r.similarity,
-                r.source,
-                r.source_date.as_deref().unwrap_or("n/a")
+                source,
+                r.source_date.as_deref().unwrap_or("n/a"),
+                dups
             );
             let display: String = r.content.chars().take(500).collect();
             println!("{}\n", display);
         }
     }
 
+    Ok(results.len())
+}
+
+/// Results of a search, ready to print: served from `result_cache` or
+/// found afresh, with paths made relative and content trimmed as `opts` ask.
+/// Returned with the candidate count when `--count` asked for it.
+pub async fn run(
+    config: &Config,
+    queries: &[String],
+    opts: &SearchOptions,
+) -> Result<(Vec<SearchResult>, Option<i64>)> {
+    let started = Instant::now();
+    if config.compress_content && !opts.content_contains.is_empty() {
+        eprintln!("⚠️  --content-contains only sees uncompressed content; chunks stored with compress_content never match");
+    }
+    // An example file or a printed embedding needs the embedding call anyway
+    let cache_key = match &config.result_cache {
+        Some(_) if !opts.no_cache && opts.example.is_empty() && !opts.show_embedding => {
+            Some(result_cache_key(config, queries, opts))
+        }
+        _ => None,
+    };
+    let cached = match (&config.result_cache, &cache_key) {
+        (Some(path), Some(key)) => ResultCache::load(path, config.result_cache_ttl_secs)?.get(key),
+        _ => None,
+    };
+    let (mut results, total) = match cached {
+        Some(hit) => hit,
+        None => {
+            let found = find(config, queries, opts).await?;
+            if let (Some(path), Some(key)) = (&config.result_cache, cache_key) {
+                ResultCache::load(path, config.result_cache_ttl_secs)?
+                    .insert(key, &found.0, found.1)?;
+            }
+            found
+        }
+    };
+    telemetry::record_search(started);
+    if let Some(base) = &opts.relative_to {
+        for r in &mut results {
+            r.source_path = r.source_path.take().map(|p| relative_path(&p, base));
+        }
+    }
+    if opts.trim_results && !matches!(opts.format, OutputFormat::Json | OutputFormat::Jsonl) {
+        for r in &mut results {
+            r.content = trim_display(&r.content);
+        }
+    }
+    Ok((results, total))
+}
+
+/// `result_cache` key: everything that shapes a page of results, but not how
+/// it is printed (format, relative paths, grouping).
+fn result_cache_key(config: &Config, queries: &[String], opts: &SearchOptions) -> String {
+    serde_json::json!({
+        "agent": config.agent_id,
+        "table": config.table_name(),
+        "column": config.embedding_column,
+        "model": config.model,
+        "queries": queries,
+        "top_k": opts.top_k,
+        "offset": opts.offset,
+        "fields": opts.fields,
+        "level": opts.level,
+        "lang": opts.lang,
+        "since": opts.since,
+        "until": opts.until,
+        "content_contains": opts.content_contains,
+        "min_content_chars": opts.min_content_chars,
+        "min_similarity": opts.min_similarity,
+        "recency_weight": opts.recency_weight,
+        "rerank": opts.rerank,
+        "federated": opts.federated,
+        "fusion": opts.fusion,
+        "dedup": opts.dedup,
+        "hybrid": opts.hybrid,
+        "keyword_weight": opts.keyword_weight,
+        "count": opts.count,
+    })
+    .to_string()
+}
+
+/// Embed the queries and fetch, filter and re-rank this page of results;
+/// returns them with the candidate count when `--count` asked for it.
+async fn find(
+    config: &Config,
+    queries: &[String],
+    opts: &SearchOptions,
+) -> Result<(Vec<SearchResult>, Option<i64>)> {
+    let top_k = opts.top_k;
+    let multi = queries.len() > 1;
+    // Given to the reranker
+    let query = queries.join(" | ");
+    let query = query.as_str();
+    let embeddings = if !opts.example.is_empty() {
+        vec![example_embedding(config, &opts.example).await?]
+    } else if multi {
+        query_embeddings(config, queries, opts.offline).await?
+    } else {
+        vec![query_embedding(config, query, opts.offline).await?]
+    };
+    if opts.show_embedding {
+        show_embeddings(queries, &embeddings, opts.format)?;
+    }
+
+    // Re-sorting over-fetches so older or lower-scored hits have candidates to promote
+    let recency = opts.recency_weight > 0.0;
+    let limit = if opts.rerank || recency || opts.dedup {
+        top_k.max(config.rerank_candidates as i64)
+    } else {
+        top_k
+    };
+
+    // The reranker and length filter read chunk text, and recency the date, even when not requested for output
+    let mut fields = opts.fields.clone();
+    if (opts.rerank || opts.dedup || opts.min_content_chars > 0)
+        && !fields.iter().any(|f| f == "content")
+    {
+        fields.push("content".to_string());
+    }
+    if recency && !fields.iter().any(|f| f == "source_date") {
+        fields.push("source_date".to_string());
+    }
+    // RRF tells hits apart by path, offsets and text
+    let rrf = multi && opts.fusion == Fusion::Rrf;
+    if rrf || opts.hybrid {
+        for f in ["content", "source_path", "start_offset"] {
+            if !fields.iter().any(|x| x == f) {
+                fields.push(f.to_string());
+            }
+        }
+    }
+
+    let mut results = if rrf || opts.hybrid {
+        let vectors = if rrf {
+            embeddings.clone()
+        } else {
+            vec![mean_vector(&embeddings)]
+        };
+        // Split between the two sides, so neither list can score negative
+        let keyword_weight = opts.keyword_weight.clamp(0.0, 1.0);
+        let vector_weight = if opts.hybrid {
+            1.0 - keyword_weight
+        } else {
+            1.0
+        };
+        let mut lists = Vec::with_capacity(vectors.len() + queries.len());
+        for embedding in &vectors {
+            let embedding_str = vector_literal(embedding);
+            let page = fetch_page(
+                config,
+                opts,
+                &embedding_str,
+                None,
+                &fields,
+                limit + opts.offset,
+                0,
+            )
+            .await?;
+            lists.push((vector_weight, page));
+        }
+        // One full-text ranking per query, fused alongside the vector rankings
+        if opts.hybrid {
+            let embedding_str = vector_literal(&mean_vector(&embeddings));
+            for q in queries {
+                let page = fetch_page(
+                    config,
+                    opts,
+                    &embedding_str,
+                    Some(q),
+                    &fields,
+                    limit + opts.offset,
+                    0,
+                )
+                .await?;
+                lists.push((keyword_weight, page));
+            }
+        }
+        let mut fused = fuse_rrf(lists);
+        fused.drain(..(opts.offset as usize).min(fused.len()));
+        fused.truncate(limit as usize);
+        for (i, r) in fused.iter_mut().enumerate() {
+            r.rank = opts.offset + i as i64 + 1;
+        }
+        fused
+    } else {
+        let embedding_str = vector_literal(&mean_vector(&embeddings));
+        fetch_page(
+            config,
+            opts,
+            &embedding_str,
+            None,
+            &fields,
+            limit,
+            opts.offset,
+        )
+        .await?
+    };
+
+    let total = if !opts.count {
+        None
+    } else if opts.federated {
+        let counts = config.federated_targets.iter().map(|t| async move {
+            count_candidates(
+                &t.db_url,
+                &t.table_name(),
+                &t.agent_id,
+                &t.embedding_column,
+                &opts.filter(),
+                config.query_timeout_secs,
+                config.query_mode,
+            )
+            .await
+        });
+        let mut sum = 0;
+        for n in futures::future::join_all(counts).await {
+            sum += n?;
+        }
+        Some(sum)
+    } else {
+        Some(
+            count_candidates(
+                config.read_url(),
+                &config.table_name(),
+                &config.agent_id,
+                &config.embedding_column,
+                &opts.filter(),
+                config.query_timeout_secs,
+                config.query_mode,
+            )
+            .await?,
+        )
+    };
+
+    // Raw similarity, before rerank or recency rescore the survivors
+    results = apply_minimums(results, opts);
+    if opts.dedup {
+        results = dedup(results);
+        if !opts.rerank && !recency {
+            results.truncate(top_k as usize);
+        }
+        for (i, r) in results.iter_mut().enumerate() {
+            r.rank = opts.offset + i as i64 + 1;
+        }
+    }
+    if opts.rerank {
+        results = rerank::rerank(config, query, results, top_k as usize, opts.offset).await?;
+    }
+    if recency {
+        results = rerank::by_recency(
+            results,
+            opts.recency_weight,
+            config.recency_half_life_days,
+            top_k as usize,
+            opts.offset,
+        );
+    }
+    Ok((results, total))
+}
+
+/// Components shown from each end of a vector by `--show-embedding`.
+const PREVIEW_COMPONENTS: usize = 4;
+
+/// `--show-embedding`: each query's vector on stderr, so stdout stays
+/// parseable. JSON output gets the full vectors; other formats a preview.
+fn show_embeddings(
+    queries: &[String],
+    embeddings: &[Vec<f64>],
+    format: OutputFormat,
+) -> Result<()> {
+    let norm = |v: &[f64]| v.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if matches!(format, OutputFormat::Json | OutputFormat::Jsonl) {
+        let out: Vec<serde_json::Value> = queries
+            .iter()
+            .zip(embeddings)
+            .map(|(q, e)| {
+                serde_json::json!({
+                    "query": q,
+                    "dimension": e.len(),
+                    "norm": norm(e),
+                    "embedding": e,
+                })
+            })
+            .collect();
+        eprintln!("{}", serde_json::to_string_pretty(&out)?);
+        return Ok(());
+    }
+    for (q, e) in queries.iter().zip(embeddings) {
+        let fmt = |v: &[f64]| {
+            v.iter()
+                .map(|x| format!("{:.6}", x))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let preview = if e.len() > 2 * PREVIEW_COMPONENTS {
+            format!(
+                "{}, …, {}",
+                fmt(&e[..PREVIEW_COMPONENTS]),
+                fmt(&e[e.len() - PREVIEW_COMPONENTS..])
+            )
+        } else {
+            fmt(e)
+        };
+        eprintln!(
+            "🧭 Embedding of \"{}\": {} dims, norm {:.4}",
+            q,
+            e.len(),
+            norm(e)
+        );
+        eprintln!("   [{}]", preview);
+        if e.iter().all(|&x| x == 0.0) {
+            eprintln!("   ⚠️  All zeros: every chunk will score the same; check `model` and the embedding server");
+        }
+    }
+    eprintln!();
+    Ok(())
+}
+
+/// One page of nearest chunks for `embedding_str` (or, given `keyword`, of
+/// the best full-text matches for it), from this agent's table or, with
+/// `--federated`, merged across every target.
+async fn fetch_page(
+    config: &Config,
+    opts: &SearchOptions,
+    embedding_str: &str,
+    keyword: Option<&str>,
+    fields: &[String],
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<SearchResult>> {
+    if !opts.federated {
+        let q = CandidateQuery {
+            embedding_str,
+            keyword,
+            limit,
+            offset,
+            fields,
+            filter: opts.filter(),
+            timeout_secs: config.query_timeout_secs,
+            column: &config.embedding_column,
+            vector_type: config.vector_type.sql(),
+            prefilter: config.binary_prefilter,
+            query_mode: config.query_mode,
+        };
+        return fetch_candidates(
+            config.read_url(),
+            &config.table_name(),
+            &config.agent_id,
+            &q,
+        )
+        .await;
+    }
+
+    if config.federated_targets.is_empty() {
+        anyhow::bail!("--federated needs at least one entry under `federated_targets` in config");
+    }
+    // Each target returns its own best `offset + limit`; the page is cut from the merged list.
+    let fetches = config.federated_targets.iter().map(|t| async {
+        let q = CandidateQuery {
+            embedding_str,
+            keyword,
+            limit: limit + offset,
+            offset: 0,
+            fields,
+            filter: opts.filter(),
+            timeout_secs: config.query_timeout_secs,
+            column: &t.embedding_column,
+            vector_type: config.vector_type.sql(),
+            prefilter: config.binary_prefilter,
+            query_mode: config.query_mode,
+        };
+        let mut rows = fetch_candidates(&t.db_url, &t.table_name(), &t.agent_id, &q)
+            .await
+            .map_err(|e| anyhow::anyhow!("Federated target '{}': {}", t.label(), e))?;
+        for r in &mut rows {
+            r.origin = Some(t.label());
+        }
+        Ok::<_, anyhow::Error>(rows)
+    });
+    let mut merged: Vec<SearchResult> = Vec::new();
+    for rows in futures::future::join_all(fetches).await {
+        merged.extend(rows?);
+    }
+    let score = |r: &SearchResult| {
+        if keyword.is_some() {
+            r.keyword_score.unwrap_or(0.0)
+        } else {
+            r.similarity
+        }
+    };
+    merged.sort_by(|a, b| {
+        score(b)
+            .partial_cmp(&score(a))
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    let mut page: Vec<SearchResult> = merged
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit as usize)
+        .collect();
+    for (i, r) in page.iter_mut().enumerate() {
+        r.rank = offset + i as i64 + 1;
+    }
+    Ok(page)
+}
+
+/// Merge weighted result lists (one per query, plus keyword lists with
+/// `--hybrid`) by reciprocal rank fusion: each hit scores
+/// `sum(weight / (RRF_K + rank))` over the lists it appears in and keeps its
+/// best similarity. Sorted best first; ranks are left for the caller to set.
+fn fuse_rrf(lists: Vec<(f64, Vec<SearchResult>)>) -> Vec<SearchResult> {
+    let mut fused: Vec<SearchResult> = Vec::new();
+    for (weight, list) in lists {
+        for (i, mut r) in list.into_iter().enumerate() {
+            let score = weight / (RRF_K + i as f64 + 1.0);
+            let same = |x: &SearchResult| {
+                x.origin == r.origin
+                    && x.source_path == r.source_path
+                    && x.start_offset == r.start_offset
+                    && x.content == r.content
+            };
+            match fused.iter_mut().find(|x| same(x)) {
+                Some(hit) => {
+                    hit.fusion_score = Some(hit.fusion_score.unwrap_or(0.0) + score);
+                    hit.similarity = hit.similarity.max(r.similarity);
+                    hit.keyword_score = hit.keyword_score.or(r.keyword_score);
+                }
+                None => {
+                    r.fusion_score = Some(score);
+                    fused.push(r);
+                }
+            }
+        }
+    }
+    fused.sort_by(|a, b| {
+        b.fusion_score
+            .partial_cmp(&a.fusion_score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    fused
+}
+
+/// Drop hits below `min_similarity` or shorter than `min_content_chars`,
+/// renumbering the rest so ranks stay contiguous from `offset`.
+fn apply_minimums(mut results: Vec<SearchResult>, opts: &SearchOptions) -> Vec<SearchResult> {
+    if let Some(min) = opts.min_similarity {
+        results.retain(|r| r.similarity >= min);
+    }
+    if opts.min_content_chars > 0 {
+        results.retain(|r| r.content.chars().count() >= opts.min_content_chars);
+    }
+    if opts.min_similarity.is_some() || opts.min_content_chars > 0 {
+        for (i, r) in results.iter_mut().enumerate() {
+            r.rank = opts.offset + i as i64 + 1;
+        }
+    }
+    results
+}
+
+/// Keep the first (best) hit of each distinct text, compared with whitespace
+/// runs collapsed, and count the copies dropped behind it.
+fn dedup(results: Vec<SearchResult>) -> Vec<SearchResult> {
+    let mut seen: std::collections::HashMap<String, usize> = std::collections::HashMap::new();
+    let mut kept: Vec<SearchResult> = Vec::new();
+    for mut r in results {
+        let key = r.content.split_whitespace().collect::<Vec<_>>().join(" ");
+        match seen.get(&key) {
+            Some(&i) => *kept[i].duplicates.get_or_insert(0) += 1,
+            None => {
+                seen.insert(key, kept.len());
+                r.duplicates = Some(0);
+                kept.push(r);
+            }
+        }
+    }
+    kept
+}
+
+/// Component-wise mean of unit-normalized vectors, so each query counts equally.
+fn mean_vector(vectors: &[Vec<f64>]) -> Vec<f64> {
+    if vectors.len() == 1 {
+        return vectors[0].clone();
+    }
+    let mut mean = vec![0.0; vectors.first().map_or(0, Vec::len)];
+    for v in vectors {
+        let norm = v
+            .iter()
+            .map(|x| x * x)
+            .sum::<f64>()
+            .sqrt()
+            .max(f64::MIN_POSITIVE);
+        for (m, x) in mean.iter_mut().zip(v) {
+            *m += x / norm / vectors.len() as f64;
+        }
+    }
+    mean
+}
+
+/// Print an ASCII histogram of the similarities of the `top_n` nearest chunks
+/// to `query`, in 0.1-wide buckets, to help pick a similarity cut-off.
+pub async fn similarity_histogram(config: &Config, query: &str, top_n: i64) -> Result<()> {
+    const BAR_WIDTH: usize = 40;
+
+    let embedding = query_embedding(config, query, false).await?;
+    let embedding_str = vector_literal(&embedding);
+    let q = CandidateQuery {
+        embedding_str: &embedding_str,
+        keyword: None,
+        limit: top_n,
+        offset: 0,
+        fields: &[],
+        filter: CandidateFilter {
+            level: SearchLevel::Chunk,
+            lang: None,
+            since: None,
+            until: None,
+            contains: &[],
+        },
+        timeout_secs: config.query_timeout_secs,
+        column: &config.embedding_column,
+        vector_type: config.vector_type.sql(),
+        prefilter: config.binary_prefilter,
+        query_mode: config.query_mode,
+    };
+    let results = fetch_candidates(
+        config.read_url(),
+        &config.table_name(),
+        &config.agent_id,
+        &q,
+    )
+    .await?;
+    if results.is_empty() {
+        println!("No chunks for agent '{}'.", config.agent_id);
+        return Ok(());
+    }
+
+    // Bucket i covers [i/10, (i+1)/10); cosine similarity can dip below zero
+    let bucket = |s: f64| ((s * 10.0).floor() as i64).min(9);
+    let lowest = results
+        .iter()
+        .map(|r| bucket(r.similarity))
+        .min()
+        .unwrap_or(0);
+    let highest = results
+        .iter()
+        .map(|r| bucket(r.similarity))
+        .max()
+        .unwrap_or(9);
+    let mut counts = vec![0usize; (highest - lowest + 1) as usize];
+    for r in &results {
+        counts[(bucket(r.similarity) - lowest) as usize] += 1;
+    }
+    let peak = counts.iter().copied().max().unwrap_or(1).max(1);
+
+    println!(
+        "📊 Similarity of the top {} chunks to \"{}\"\n",
+        results.len(),
+        query
+    );
+    for (i, &n) in counts.iter().enumerate().rev() {
+        let low = (lowest + i as i64) as f64 / 10.0;
+        let bar = "█".repeat((n * BAR_WIDTH).div_ceil(peak));
+        println!(
+            "  {:>5.2}–{:<5.2} │{:<width$} {}",
+            low,
+            low + 0.1,
+            bar,
+            n,
+            width = BAR_WIDTH
+        );
+    }
+    let best = results.first().map_or(0.0, |r| r.similarity);
+    let worst = results.last().map_or(0.0, |r| r.similarity);
+    println!("\n  max={:.4}  min={:.4}", best, worst);
+    Ok(())
+}
+
+/// Embed `query`, going through `query_cache` when one is configured. With
+/// `offline`, a query missing from the cache is an error instead of a request.
+async fn query_embedding(config: &Config, query: &str, offline: bool) -> Result<Vec<f64>> {
+    let Some(path) = &config.query_cache else {
+        if offline {
+            anyhow::bail!("--offline needs `query_cache` set in config");
+        }
+        return get_embedding(config, query).await;
+    };
+    let mut cache = QueryCache::load(path)?;
+    if let Some(embedding) = cache.get(&config.model, query) {
+        return Ok(embedding.clone());
+    }
+    if offline {
+        anyhow::bail!(
+            "--offline: query {:?} is not in the query cache for model '{}'",
+            query,
+            config.model
+        );
+    }
+    let embedding = get_embedding(config, query).await?;
+    cache.insert(&config.model, query, embedding.clone())?;
+    Ok(embedding)
+}
+
+/// Mean of an example file's chunk embeddings. Chunks the server rejects are
+/// left out; none are cached, since a draft rarely gets searched twice as is.
+async fn example_embedding(config: &Config, chunks: &[String]) -> Result<Vec<f64>> {
+    let vectors: Vec<Vec<f64>> = get_embeddings_batch(config, chunks, config.embed_batch_size)
+        .await?
+        .into_iter()
+        .flatten()
+        .collect();
+    if vectors.is_empty() {
+        anyhow::bail!("Embedding server rejected every chunk of the example file");
+    }
+    Ok(mean_vector(&vectors))
+}
+
+/// Embed several queries: cached ones come from `query_cache`, the rest go to
+/// the server in one batch request (and are then cached).
+async fn query_embeddings(
+    config: &Config,
+    queries: &[String],
+    offline: bool,
+) -> Result<Vec<Vec<f64>>> {
+    let mut cache = match &config.query_cache {
+        Some(path) => Some(QueryCache::load(path)?),
+        None if offline => anyhow::bail!("--offline needs `query_cache` set in config"),
+        None => None,
+    };
+    let mut out: Vec<Option<Vec<f64>>> = queries
+        .iter()
+        .map(|q| {
+            cache
+                .as_ref()
+                .and_then(|c| c.get(&config.model, q))
+                .cloned()
+        })
+        .collect();
+
+    let missing: Vec<String> = queries
+        .iter()
+        .zip(&out)
+        .filter(|(_, e)| e.is_none())
+        .map(|(q, _)| q.clone())
+        .collect();
+    if let Some(q) = missing.first().filter(|_| offline) {
+        anyhow::bail!(
+            "--offline: query {:?} is not in the query cache for model '{}'",
+            q,
+            config.model
+        );
+    }
+    if !missing.is_empty() {
+        let embedded = get_embeddings_batch(config, &missing, missing.len()).await?;
+        let mut embedded = missing.iter().zip(embedded);
+        for slot in out.iter_mut().filter(|e| e.is_none()) {
+            let (q, embedding) = embedded.next().expect("one embedding per missing query");
+            let embedding = embedding
+                .ok_or_else(|| anyhow::anyhow!("Embedding server rejected query {:?}", q))?;
+            if let Some(cache) = &mut cache {
+                cache.insert(&config.model, q, embedding.clone())?;
+            }
+            *slot = Some(embedding);
+        }
+    }
+    Ok(out.into_iter().flatten().collect())
+}
+
+/// WHERE clause shared by `fetch_candidates` and `count_candidates`.
+fn candidate_filter(
+    agent_id: &str,
+    column: &str,
+    f: &CandidateFilter,
+    binds: &mut Binds,
+) -> String {
+    let mut filter = format!(
+        "agent_id = {} AND source {} '{}' AND {} IS NOT NULL",
+        binds.bind(agent_id),
+        if f.level == SearchLevel::Document {
+            "="
+        } else {
+            "<>"
+        },
+        index::DOCUMENT_SOURCE,
+        column
+    );
+    if let Some(lang) = f.lang {
+        filter.push_str(&format!(" AND lang = {}", binds.bind(lang)));
+    }
+    // A comparison with a NULL source_date is never true, so undated rows drop out
+    match (f.since, f.until) {
+        (Some(since), Some(until)) => filter.push_str(&format!(
+            " AND source_date BETWEEN {}::date AND {}::date",
+            binds.bind(&since.to_string()),
+            binds.bind(&until.to_string())
+        )),
+        (Some(since), None) => filter.push_str(&format!(
+            " AND source_date >= {}::date",
+            binds.bind(&since.to_string())
+        )),
+        (None, Some(until)) => filter.push_str(&format!(
+            " AND source_date <= {}::date",
+            binds.bind(&until.to_string())
+        )),
+        (None, None) => {}
+    }
+    for term in f.contains {
+        // LIKE wildcards in the term match literally
+        let pattern = term
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        filter.push_str(&format!(
+            " AND content ILIKE {}",
+            binds.bind(&format!("%{}%", pattern))
+        ));
+    }
+    filter
+}
+
+/// Values for a query: `$n::text` placeholders bound at execution when it
+/// runs prepared, quoted literals inlined into the SQL otherwise.
+pub struct Binds {
+    pub prepared: bool,
+    values: Vec<String>,
+}
+
+impl Binds {
+    pub fn new(prepared: bool) -> Self {
+        Self {
+            prepared,
+            values: Vec::new(),
+        }
+    }
+
+    /// SQL standing for `value`; cast it (`::vector`) for non-text columns.
+    pub fn bind(&mut self, value: &str) -> String {
+        if self.prepared {
+            self.values.push(value.to_string());
+            format!("${}::text", self.values.len())
+        } else {
+            format!("'{}'", value.replace('\'', "''"))
+        }
+    }
+
+    /// The bound values, in placeholder order, for `query`/`execute`.
+    pub fn params(&self) -> Vec<&(dyn ToSql + Sync)> {
+        self.values
+            .iter()
+            .map(|v| v as &(dyn ToSql + Sync))
+            .collect()
+    }
+}
+
+/// Run a read query, cancelled by Postgres after `timeout_secs` when set.
+/// `SET LOCAL` keeps the limit inside this query's transaction, so it doesn't
+/// leak to other clients sharing a pooled connection.
+pub async fn read_query(
+    client: &Client,
+    sql: &str,
+    timeout_secs: Option<u64>,
+) -> Result<Vec<SimpleQueryMessage>> {
+    let Some(secs) = timeout_secs else {
+        return client
+            .simple_query(sql)
+            .await
+            .map_err(|e| read_error(e, None));
+    };
+    let wrapped = format!(
+        "BEGIN; SET LOCAL statement_timeout = '{}s'; {}; COMMIT",
+        secs, sql
+    );
+    match client.simple_query(&wrapped).await {
+        Ok(msgs) => Ok(msgs),
+        Err(e) => {
+            // The failed statement skipped the COMMIT; don't hand the pool a
+            // client still inside the aborted transaction
+            let _ = client.simple_query("ROLLBACK").await;
+            Err(read_error(e, timeout_secs))
+        }
+    }
+}
+
+/// `read_query` for a query built with `binds`: run prepared with the values
+/// bound, or inlined as a simple query. Every selected column must be text
+/// (cast it), and comes back as one string per column.
+pub async fn read_rows(
+    client: &Client,
+    sql: &str,
+    binds: &Binds,
+    timeout_secs: Option<u64>,
+) -> Result<Vec<Vec<Option<String>>>> {
+    if !binds.prepared {
+        return Ok(read_query(client, sql, timeout_secs)
+            .await?
+            .iter()
+            .filter_map(|m| match m {
+                SimpleQueryMessage::Row(row) => Some(
+                    (0..row.len())
+                        .map(|i| row.get(i).map(str::to_string))
+                        .collect(),
+                ),
+                _ => None,
+            })
+            .collect());
+    }
+    let params = binds.params();
+    if let Some(secs) = timeout_secs {
+        client
+            .batch_execute(&format!("BEGIN; SET LOCAL statement_timeout = '{}s'", secs))
+            .await
+            .map_err(|e| read_error(e, None))?;
+    }
+    let rows = client.query(sql, &params).await;
+    if timeout_secs.is_some() {
+        // Ends the transaction even after an error (as a rollback)
+        client
+            .batch_execute("COMMIT")
+            .await
+            .map_err(|e| read_error(e, None))?;
+    }
+    let rows = rows.map_err(|e| read_error(e, timeout_secs))?;
+    let mut out = Vec::with_capacity(rows.len());
+    for row in &rows {
+        out.push(
+            (0..row.len())
+                .map(|i| row.try_get(i))
+                .collect::<Result<Vec<Option<String>>, _>>()?,
+        );
+    }
+    Ok(out)
+}
+
+fn read_error(e: tokio_postgres::Error, timeout_secs: Option<u64>) -> anyhow::Error {
+    telemetry::record_db_error();
+    match timeout_secs {
+        Some(secs) if e.code() == Some(&tokio_postgres::error::SqlState::QUERY_CANCELED) => anyhow::anyhow!(
+            "Query timed out after {}s (query_timeout_secs). Without a vector index the search scans \
+             every chunk: create the ivfflat index from db/001_init.sql, or ask for a smaller --top",
+            secs
+        ),
+        _ => e.into(),
+    }
+}
+
+/// How many chunks `fetch_candidates` could return without a limit.
+async fn count_candidates(
+    db_url: &str,
+    table: &str,
+    agent_id: &str,
+    column: &str,
+    filter: &CandidateFilter<'_>,
+    timeout_secs: Option<u64>,
+    query_mode: QueryMode,
+) -> Result<i64> {
+    let client = db::connect(db_url, timeout_secs).await?;
+    let mut binds = Binds::new(db::use_prepared(db_url, &client, query_mode).await?);
+    let sql = format!(
+        "SELECT count(*)::text FROM {} WHERE {}",
+        table,
+        candidate_filter(agent_id, column, filter, &mut binds)
+    );
+    let rows = read_rows(&client, &sql, &binds, timeout_secs).await?;
+    Ok(rows
+        .first()
+        .and_then(|row| row.first().cloned().flatten())
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(0))
+}
+
+/// Nearest chunks to `embedding_str` for one agent in one database table.
+async fn fetch_candidates(
+    db_url: &str,
+    table: &str,
+    agent_id: &str,
+    q: &CandidateQuery<'_>,
+) -> Result<Vec<SearchResult>> {
+    let CandidateQuery {
+        embedding_str,
+        keyword,
+        limit,
+        offset,
+        fields,
+        filter,
+        timeout_secs,
+        column: vector_column,
+        vector_type,
+        prefilter,
+        query_mode,
+    } = *q;
+    let client = db::connect(db_url, timeout_secs).await?;
+    let mut binds = Binds::new(db::use_prepared(db_url, &client, query_mode).await?);
+    let query_vector = format!("{}::{}", binds.bind(embedding_str), vector_type);
+    let ts_query = keyword.map(|k| format!("plainto_tsquery('{}', {})", TSV_CONFIG, binds.bind(k)));
+
+    // Unrequested columns are selected as NULL so row positions stay fixed
+    let column = |field: &str, expr: &str| {
+        if fields.iter().any(|f| f == field) {
+            expr.to_string()
+        } else {
+            "NULL".to_string()
+        }
+    };
+    // With a prefilter, exact distances are only computed for the rows nearest
+    // in Hamming distance of their sign bits (which a bit index can serve)
+    let candidates = match (&ts_query, prefilter) {
+        (Some(ts_query), _) => format!(
+            "{} WHERE {} AND content_tsv @@ {}",
+            table,
+            candidate_filter(agent_id, vector_column, &filter, &mut binds),
+            ts_query
+        ),
+        (None, Some(n)) => {
+            let dim = embedding_str.matches(',').count() + 1;
+            format!(
+                "(SELECT * FROM {table} WHERE {filter}
+                  ORDER BY binary_quantize({col})::bit({dim}) <~> binary_quantize({q})::bit({dim})
+                  LIMIT {n}) AS shortlist",
+                table = table,
+                filter = candidate_filter(agent_id, vector_column, &filter, &mut binds),
+                col = vector_column,
+                dim = dim,
+                q = query_vector,
+                n = n.max((limit + offset) as usize),
+            )
+        }
+        (None, None) => format!(
+            "{} WHERE {}",
+            table,
+            candidate_filter(agent_id, vector_column, &filter, &mut binds)
+        ),
+    };
+    let distance = format!("{} <=> {}", vector_column, query_vector);
+    let (keyword_score, order) = match &ts_query {
+        Some(ts_query) => {
+            let rank = format!("ts_rank_cd(content_tsv, {})", ts_query);
+            (
+                format!("{}::text", rank),
+                format!("{} DESC, {}", rank, distance),
+            )
+        }
+        None => ("NULL".to_string(), distance.clone()),
+    };
+    // Every column as text, so prepared and simple queries read the same way
+    let query_sql = format!(
+        "SELECT {}, {}, {}, {},
+                (1 - ({}))::text as similarity,
+                {}, {}, {}, {}, {}, {}, {}, {}
+         FROM {}
+         ORDER BY {}
+         LIMIT {} OFFSET {}",
+        column("content", "content::text"),
+        column("source", "source::text"),
+        column("source_path", "source_path::text"),
+        column("source_date", "source_date::text"),
+        distance,
+        column("start_offset", "start_offset::text"),
+        column("end_offset", "end_offset::text"),
+        column("title", "title::text"),
+        column("embedding", &format!("{}::text", vector_column)),
+        column("content", "encode(content_compressed, 'hex')"),
+        column("metadata", "metadata::text"),
+        column("embed_text", "embed_text::text"),
+        keyword_score,
+        candidates,
+        order,
+        limit,
+        offset
+    );
+
+    let rows = read_rows(&client, &query_sql, &binds, timeout_secs).await?;
+
+    let mut results: Vec<SearchResult> = Vec::new();
+    for row in &rows {
+        let get = |i: usize| row.get(i).and_then(|v| v.as_deref());
+        let similarity: f64 = get(4).unwrap_or("0").parse().unwrap_or(0.0);
+        let content = match (get(0), get(9)) {
+            (Some(text), _) => text.to_string(),
+            (None, Some(hex)) => compress::decompress_hex(hex)?,
+            (None, None) => String::new(),
+        };
+        results.push(SearchResult {
+            rank: offset + results.len() as i64 + 1,
+            content,
+            source: get(1).unwrap_or("").to_string(),
+            source_path: get(2).map(|s| s.to_string()),
+            source_date: get(3).map(|s| s.to_string()),
+            similarity,
+            start_offset: get(5).and_then(|s| s.parse().ok()),
+            end_offset: get(6).and_then(|s| s.parse().ok()),
+            title: get(7).map(|s| s.to_string()),
+            embedding: get(8).map(parse_vector),
+            metadata: get(10).and_then(|s| serde_json::from_str(s).ok()),
+            embed_text: get(11).map(|s| s.to_string()),
+            fusion_score: None,
+            keyword_score: get(12).and_then(|s| s.parse().ok()),
+            duplicates: None,
+            rerank_score: None,
+            recency_score: None,
+            origin: None,
+        });
+    }
+    Ok(results)
+}
+
+/// Serialize results keeping only the requested `FIELDS`; extras that are
+/// only set by other flags (`rerank_score`, `origin`) pass through.
+fn select_fields(results: &[SearchResult], fields: &[String]) -> Result<Vec<serde_json::Value>> {
+    let mut out = Vec::with_capacity(results.len());
+    for r in results {
+        let mut value = serde_json::to_value(r)?;
+        if let Some(obj) = value.as_object_mut() {
+            obj.retain(|k, _| !FIELDS.contains(&k.as_str()) || fields.iter().any(|f| f == k));
+        }
+        out.push(value);
+    }
+    Ok(out)
+}
+
+/// ` of N` for headers when `--count` was given.
+fn of_total(total: Option<i64>) -> String {
+    total.map_or(String::new(), |n| format!(" of {}", n))
+}
+
+/// Second header line: what produced the scores, for reproducing a search.
+fn provenance(config: &Config) -> String {
+    format!("   model={} metric={}", config.model, METRIC)
+}
+
+/// Pretty-print `results`, wrapped as `{query, top_k, model, metric, filters,
+/// results}` with `--json-envelope` so consumers can log what was asked. `--count` implies the
+/// envelope and adds `total_candidates`; several queries imply it and add
+/// `queries` and `fusion`.
+fn print_json(
+    config: &Config,
+    queries: &[String],
+    opts: &SearchOptions,
+    total: Option<i64>,
+    results: serde_json::Value,
+) -> Result<()> {
+    let multi = queries.len() > 1;
+    let out = if opts.json_envelope || total.is_some() || multi {
+        let mut out = serde_json::json!({
+            "query": queries.join(" | "),
+            "top_k": opts.top_k,
+            "model": config.model,
+            "metric": METRIC,
+            "filters": {
+                "offset": opts.offset,
+                "level": opts.level,
+                "rerank": opts.rerank,
+                "federated": opts.federated,
+                "group_by_source": opts.group_by_source,
+                "recency_weight": opts.recency_weight,
+                "min_content_chars": opts.min_content_chars,
+                "min_similarity": opts.min_similarity,
+                "dedup": opts.dedup,
+                "hybrid": opts.hybrid,
+                "keyword_weight": opts.keyword_weight,
+                "lang": opts.lang,
+                "since": opts.since,
+                "until": opts.until,
+                "content_contains": opts.content_contains,
+                "fields": opts.fields,
+            },
+            "results": results,
+        });
+        if let Some(n) = total {
+            out["total_candidates"] = n.into();
+        }
+        if multi {
+            out["queries"] = serde_json::to_value(queries)?;
+            out["fusion"] = serde_json::to_value(opts.fusion)?;
+        }
+        out
+    } else {
+        results
+    };
+    println!("{}", serde_json::to_string_pretty(&out)?);
     Ok(())
 }
+
+/// `content` trimmed, with trailing spaces dropped and blank-line runs
+/// collapsed to one, for display only.
+fn trim_display(content: &str) -> String {
+    let mut out = String::with_capacity(content.len());
+    let mut blank = false;
+    for line in content.trim().lines().map(str::trim_end) {
+        if line.is_empty() {
+            blank = true;
+            continue;
+        }
+        if !out.is_empty() {
+            out.push_str(if blank { "\n\n" } else { "\n" });
+        }
+        out.push_str(line);
+        blank = false;
+    }
+    out
+}
+
+/// `path` relative to `base` when it lies under it, else unchanged.
+fn relative_path(path: &str, base: &Path) -> String {
+    match Path::new(path).strip_prefix(base) {
+        Ok(rel) if !rel.as_os_str().is_empty() => rel.to_string_lossy().to_string(),
+        _ => path.to_string(),
+    }
+}
+
+/// Write one compact JSON object per line, flushing as we go so consumers can stream.
+fn print_jsonl<T: Serialize>(items: &[T]) -> Result<()> {
+    let mut out = std::io::stdout().lock();
+    for item in items {
+        serde_json::to_writer(&mut out, item)?;
+        writeln!(out)?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+/// `{"<source>": {"best_similarity": ..., "results": [...]}}`, keyed in the
+/// groups' best-first order.
+fn grouped_json(groups: &[SourceGroup]) -> serde_json::Value {
+    let mut out = serde_json::Map::new();
+    for g in groups {
+        out.insert(
+            g.source.clone(),
+            serde_json::json!({ "best_similarity": g.best_similarity, "results": g.results }),
+        );
+    }
+    serde_json::Value::Object(out)
+}
+
+/// Group results by source, ordered by each group's best hit.
+/// Input is already sorted by similarity, so first-seen order is best-first.
+fn group_by_source(results: &[SearchResult]) -> Vec<SourceGroup<'_>> {
+    let mut groups: Vec<SourceGroup> = Vec::new();
+    for r in results {
+        let key = r.source_path.clone().unwrap_or_else(|| r.source.clone());
+        match groups.iter_mut().find(|g| g.source == key) {
+            Some(g) => g.results.push(r),
+            None => groups.push(SourceGroup {
+                source: key,
+                best_similarity: r.similarity,
+                results: vec![r],
+            }),
+        }
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn filter(since: Option<&str>, until: Option<&str>) -> CandidateFilter<'static> {
+        CandidateFilter {
+            level: SearchLevel::Chunk,
+            lang: None,
+            since: since.map(|d| d.parse().unwrap()),
+            until: until.map(|d| d.parse().unwrap()),
+            contains: &[],
+        }
+    }
+
+    const BASE: &str = "agent_id = 'me' AND source <> 'document_summary' AND embedding IS NOT NULL";
+
+    #[test]
+    fn candidate_filter_adds_date_bounds_only_when_set() {
+        let sql =
+            |f: &CandidateFilter| candidate_filter("me", "embedding", f, &mut Binds::new(false));
+        assert_eq!(sql(&filter(None, None)), BASE);
+        assert_eq!(
+            sql(&filter(Some("2025-01-01"), None)),
+            format!("{} AND source_date >= '2025-01-01'::date", BASE)
+        );
+        assert_eq!(
+            sql(&filter(None, Some("2025-01-31"))),
+            format!("{} AND source_date <= '2025-01-31'::date", BASE)
+        );
+        assert_eq!(
+            sql(&filter(Some("2025-01-01"), Some("2025-01-31"))),
+            format!(
+                "{} AND source_date BETWEEN '2025-01-01'::date AND '2025-01-31'::date",
+                BASE
+            )
+        );
+    }
+
+    #[test]
+    fn candidate_filter_binds_every_value_when_prepared() {
+        let contains = ["50%_off".to_string()];
+        let f = CandidateFilter {
+            level: SearchLevel::Document,
+            lang: Some("eng"),
+            contains: &contains,
+            ..filter(Some("2025-01-01"), Some("2025-01-31"))
+        };
+        let mut binds = Binds::new(true);
+        assert_eq!(
+            candidate_filter("o'brien", "vec", &f, &mut binds),
+            "agent_id = $1::text AND source = 'document_summary' AND vec IS NOT NULL AND lang = $2::text \
+             AND source_date BETWEEN $3::text::date AND $4::text::date AND content ILIKE $5::text"
+        );
+        let values: Vec<String> = binds.values.clone();
+        assert_eq!(
+            values,
+            [
+                "o'brien",
+                "eng",
+                "2025-01-01",
+                "2025-01-31",
+                "%50\\%\\_off%"
+            ]
+        );
+    }
+
+    fn hit(rank: i64, similarity: f64, content: &str) -> SearchResult {
+        serde_json::from_value(serde_json::json!({
+            "rank": rank,
+            "content": content,
+            "source": "note",
+            "similarity": similarity,
+        }))
+        .unwrap()
+    }
+
+    fn ranked(results: &[SearchResult]) -> Vec<(i64, &str)> {
+        results
+            .iter()
+            .map(|r| (r.rank, r.content.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn minimums_drop_weak_and_short_hits_and_renumber_the_rest() {
+        let hits = || {
+            vec![
+                hit(11, 0.9, "strong match"),
+                hit(12, 0.5, "on the line"),
+                hit(13, 0.2, "noise"),
+            ]
+        };
+        let mut opts = SearchOptions::json(3);
+        opts.offset = 10;
+
+        assert_eq!(
+            ranked(&apply_minimums(hits(), &opts)),
+            [(11, "strong match"), (12, "on the line"), (13, "noise")]
+        );
+
+        opts.min_similarity = Some(0.5);
+        assert_eq!(
+            ranked(&apply_minimums(hits(), &opts)),
+            [(11, "strong match"), (12, "on the line")]
+        );
+
+        opts.min_content_chars = 12;
+        assert_eq!(
+            ranked(&apply_minimums(hits(), &opts)),
+            [(11, "strong match")]
+        );
+
+        opts.min_content_chars = 0;
+        opts.min_similarity = Some(0.95);
+        assert!(apply_minimums(hits(), &opts).is_empty());
+    }
+
+    #[test]
+    fn unfiltered_results_keep_their_ranks() {
+        let hits = vec![hit(1, 0.9, "a"), hit(3, 0.8, "b")];
+        assert_eq!(
+            ranked(&apply_minimums(hits, &SearchOptions::json(3))),
+            [(1, "a"), (3, "b")]
+        );
+    }
+
+    fn list(hits: &[(&str, f64)]) -> Vec<SearchResult> {
+        hits.iter()
+            .enumerate()
+            .map(|(i, (c, sim))| hit(i as i64 + 1, *sim, c))
+            .collect()
+    }
+
+    fn order(fused: &[SearchResult]) -> Vec<&str> {
+        fused.iter().map(|r| r.content.as_str()).collect()
+    }
+
+    fn close(a: Option<f64>, b: f64) -> bool {
+        a.is_some_and(|a| (a - b).abs() < 1e-12)
+    }
+
+    #[test]
+    fn rrf_sums_reciprocal_ranks_across_lists() {
+        let fused = fuse_rrf(vec![
+            (1.0, list(&[("a", 0.9), ("b", 0.8), ("c", 0.7)])),
+            (1.0, list(&[("c", 0.6), ("d", 0.5), ("a", 0.4)])),
+        ]);
+        assert_eq!(order(&fused), ["a", "c", "b", "d"]);
+        assert!(close(fused[0].fusion_score, 1.0 / 61.0 + 1.0 / 63.0));
+        assert!(close(fused[1].fusion_score, 1.0 / 63.0 + 1.0 / 61.0));
+        assert!(close(fused[2].fusion_score, 1.0 / 62.0));
+        assert!(close(fused[3].fusion_score, 1.0 / 62.0));
+        // Merged hits keep their best similarity
+        assert_eq!(fused[1].similarity, 0.7);
+    }
+
+    #[test]
+    fn rrf_weights_scale_each_lists_contribution() {
+        let vector = list(&[("semantic", 0.9), ("both", 0.8)]);
+        let mut keyword = list(&[("exact", 0.1), ("both", 0.8)]);
+        for (i, r) in keyword.iter_mut().enumerate() {
+            r.keyword_score = Some(1.0 / (i as f64 + 1.0));
+        }
+
+        // `--keyword-weight w` weighs the vector list 1 - w
+        let even = fuse_rrf(vec![(0.5, vector.clone()), (0.5, keyword.clone())]);
+        assert_eq!(order(&even), ["both", "semantic", "exact"]);
+
+        let keyword_heavy = fuse_rrf(vec![(0.25, vector.clone()), (0.75, keyword.clone())]);
+        assert_eq!(order(&keyword_heavy), ["both", "exact", "semantic"]);
+        assert!(close(
+            keyword_heavy[0].fusion_score,
+            0.25 / 62.0 + 0.75 / 62.0
+        ));
+        assert!(close(keyword_heavy[1].fusion_score, 0.75 / 61.0));
+        assert_eq!(keyword_heavy[0].keyword_score, Some(0.5));
+
+        let vector_only = fuse_rrf(vec![(1.0, vector), (0.0, keyword)]);
+        assert_eq!(order(&vector_only)[..2], ["semantic", "both"]);
+    }
+
+    #[test]
+    fn rrf_keeps_same_text_from_different_places_apart() {
+        let mut elsewhere = hit(1, 0.5, "a");
+        elsewhere.source_path = Some("/notes/other.md".to_string());
+        let fused = fuse_rrf(vec![(1.0, list(&[("a", 0.9)])), (1.0, vec![elsewhere])]);
+        assert_eq!(fused.len(), 2);
+        assert!(fused.iter().all(|r| close(r.fusion_score, 1.0 / 61.0)));
+    }
+
+    /// A config for a fresh agent with `files` indexed from a temporary
+    /// markdown directory, which is returned to keep it alive.
+    async fn indexed(
+        db_url: &str,
+        embedder: &wiremock::MockServer,
+        files: &[(&str, &str)],
+    ) -> (Config, tempfile::TempDir) {
+        let notes = testing::notes(files);
+        let config = testing::notes_config(db_url, embedder, notes.path());
+        index::run_incremental_index(&config, false, false, None)
+            .await
+            .unwrap();
+        (config, notes)
+    }
+
+    #[tokio::test]
+    async fn hybrid_search_fuses_keyword_hits() {
+        let Some(db_url) = testing::db_url() else {
+            return;
+        };
+        let embedder = testing::embedder().await;
+        let (config, _notes) = indexed(
+            &db_url,
+            &embedder,
+            &[
+                (
+                    "2025-01-06.md",
+                    "# Monday\n\nThe importer crashed with error E4242 on the nightly run.\n",
+                ),
+                (
+                    "2025-01-07.md",
+                    "# Tuesday\n\nThe importer crashed again, nobody wrote the error down.\n",
+                ),
+            ],
+        )
+        .await;
+        let mut opts = SearchOptions::json(5);
+        opts.hybrid = true;
+        opts.keyword_weight = 0.7;
+
+        let (results, _) = run(&config, &["E4242".to_string()], &opts).await.unwrap();
+        assert!(results[0].content.contains("E4242"), "{:?}", results);
+        assert!(results[0].keyword_score.is_some_and(|s| s > 0.0));
+        assert!(results[0].fusion_score.is_some());
+        assert!(results.iter().skip(1).all(|r| r.keyword_score.is_none()));
+
+        testing::clear_agent(&config).await;
+    }
+
+    #[tokio::test]
+    async fn min_similarity_filters_database_results() {
+        let Some(db_url) = testing::db_url() else {
+            return;
+        };
+        let embedder = testing::embedder().await;
+        let files = [
+            ("2025-01-06.md", testing::MONDAY),
+            ("2025-01-07.md", testing::TUESDAY),
+        ];
+        let (config, _notes) = indexed(&db_url, &embedder, &files).await;
+        let query = ["deploy staging database disk space".to_string()];
+
+        let mut opts = SearchOptions::json(5);
+        let (all, _) = run(&config, &query, &opts).await.unwrap();
+        assert_eq!(all.len(), 2);
+        let (best, worst) = (all[0].similarity, all[1].similarity);
+        assert!(best > worst, "{} vs {}", best, worst);
+
+        opts.min_similarity = Some((best + worst) / 2.0);
+        let (kept, _) = run(&config, &query, &opts).await.unwrap();
+        assert_eq!(kept.len(), 1);
+        assert!(kept[0].content.contains("out of disk space"));
+        assert_eq!(kept[0].rank, 1);
+
+        opts.min_similarity = Some(best + 0.01);
+        let (none, _) = run(&config, &query, &opts).await.unwrap();
+        assert!(none.is_empty());
+
+        testing::clear_agent(&config).await;
+    }
+
+    #[test]
+    fn binds_quote_inline_or_number_placeholders() {
+        let mut inline = Binds::new(false);
+        assert_eq!(inline.bind("o'brien's"), "'o''brien''s'");
+        assert!(inline.params().is_empty());
+
+        let mut prepared = Binds::new(true);
+        assert_eq!(prepared.bind("o'brien"), "$1::text");
+        assert_eq!(prepared.bind("2025-01-06"), "$2::text");
+        assert_eq!(prepared.params().len(), 2);
+    }
+}
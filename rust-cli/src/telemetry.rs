@@ -0,0 +1,55 @@
+//! Optional Prometheus metrics. Recording is a no-op until `install` is called,
+//! so one-shot commands pay nothing when `--metrics-port` isn't given.
+
+use anyhow::Result;
+use metrics_exporter_prometheus::PrometheusBuilder;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Instant;
+
+const SEARCHES_TOTAL: &str = "agent_memory_searches_total";
+const SEARCH_SECONDS: &str = "agent_memory_search_duration_seconds";
+const EMBED_SECONDS: &str = "agent_memory_embed_duration_seconds";
+const DB_ERRORS_TOTAL: &str = "agent_memory_db_errors_total";
+const INDEXED_CHUNKS_TOTAL: &str = "agent_memory_indexed_chunks_total";
+
+/// Start the Prometheus exporter, serving `/metrics` on `host:port`. The
+/// endpoint has no authentication, so `--metrics-host` defaults to loopback.
+pub fn install(host: IpAddr, port: u16) -> Result<()> {
+    let addr = SocketAddr::new(host, port);
+    PrometheusBuilder::new()
+        .with_http_listener(addr)
+        .install()
+        .map_err(|e| anyhow::anyhow!("Cannot start metrics exporter on {}: {}", addr, e))?;
+
+    metrics::describe_counter!(SEARCHES_TOTAL, "Number of searches executed");
+    metrics::describe_histogram!(
+        SEARCH_SECONDS,
+        metrics::Unit::Seconds,
+        "End-to-end search latency"
+    );
+    metrics::describe_histogram!(
+        EMBED_SECONDS,
+        metrics::Unit::Seconds,
+        "Embedding request latency"
+    );
+    metrics::describe_counter!(DB_ERRORS_TOTAL, "Database errors");
+    metrics::describe_counter!(INDEXED_CHUNKS_TOTAL, "Chunks inserted by indexing");
+    Ok(())
+}
+
+pub fn record_search(started: Instant) {
+    metrics::counter!(SEARCHES_TOTAL).increment(1);
+    metrics::histogram!(SEARCH_SECONDS).record(started.elapsed().as_secs_f64());
+}
+
+pub fn record_embed(started: Instant) {
+    metrics::histogram!(EMBED_SECONDS).record(started.elapsed().as_secs_f64());
+}
+
+pub fn record_db_error() {
+    metrics::counter!(DB_ERRORS_TOTAL).increment(1);
+}
+
+pub fn record_indexed_chunks(n: usize) {
+    metrics::counter!(INDEXED_CHUNKS_TOTAL).increment(n as u64);
+}
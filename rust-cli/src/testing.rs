@@ -0,0 +1,234 @@
+//! Helpers shared by tests. Tests that need Postgres run against the database
+//! named by `AGENT_MEMORY_TEST_DB` (a connection string for a database set up
+//! with `db/001_init.sql` and `agent-memory migrate`) and return early when it
+//! isn't set, so `cargo test` passes without one. They write under an agent ID
+//! of their own and remove its rows with `clear_agent`.
+
+use axum::extract::State;
+use axum::routing::post;
+use axum::Json;
+use serde_json::{json, Value};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+use crate::config::{Config, Source, SourceType};
+use crate::db;
+
+/// Size of the vectors `embedder` returns: the `vector(768)` column of
+/// `db/001_init.sql`.
+pub const DIMENSION: usize = 768;
+
+/// The test database's connection string, or `None`, with a note on stderr,
+/// when the calling test should be skipped.
+pub fn db_url() -> Option<String> {
+    match std::env::var("AGENT_MEMORY_TEST_DB") {
+        Ok(url) if !url.trim().is_empty() => Some(url),
+        _ => {
+            eprintln!("skipped: set AGENT_MEMORY_TEST_DB to run database tests");
+            None
+        }
+    }
+}
+
+/// `db_url` with an `application_name`, which also keys a pool of its own:
+/// tests counting connections don't share them with tests running alongside.
+pub fn own_pool_url(db_url: &str, name: &str) -> String {
+    if db_url.starts_with("postgres://") || db_url.starts_with("postgresql://") {
+        let sep = if db_url.contains('?') { '&' } else { '?' };
+        format!("{}{}application_name={}", db_url, sep, name)
+    } else {
+        format!("{} application_name={}", db_url, name)
+    }
+}
+
+/// A config with every default, a fresh agent ID, and embeddings from
+/// `embed_url` (e.g. `embedder`'s), retried without waiting.
+pub fn config(db_url: &str, embed_url: &str) -> Config {
+    let yaml = format!(
+        "agent_id: test-{}\ndb_url: {:?}\nollama_url: {:?}\nembed_retry_base_ms: 0\n",
+        uuid::Uuid::new_v4().simple(),
+        db_url,
+        embed_url,
+    );
+    serde_yaml::from_str(&yaml).expect("test config parses")
+}
+
+/// The embedding `embedder` gives `text`: one dimension per distinct word, so
+/// texts sharing words are similar and texts sharing none are orthogonal.
+pub fn vector_for(text: &str) -> Vec<f64> {
+    let mut v = vec![0.0; DIMENSION];
+    for word in text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+    {
+        let mut hasher = DefaultHasher::new();
+        word.to_lowercase().hash(&mut hasher);
+        v[hasher.finish() as usize % DIMENSION] += 1.0;
+    }
+    v
+}
+
+/// A mock Ollama answering `/api/embeddings` and `/api/embed` with
+/// `vector_for` each text. Point `ollama_url` at `{uri}/api/embeddings`.
+pub async fn embedder() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/api/embeddings"))
+        .respond_with(|request: &Request| {
+            let body: Value = request.body_json().unwrap_or_default();
+            let prompt = body["prompt"].as_str().unwrap_or_default();
+            ResponseTemplate::new(200).set_body_json(json!({ "embedding": vector_for(prompt) }))
+        })
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/api/embed"))
+        .respond_with(|request: &Request| {
+            let body: Value = request.body_json().unwrap_or_default();
+            let embeddings: Vec<Vec<f64>> = body["input"]
+                .as_array()
+                .map(|texts| {
+                    texts
+                        .iter()
+                        .map(|t| vector_for(t.as_str().unwrap_or_default()))
+                        .collect()
+                })
+                .unwrap_or_default();
+            ResponseTemplate::new(200).set_body_json(json!({ "embeddings": embeddings }))
+        })
+        .mount(&server)
+        .await;
+    server
+}
+
+/// A daily note about a deploy that ran out of disk space.
+pub const MONDAY: &str =
+    "# Monday\n\nThe deploy failed twice because the staging database ran out of disk space.\n";
+/// A daily note sharing no words with `MONDAY` past its heading.
+pub const TUESDAY: &str =
+    "# Tuesday\n\nLunch with the design team about the new onboarding illustrations.\n";
+
+/// A source reading `path` as `source_type`, every option at its default.
+pub fn source(path: &Path, source_type: SourceType) -> Source {
+    Source {
+        path: path.to_path_buf(),
+        source_type,
+        source_label: None,
+        source_date: None,
+        append_only: false,
+        recursive: false,
+        label_from: None,
+    }
+}
+
+/// A quiet config embedding with `embedder`, without sources.
+pub fn embedder_config(db_url: &str, embedder: &MockServer) -> Config {
+    let mut config = config(db_url, &format!("{}/api/embeddings", embedder.uri()));
+    config.quiet = true;
+    config
+}
+
+/// A config indexing the markdown files in `dir` with `embedder`.
+pub fn notes_config(db_url: &str, embedder: &MockServer, dir: &Path) -> Config {
+    let mut config = embedder_config(db_url, embedder);
+    config.sources.push(source(dir, SourceType::MarkdownDir));
+    config
+}
+
+/// A temporary directory holding `files`, as `(name, text)` pairs.
+pub fn notes(files: &[(&str, &str)]) -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    for (name, text) in files {
+        std::fs::write(dir.path().join(name), text).unwrap();
+    }
+    dir
+}
+
+/// A mock Ollama like `embedder` that holds each request for a while before
+/// answering, to count how many it is asked to hold at once.
+pub struct SlowEmbedder {
+    /// Point `ollama_url` here.
+    pub url: String,
+    delay: Duration,
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+}
+
+impl SlowEmbedder {
+    /// Start one holding each request for `delay`. Without `batch` it has no
+    /// `/api/embed`, like an Ollama older than the batch endpoint.
+    pub async fn start(delay: Duration, batch: bool) -> Arc<SlowEmbedder> {
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0))
+            .await
+            .unwrap();
+        let embedder = Arc::new(SlowEmbedder {
+            url: format!("http://{}/api/embeddings", listener.local_addr().unwrap()),
+            delay,
+            in_flight: AtomicUsize::new(0),
+            max_in_flight: AtomicUsize::new(0),
+        });
+        let mut router = axum::Router::new().route(
+            "/api/embeddings",
+            post(|State(e): State<Arc<SlowEmbedder>>, Json(body): Json<Value>| async move {
+                e.hold().await;
+                Json(json!({ "embedding": vector_for(body["prompt"].as_str().unwrap_or_default()) }))
+            }),
+        );
+        if batch {
+            router = router.route(
+                "/api/embed",
+                post(
+                    |State(e): State<Arc<SlowEmbedder>>, Json(body): Json<Value>| async move {
+                        e.hold().await;
+                        let texts = body["input"].as_array().cloned().unwrap_or_default();
+                        let embeddings: Vec<Vec<f64>> = texts
+                            .iter()
+                            .map(|t| vector_for(t.as_str().unwrap_or_default()))
+                            .collect();
+                        Json(json!({ "embeddings": embeddings }))
+                    },
+                ),
+            );
+        }
+        let router = router.with_state(embedder.clone());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        embedder
+    }
+
+    /// The most requests it has held at once.
+    pub fn max_in_flight(&self) -> usize {
+        self.max_in_flight.load(Ordering::SeqCst)
+    }
+
+    async fn hold(&self) {
+        let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_in_flight.fetch_max(now, Ordering::SeqCst);
+        tokio::time::sleep(self.delay).await;
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Remove everything stored for `config`'s agent.
+pub async fn clear_agent(config: &Config) {
+    let client = db::connect(&config.db_url, Some(10))
+        .await
+        .expect("test database reachable");
+    for table in [
+        config.table_name(),
+        format!("{}.index_state", config.schema),
+    ] {
+        client
+            .execute(
+                &format!("DELETE FROM {} WHERE agent_id = $1", table),
+                &[&config.agent_id],
+            )
+            .await
+            .expect("test rows deleted");
+    }
+}
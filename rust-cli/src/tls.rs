@@ -0,0 +1,327 @@
+//! TLS for database connections, following libpq's `sslmode` and
+//! `sslrootcert` options in the connection string (or `ssl` and
+//! `root_cert_path` in config). tokio-postgres only knows `disable`,
+//! `prefer` and `require`, and doesn't verify certificates itself, so those
+//! two options are taken out of the string here and turned into a
+//! postgres-native-tls connector.
+
+use anyhow::Result;
+use postgres_native_tls::MakeTlsConnector;
+use std::path::{Path, PathBuf};
+use tokio_postgres::config::SslMode as PgSslMode;
+
+/// libpq's `sslmode`, minus `allow`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SslMode {
+    /// Never encrypt. What a connection string without `sslmode` gets here,
+    /// unlike libpq's `prefer`.
+    Disable,
+    /// Encrypt when the server offers it, without checking its certificate.
+    Prefer,
+    /// Always encrypt; the certificate is only checked when a root
+    /// certificate is given, as with `verify-ca`.
+    Require,
+    /// Always encrypt, with a certificate signed by a trusted CA.
+    VerifyCa,
+    /// `verify-ca`, and the certificate must be for the host connected to.
+    VerifyFull,
+}
+
+impl SslMode {
+    fn parse(s: &str) -> Result<Self> {
+        Ok(match s {
+            "disable" => SslMode::Disable,
+            "prefer" => SslMode::Prefer,
+            "require" => SslMode::Require,
+            "verify-ca" => SslMode::VerifyCa,
+            "verify-full" => SslMode::VerifyFull,
+            _ => anyhow::bail!(
+                "Unsupported sslmode {:?} in db_url (use disable, prefer, require, verify-ca or verify-full)",
+                s
+            ),
+        })
+    }
+}
+
+/// A connection string split into what tokio-postgres parses and the TLS
+/// options it doesn't.
+#[derive(Debug)]
+pub struct TlsSettings {
+    /// The connection string without `sslmode` and `sslrootcert`
+    pub db_url: String,
+    pub mode: SslMode,
+    /// PEM file with the CA certificate(s) to trust instead of the system's
+    pub root_cert: Option<PathBuf>,
+}
+
+impl TlsSettings {
+    /// Read `sslmode` and `sslrootcert` from a `postgres://` URL's query
+    /// string or a `key=value` connection string.
+    pub fn parse(db_url: &str) -> Result<Self> {
+        let mut mode = None;
+        let mut root_cert = None;
+        let mut take = |key: &str, value: String| -> Result<bool> {
+            match key {
+                "sslmode" => mode = Some(SslMode::parse(&value)?),
+                "sslrootcert" => root_cert = Some(PathBuf::from(value)),
+                _ => return Ok(false),
+            }
+            Ok(true)
+        };
+
+        let rest = if is_url(db_url) {
+            match db_url.split_once('?') {
+                Some((base, query)) => {
+                    let mut kept = Vec::new();
+                    for pair in query.split('&').filter(|p| !p.is_empty()) {
+                        let decoded = url::form_urlencoded::parse(pair.as_bytes()).next();
+                        let taken = match decoded {
+                            Some((key, value)) => take(&key, value.into_owned())?,
+                            None => false,
+                        };
+                        if !taken {
+                            kept.push(pair);
+                        }
+                    }
+                    if kept.is_empty() {
+                        base.to_string()
+                    } else {
+                        format!("{}?{}", base, kept.join("&"))
+                    }
+                }
+                None => db_url.to_string(),
+            }
+        } else {
+            let mut kept = Vec::new();
+            for (token, key, value) in key_values(db_url) {
+                if !take(key, value)? {
+                    kept.push(token);
+                }
+            }
+            kept.join(" ")
+        };
+
+        Ok(TlsSettings {
+            db_url: rest,
+            mode: mode.unwrap_or(SslMode::Disable),
+            root_cert,
+        })
+    }
+
+    /// What tokio-postgres should do before the handshake: the verify modes
+    /// are `require` to it, with the checking done by the connector.
+    pub fn pg_ssl_mode(&self) -> PgSslMode {
+        match self.mode {
+            SslMode::Disable => PgSslMode::Disable,
+            SslMode::Prefer => PgSslMode::Prefer,
+            SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull => PgSslMode::Require,
+        }
+    }
+
+    /// The connector for these settings, reading `root_cert` if set.
+    pub fn connector(&self) -> Result<MakeTlsConnector> {
+        let mut builder = native_tls::TlsConnector::builder();
+        if let Some(path) = &self.root_cert {
+            let pem = std::fs::read(path).map_err(|e| {
+                anyhow::anyhow!("Cannot read sslrootcert {}: {}", path.display(), e)
+            })?;
+            let cert = native_tls::Certificate::from_pem(&pem)
+                .map_err(|e| anyhow::anyhow!("Invalid sslrootcert {}: {}", path.display(), e))?;
+            builder
+                .add_root_certificate(cert)
+                .disable_built_in_roots(true);
+        }
+        match self.mode {
+            SslMode::Require if self.root_cert.is_some() => {
+                builder.danger_accept_invalid_hostnames(true);
+            }
+            SslMode::Disable | SslMode::Prefer | SslMode::Require => {
+                builder.danger_accept_invalid_certs(true);
+            }
+            SslMode::VerifyCa => {
+                builder.danger_accept_invalid_hostnames(true);
+            }
+            SslMode::VerifyFull => {}
+        }
+        Ok(MakeTlsConnector::new(builder.build()?))
+    }
+}
+
+/// `db_url` with `sslmode=require` added when `ssl` is set and with
+/// `sslrootcert` set to `root_cert`, unless the string already has them.
+pub fn with_defaults(db_url: &str, ssl: bool, root_cert: Option<&Path>) -> String {
+    let has = |key: &str| {
+        if is_url(db_url) {
+            db_url.split_once('?').is_some_and(|(_, q)| {
+                url::form_urlencoded::parse(q.as_bytes()).any(|(k, _)| k == key)
+            })
+        } else {
+            key_values(db_url).iter().any(|(_, k, _)| *k == key)
+        }
+    };
+    let mut extra = Vec::new();
+    if ssl && !has("sslmode") {
+        extra.push(("sslmode", "require".to_string()));
+    }
+    if let Some(path) = root_cert.filter(|_| !has("sslrootcert")) {
+        extra.push(("sslrootcert", path.display().to_string()));
+    }
+    let mut out = db_url.to_string();
+    for (key, value) in extra {
+        if is_url(db_url) {
+            let pair: String = url::form_urlencoded::Serializer::new(String::new())
+                .append_pair(key, &value)
+                .finish();
+            out.push(if out.contains('?') { '&' } else { '?' });
+            out.push_str(&pair);
+        } else {
+            let quoted = value.replace('\\', "\\\\").replace('\'', "\\'");
+            out.push_str(&format!(" {}='{}'", key, quoted));
+        }
+    }
+    out
+}
+
+fn is_url(db_url: &str) -> bool {
+    db_url.starts_with("postgres://") || db_url.starts_with("postgresql://")
+}
+
+/// `(token, key, value)` for each `key=value` of a key/value connection
+/// string, values unquoted. Whitespace around `=` is not supported.
+fn key_values(s: &str) -> Vec<(String, &str, String)> {
+    let mut out = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut value = String::new();
+        let mut key_end = None;
+        let mut quoted = false;
+        let mut end = s.len();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '=' if key_end.is_none() => key_end = Some(i),
+                '\'' if key_end.is_some() => quoted = !quoted,
+                '\\' if quoted => value.extend(chars.next().map(|(_, c)| c)),
+                c if c.is_whitespace() && !quoted => {
+                    end = i;
+                    break;
+                }
+                c if key_end.is_some() => value.push(c),
+                _ => {}
+            }
+        }
+        let key = &s[start..key_end.unwrap_or(end)];
+        out.push((s[start..end].to_string(), key, value));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_without_sslmode_is_unencrypted_and_unchanged() {
+        let tls = TlsSettings::parse("postgres://u@db.example.com/mem?application_name=x").unwrap();
+        assert_eq!(tls.mode, SslMode::Disable);
+        assert_eq!(
+            tls.db_url,
+            "postgres://u@db.example.com/mem?application_name=x"
+        );
+        assert!(tls.root_cert.is_none());
+        assert_eq!(tls.pg_ssl_mode(), PgSslMode::Disable);
+    }
+
+    #[test]
+    fn url_sslmode_is_taken_out_of_the_query() {
+        for (mode, expected, pg) in [
+            ("disable", SslMode::Disable, PgSslMode::Disable),
+            ("prefer", SslMode::Prefer, PgSslMode::Prefer),
+            ("require", SslMode::Require, PgSslMode::Require),
+            ("verify-ca", SslMode::VerifyCa, PgSslMode::Require),
+            ("verify-full", SslMode::VerifyFull, PgSslMode::Require),
+        ] {
+            let url = format!("postgresql://u@h/mem?connect_timeout=5&sslmode={}", mode);
+            let tls = TlsSettings::parse(&url).unwrap();
+            assert_eq!(tls.mode, expected, "{}", mode);
+            assert_eq!(tls.pg_ssl_mode(), pg, "{}", mode);
+            assert_eq!(tls.db_url, "postgresql://u@h/mem?connect_timeout=5");
+        }
+    }
+
+    #[test]
+    fn url_sslrootcert_is_decoded() {
+        let tls = TlsSettings::parse(
+            "postgres://u@h/mem?sslmode=verify-full&sslrootcert=%2Fetc%2Fmy%20ca.pem",
+        )
+        .unwrap();
+        assert_eq!(tls.mode, SslMode::VerifyFull);
+        assert_eq!(tls.root_cert, Some(PathBuf::from("/etc/my ca.pem")));
+        assert_eq!(tls.db_url, "postgres://u@h/mem");
+    }
+
+    #[test]
+    fn key_value_string_keeps_other_options() {
+        let tls = TlsSettings::parse(
+            "host=h port=5433 sslmode=verify-ca sslrootcert='/tmp/a b\\'s.pem' dbname=mem",
+        )
+        .unwrap();
+        assert_eq!(tls.mode, SslMode::VerifyCa);
+        assert_eq!(tls.root_cert, Some(PathBuf::from("/tmp/a b's.pem")));
+        assert_eq!(tls.db_url, "host=h port=5433 dbname=mem");
+    }
+
+    #[test]
+    fn unsupported_sslmode_is_an_error() {
+        let err = TlsSettings::parse("postgres://h/mem?sslmode=allow").unwrap_err();
+        assert!(
+            err.to_string().contains("Unsupported sslmode \"allow\""),
+            "{}",
+            err
+        );
+        assert!(TlsSettings::parse("host=h sslmode=bogus").is_err());
+    }
+
+    #[test]
+    fn config_defaults_fill_in_only_what_is_missing() {
+        let ca = Path::new("/etc/ca.pem");
+        assert_eq!(
+            with_defaults("postgres://h/mem", true, Some(ca)),
+            "postgres://h/mem?sslmode=require&sslrootcert=%2Fetc%2Fca.pem"
+        );
+        assert_eq!(
+            with_defaults("postgres://h/mem?sslmode=verify-full", true, None),
+            "postgres://h/mem?sslmode=verify-full"
+        );
+        assert_eq!(
+            with_defaults("postgres://h/mem", false, None),
+            "postgres://h/mem"
+        );
+
+        let kv = with_defaults("host=h dbname=mem", true, Some(Path::new("/tmp/it's.pem")));
+        let tls = TlsSettings::parse(&kv).unwrap();
+        assert_eq!(tls.mode, SslMode::Require);
+        assert_eq!(tls.root_cert, Some(PathBuf::from("/tmp/it's.pem")));
+        assert_eq!(tls.db_url, "host=h dbname=mem");
+    }
+
+    #[test]
+    fn connector_builds_for_every_mode() {
+        for mode in ["disable", "prefer", "require", "verify-ca", "verify-full"] {
+            let tls = TlsSettings::parse(&format!("host=h sslmode={}", mode)).unwrap();
+            assert!(tls.connector().is_ok(), "{}", mode);
+        }
+        let missing =
+            TlsSettings::parse("host=h sslmode=verify-full sslrootcert=/nonexistent/ca.pem")
+                .unwrap();
+        let err = missing.connector().err().expect("missing root cert");
+        assert!(
+            err.to_string().contains("Cannot read sslrootcert"),
+            "{}",
+            err
+        );
+    }
+}